@@ -0,0 +1,206 @@
+//! Asynchronous I/O core
+//!
+//! Available with `async-daemon` feature. The daemon uses it to multiplex
+//! niri event stream and control clients on a few worker threads instead of
+//! a thread per connection. One-shot commands keep the blocking API of
+//! [crate::niri] and [crate::kitty].
+
+use crate::{
+    control,
+    error::{Error, Result},
+};
+use niri_ipc::{Event, Reply, Request, Response};
+use std::{
+    os::unix::net::UnixListener as StdUnixListener,
+    path::{Path, PathBuf},
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+    net::{
+        UnixListener, UnixStream,
+        unix::{OwnedReadHalf, OwnedWriteHalf},
+    },
+};
+
+/// Asynchronous connection to niri
+pub struct NiriClient {
+    reader: BufReader<OwnedReadHalf>,
+    writer: OwnedWriteHalf,
+}
+
+impl NiriClient {
+    /// Connect niri socket at `path` or at `$NIRI_SOCKET`
+    pub async fn connect(path: Option<&Path>) -> Result<Self> {
+        let path = match path {
+            Some(path) => path.to_owned(),
+            None => std::env::var_os(niri_ipc::socket::SOCKET_PATH_ENV)
+                .map(PathBuf::from)
+                .ok_or_else(|| {
+                    Error::NiriUnreachable(std::io::Error::new(
+                        std::io::ErrorKind::NotFound,
+                        "NIRI_SOCKET is not set",
+                    ))
+                })?,
+        };
+        let stream = UnixStream::connect(&path)
+            .await
+            .map_err(Error::NiriUnreachable)?;
+        let (reader, writer) = stream.into_split();
+        Ok(Self {
+            reader: BufReader::new(reader),
+            writer,
+        })
+    }
+
+    /// Send request and wait for reply
+    pub async fn send(&mut self, request: Request) -> Result<Reply> {
+        let mut line = serde_json::to_string(&request)?;
+        line.push('\n');
+        self.writer.write_all(line.as_bytes()).await?;
+        Ok(serde_json::from_str(&self.read_line().await?)?)
+    }
+
+    /// Send request and unwrap successful response
+    pub async fn request(&mut self, request: Request) -> Result<Response> {
+        Ok(self.send(request).await??)
+    }
+
+    /// Turn connection into stream of niri events
+    pub async fn event_stream(mut self) -> Result<EventStream> {
+        self.request(Request::EventStream).await?;
+        Ok(EventStream { client: self })
+    }
+
+    async fn read_line(&mut self) -> Result<String> {
+        let mut line = String::new();
+        if self.reader.read_line(&mut line).await? == 0 {
+            Err("niri closed connection")?
+        }
+        Ok(line)
+    }
+}
+
+/// Stream of niri events
+pub struct EventStream {
+    client: NiriClient,
+}
+
+impl EventStream {
+    /// Wait for next event, [None] when niri closes the stream
+    pub async fn next(&mut self) -> Result<Option<Event>> {
+        let mut line = String::new();
+        if self.client.reader.read_line(&mut line).await? == 0 {
+            return Ok(None);
+        }
+        Ok(Some(serde_json::from_str(&line)?))
+    }
+}
+
+/// Serve control socket on the runtime until shutdown is requested
+///
+/// Connections are served by tasks, requests are handled on blocking pool.
+/// Once the reply to shutdown request is written, the socket is removed and
+/// accepting stops.
+pub(crate) async fn serve<H>(
+    listener: StdUnixListener,
+    handler: H,
+) -> Result<()>
+where
+    H: Fn(control::Request) -> control::Reply + Send + Sync + 'static,
+{
+    let path = listener.local_addr()?.as_pathname().map(Path::to_path_buf);
+    listener.set_nonblocking(true)?;
+    let listener = UnixListener::from_std(listener)?;
+    let handler = Arc::new(handler);
+    let stop = Arc::new(AtomicBool::new(false));
+    loop {
+        let (stream, _) = listener.accept().await?;
+        if stop.load(Ordering::Relaxed) {
+            break;
+        }
+        let (handler, stop, path) =
+            (handler.clone(), stop.clone(), path.clone());
+        tokio::spawn(async move {
+            // Broken client must not break daemon
+            if let Ok(true) = serve_connection(stream, handler).await {
+                stop.store(true, Ordering::Relaxed);
+                control::wake(path.as_deref());
+            }
+        });
+    }
+    control::unlink(path.as_deref());
+    Ok(())
+}
+
+/// Serve requests of connection
+///
+/// Returns whenever shutdown request was replied.
+async fn serve_connection<H>(
+    stream: UnixStream,
+    handler: Arc<H>,
+) -> Result<bool>
+where
+    H: Fn(control::Request) -> control::Reply + Send + Sync + 'static,
+{
+    let (reader, mut writer) = stream.into_split();
+    let mut reader = BufReader::new(reader);
+    let json = match reader.fill_buf().await?.first() {
+        Some(byte) => *byte == b'{',
+        None => return Ok(false),
+    };
+    if json {
+        let mut lines = reader.lines();
+        while let Some(line) = lines.next_line().await? {
+            let request: control::Request = serde_json::from_str(&line)?;
+            let shutdown = request.shutdown;
+            let reply = dispatch(request, &handler).await?;
+            let mut line = serde_json::to_string(&reply)?;
+            line.push('\n');
+            writer.write_all(line.as_bytes()).await?;
+            if shutdown && reply.ok {
+                return Ok(true);
+            }
+        }
+    } else {
+        loop {
+            let mut len = [0; 4];
+            match reader.read_exact(&mut len).await {
+                Ok(_) => (),
+                Err(err) if err.kind() == std::io::ErrorKind::UnexpectedEof => {
+                    return Ok(false);
+                }
+                Err(err) => Err(err)?,
+            }
+            let mut frame = vec![0; control::frame_len(len)?];
+            reader.read_exact(&mut frame).await?;
+            let request =
+                control::request_from_value(control::decode_frame(&frame)?)?;
+            let shutdown = request.shutdown;
+            let reply = dispatch(request, &handler).await?;
+            let frame =
+                control::encode_frame(&control::reply_to_value(&reply))?;
+            writer.write_all(&frame).await?;
+            if shutdown && reply.ok {
+                return Ok(true);
+            }
+        }
+    }
+    Ok(false)
+}
+
+async fn dispatch<H>(
+    request: control::Request,
+    handler: &Arc<H>,
+) -> Result<control::Reply>
+where
+    H: Fn(control::Request) -> control::Reply + Send + Sync + 'static,
+{
+    let handler = handler.clone();
+    tokio::task::spawn_blocking(move || control::dispatch(request, &*handler))
+        .await
+        .map_err(|err| Error::from(err.to_string()))
+}
@@ -0,0 +1,157 @@
+//! Library API for other Rust tools
+//!
+//! The [Launcher] is built from command line by clap. [LauncherBuilder]
+//! builds it from code instead, and [Launcher::inspect] gives the
+//! [WindowContext] of base window without launching anything.
+//!
+//! ```no_run
+//! use niri_integration::{Command, LauncherBuilder};
+//!
+//! let mut launcher = LauncherBuilder::new().window(42).build(Command::Cwd)?;
+//! let context = launcher.inspect()?;
+//! println!("{:?}", context.cwd);
+//! # Ok::<(), niri_integration::error::Error>(())
+//! ```
+
+use super::{Application, Command, Launcher, LaunchingData, Parser};
+use crate::error::Result;
+use std::{
+    collections::HashMap,
+    ffi::OsString,
+    path::{Path, PathBuf},
+};
+
+/// Builder of [Launcher] without command line parsing
+///
+/// Options which are not set get the same defaults as command line has.
+#[derive(Debug, Default, Clone)]
+pub struct LauncherBuilder {
+    args: Vec<OsString>,
+}
+
+impl LauncherBuilder {
+    /// Create builder with default options
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn arg<S: Into<OsString>>(mut self, name: &str, value: S) -> Self {
+        self.args.push(name.into());
+        self.args.push(value.into());
+        self
+    }
+
+    fn flag(mut self, name: &str, enable: bool) -> Self {
+        if enable {
+            self.args.push(name.into());
+        }
+        self
+    }
+
+    /// Path to niri socket
+    pub fn niri_socket<P: AsRef<Path>>(self, path: P) -> Self {
+        self.arg("--path", path.as_ref())
+    }
+
+    /// Template of kitty socket, see `--kitty-socket`
+    pub fn kitty_socket<S: Into<OsString>>(self, template: S) -> Self {
+        self.arg("--kitty-socket", template)
+    }
+
+    /// Path to configuration file
+    pub fn config<P: AsRef<Path>>(self, path: P) -> Self {
+        self.arg("--config-path", path.as_ref())
+    }
+
+    /// Profile of configuration to apply
+    pub fn profile<S: Into<OsString>>(self, profile: S) -> Self {
+        self.arg("--profile", profile)
+    }
+
+    /// Use niri window with `id` as base window instead of focused one
+    pub fn window(self, id: u64) -> Self {
+        self.arg("--window", id.to_string())
+    }
+
+    /// Drive nvim instance with pid or socket path, see `--instance`
+    pub fn instance<S: Into<OsString>>(self, instance: S) -> Self {
+        self.arg("--instance", instance)
+    }
+
+    /// Do not inherit anything from base window
+    pub fn fresh(self, fresh: bool) -> Self {
+        self.flag("--fresh", fresh)
+    }
+
+    /// Only print mutating requests instead of performing them
+    pub fn dry_run(self, dry_run: bool) -> Self {
+        self.flag("--dry-run", dry_run)
+    }
+
+    /// Build launcher which will run `command`
+    pub fn build(self, command: Command) -> Result<Launcher> {
+        // Any command works here, it is replaced right after parsing
+        let args = std::iter::once(OsString::from("niri-integration"))
+            .chain(self.args)
+            .chain(std::iter::once(OsString::from("test")));
+        let mut launcher = Launcher::try_parse_from(args)
+            .map_err(|err| format!("Invalid launcher options: {err}"))?;
+        launcher.command = command;
+        Ok(launcher)
+    }
+}
+
+/// Launching context of base window
+///
+/// This is what launched programs inherit from base window.
+#[derive(Debug, Clone, Default)]
+pub struct WindowContext {
+    /// Kind of application of base window: `kitty`, `vim` or `other`
+    pub application: &'static str,
+    /// Working directory
+    pub cwd: Option<String>,
+    /// Environment variables allowed by configuration
+    pub env: HashMap<String, String>,
+    /// Destination of ssh session if base window is remote
+    pub ssh: Option<String>,
+    /// Name of container base window runs within
+    pub container: Option<String>,
+    /// Root of git repository of cwd
+    pub git_root: Option<PathBuf>,
+    /// Current branch of git repository
+    pub git_branch: Option<String>,
+}
+
+impl WindowContext {
+    fn new(data: LaunchingData) -> Self {
+        let application = match data.application {
+            Application::Kitty(_) => "kitty",
+            Application::Vim(_) | Application::LegacyVim(_) => "vim",
+            Application::None => "other",
+        };
+        let (git_root, git_branch) = match data.git {
+            Some(repo) => (Some(repo.root), repo.branch),
+            None => (None, None),
+        };
+        Self {
+            application,
+            cwd: data.cwd,
+            env: data.env,
+            ssh: data.ssh,
+            container: data.container.map(|container| container.name),
+            git_root,
+            git_branch,
+        }
+    }
+}
+
+impl Launcher {
+    /// Collect launching context of base window
+    ///
+    /// Loads configuration and connects niri, but does not run the command.
+    pub fn inspect(&mut self) -> Result<WindowContext> {
+        self.load_config()?;
+        let mut socket = self.connect_niri()?;
+        Ok(WindowContext::new(self.get_launching_data(&mut socket)?))
+    }
+}
@@ -0,0 +1,731 @@
+//! User configuration
+//!
+//! The configuration is read from JSON file. By default it is searched at
+//! `${XDG_CONFIG_HOME}/niri-integration/config.json` (or
+//! `~/.config/niri-integration/config.json`). Missing file means default
+//! configuration.
+//!
+//! The `profiles` object of configuration holds named overrides, e.g.
+//! `{"profiles": {"work": {"kitty-socket": "/tmp/kitty-{pid}"}}}`. The chosen
+//! profile is merged recursively into the rest of configuration.
+
+use crate::error::Result;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    fmt,
+    fs::File,
+    io,
+    path::{Component, Path, PathBuf},
+    str::FromStr,
+};
+
+/// Replacement of secret values
+pub const MASK: &str = "********";
+
+/// Top-level configuration structure
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct Config {
+    /// Rule which keeps terminal next to editor of the same project
+    pub pair: PairConfig,
+    /// Rule which hides terminal while GUI program launched from it runs
+    pub swallow: SwallowConfig,
+    /// Launching of terminal from ssh session
+    pub ssh: SshConfig,
+    /// Launching of terminal from distrobox or toolbox container
+    pub container: ContainerConfig,
+    /// Inheritance of environment variables
+    pub env: EnvConfig,
+    /// Launching of programs
+    pub launch: LaunchConfig,
+    /// Synchronisation of vim window width
+    pub vim: VimConfig,
+    /// Picking directory of launches without source window
+    pub picker: PickerConfig,
+    /// Titles of kitty tabs and windows made from cwd and git branch
+    pub title: TitleConfig,
+    /// Whenever to report failures with desktop notification
+    pub notify: bool,
+    /// Sources of launching data by app id, checked before built-in ones
+    pub sources: Vec<SourceRule>,
+    /// Rules placing windows launched by this tool, the first matching wins
+    pub placement: Vec<PlacementRule>,
+    /// Template of kitty socket, `--kitty-socket` takes precedence
+    pub kitty_socket: Option<String>,
+    /// Directories of launches without cwd to inherit by name or index of
+    /// focused workspace, e.g. `{"web": "~/src/web", "2": "~/notes"}`
+    pub workspace_dirs: HashMap<String, String>,
+    /// Named overrides of configuration
+    pub profiles: HashMap<String, serde_json::Value>,
+}
+
+impl Config {
+    /// Default directory of launches on workspace
+    ///
+    /// The name of workspace takes precedence over its index. Leading `~` is
+    /// expanded to the home directory.
+    pub fn workspace_dir(
+        &self,
+        workspace: &niri_ipc::Workspace,
+    ) -> Option<PathBuf> {
+        let dir = workspace
+            .name
+            .as_ref()
+            .and_then(|name| self.workspace_dirs.get(name))
+            .or_else(|| self.workspace_dirs.get(&workspace.idx.to_string()))?;
+        match dir.strip_prefix("~") {
+            Some(rest) => {
+                let home = PathBuf::from(std::env::var_os("HOME")?);
+                Some(home.join(rest.trim_start_matches('/')))
+            }
+            None => Some(PathBuf::from(dir)),
+        }
+    }
+}
+
+/// Rule selecting source of launching data for windows
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct SourceRule {
+    /// Regular expression over app id of windows, e.g.
+    /// `^org\.wezfurlong\.wezterm$`
+    pub app_id: String,
+    /// Regular expression over title of windows, any title if not set
+    #[serde(default)]
+    pub title: Option<String>,
+    /// Name of source: `kitty`, `neovide` or `process`
+    #[serde(default = "SourceRule::default_source")]
+    pub source: String,
+    /// External extractor run after the source, e.g.
+    /// `["my-extractor", "{pid}", "{app_id}"]`
+    ///
+    /// Arguments may contain `{pid}`, `{id}`, `{app_id}` and `{title}` of
+    /// window. The extractor prints JSON object with optional `cwd` string
+    /// and `env` map which are merged into data of the source.
+    #[serde(default)]
+    pub command: Vec<String>,
+}
+
+impl SourceRule {
+    fn default_source() -> String {
+        "process".into()
+    }
+}
+
+/// Rule placing launched window relatively to the base window
+///
+/// E.g. `{"app-id": "^neovide$", "workspace": "dev", "position": "right",
+/// "width": "60%"}` puts neovide launched on workspace `dev` to the column
+/// right of the window it was launched from.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct PlacementRule {
+    /// Regular expression over app id of launched window
+    pub app_id: String,
+    /// Name of source of base window, any source if not set
+    #[serde(default)]
+    pub source: Option<String>,
+    /// Name or index of focused workspace, any workspace if not set
+    #[serde(default)]
+    pub workspace: Option<String>,
+    /// Where to place launched window, where niri opens it if not set
+    #[serde(default)]
+    pub position: Option<Placement>,
+    /// Width of launched window
+    #[serde(default)]
+    pub width: Option<Width>,
+}
+
+/// Position of launched window relatively to the base window
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Placement {
+    /// Column right of the base window's one
+    Right,
+    /// Column left of the base window's one
+    Left,
+    /// Into the base window's column
+    Consume,
+    /// Floating layout
+    Floating,
+}
+
+/// Configuration of interactive directory picker
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct PickerConfig {
+    /// Whenever to pick directory among `zoxide query -l` ones when there is
+    /// no cwd to inherit
+    pub enable: bool,
+    /// Picker which reads choices from stdin and prints the chosen one
+    pub command: Vec<String>,
+}
+
+/// Configuration of vim width synchronisation
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct VimConfig {
+    /// Multiplier of textwidth of each column, overrides calibrated one
+    pub column_width_koeff: Option<f64>,
+    /// Minimal width of vim window
+    pub min_width: Option<Width>,
+    /// Maximal width of vim window
+    pub max_width: Option<Width>,
+    /// Widths cycled by `vim cycle-width`: `fit` or [Width]
+    pub presets: Vec<WidthPreset>,
+    /// Desired widths in symbols by filetype, override `textwidth` of buffers
+    pub filetype_widths: HashMap<String, i64>,
+    /// Duration of view shift animation in milliseconds, 0 to jump at once
+    pub animate: u64,
+    /// Gaps between niri windows and output edges in logical pixels
+    pub gaps: f64,
+    /// Width of niri window border in logical pixels
+    ///
+    /// The focus ring is drawn within gaps and does not need to be accounted.
+    pub border: f64,
+    /// Whenever daemon subscribes to layout changes of neovide windows and
+    /// syncs their width, so the vim plugin is not needed
+    pub watch: bool,
+    /// Whenever daemon shifts view to the current vim split when neovide
+    /// window gets focus, like `vim shift` does
+    pub follow: bool,
+    /// Whenever daemon resizes vim splits when niri changes width of neovide
+    /// window, like `vim fit` does
+    pub fit: bool,
+}
+
+/// Width either in pixels (`1200`) or in percents of output width (`90%`)
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum Width {
+    /// Absolute width in pixels
+    Pixels(i64),
+    /// Part of output width from 0 to 1
+    Fraction(f64),
+}
+
+/// Configuration of programs launching
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct LaunchConfig {
+    /// Whenever to run programs within transient systemd scopes
+    pub scope: bool,
+    /// Slice of transient scopes
+    pub slice: String,
+    /// Whenever existing kitty within the same git repository is focused
+    /// instead of launching new one, not only the one in the same directory
+    pub match_repo: bool,
+    /// Command of terminal, see [LaunchConfig::expand]
+    pub terminal: Vec<String>,
+    /// Command of editor, see [LaunchConfig::expand]
+    ///
+    /// Files to open are appended to the command.
+    pub editor: Vec<String>,
+}
+
+/// Configuration of environment inheritance
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct EnvConfig {
+    /// Whenever to re-establish nix develop and direnv environments instead
+    /// of copying them
+    pub refresh: bool,
+    /// Whenever to re-activate python virtualenv found in environment
+    pub venv: bool,
+    /// Glob patterns of variables to inherit, empty list allows all
+    pub allow: Vec<String>,
+    /// Glob patterns of variables to never inherit
+    pub deny: Vec<String>,
+    /// Glob patterns of variables which values are masked in output
+    pub secrets: Vec<String>,
+}
+
+/// Configuration of container-aware terminal launching
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct ContainerConfig {
+    /// Whenever to enter the container of focused terminal
+    pub enable: bool,
+}
+
+/// Configuration of ssh-aware terminal launching
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct SshConfig {
+    /// Whenever to reconnect to remote host of focused terminal
+    pub enable: bool,
+    /// Command to run in new terminal
+    ///
+    /// Each argument may contain `{destination}` and `{cwd}` placeholders. The
+    /// `{cwd}` is replaced with shell-quoted remote directory or `~` if the
+    /// directory is unknown.
+    pub command: Vec<String>,
+}
+
+/// Configuration of auto-pairing rule of daemon
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct PairConfig {
+    /// Whenever to apply the rule
+    pub enable: bool,
+    /// Where to place terminal relatively to editor
+    pub placement: PairPlacement,
+}
+
+/// Configuration of swallowing rule of daemon
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct SwallowConfig {
+    /// Whenever to apply the rule
+    pub enable: bool,
+    /// Regular expression over app id of windows which swallow terminal
+    pub app_id: String,
+}
+
+impl Default for SwallowConfig {
+    fn default() -> Self {
+        Self {
+            enable: false,
+            app_id: ".*".into(),
+        }
+    }
+}
+
+/// Placement of terminal relatively to its editor
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum PairPlacement {
+    /// Consume terminal into editor's column
+    Consume,
+    /// Place terminal to the column right after editor's one
+    Adjacent,
+}
+
+impl Default for PairConfig {
+    fn default() -> Self {
+        Self {
+            enable: true,
+            placement: PairPlacement::Consume,
+        }
+    }
+}
+
+/// Configuration of automatic kitty titles
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct TitleConfig {
+    /// Whenever daemon retitles kitty whenever its window gets focus
+    pub enable: bool,
+    /// Template of title with `{cwd}` and `{branch}` placeholders
+    ///
+    /// The `{branch}` is git branch of cwd, empty outside of repository.
+    pub format: String,
+    /// Number of trailing components of cwd kept in title
+    pub components: usize,
+    /// Whenever to set titles of kitty windows too, not only of tabs
+    ///
+    /// The title of window is shown by niri, but it replaces the one set by
+    /// programs running in the window.
+    pub window: bool,
+}
+
+impl Default for TitleConfig {
+    fn default() -> Self {
+        Self {
+            enable: false,
+            format: "{cwd} {branch}".into(),
+            components: 2,
+            window: false,
+        }
+    }
+}
+
+impl TitleConfig {
+    /// Expand title template for cwd and git branch
+    ///
+    /// The home directory is shortened to `~`, leading components beyond
+    /// [TitleConfig::components] are replaced with `…`.
+    pub fn expand(&self, cwd: &Path, branch: Option<&str>) -> String {
+        let home = std::env::var_os("HOME").map(PathBuf::from);
+        let (prefix, rest) =
+            match home.and_then(|home| cwd.strip_prefix(home).ok()) {
+                Some(rest) => ("~", rest),
+                None => ("", cwd),
+            };
+        let names: Vec<_> = rest
+            .components()
+            .filter_map(|component| match component {
+                Component::Normal(name) => Some(name.to_string_lossy()),
+                _ => None,
+            })
+            .collect();
+        let skip = names.len().saturating_sub(self.components);
+        let head = if skip > 0 { "…" } else { prefix };
+        let cwd = std::iter::once(head)
+            .chain(names[skip..].iter().map(|name| name.as_ref()))
+            .collect::<Vec<_>>()
+            .join("/");
+        let cwd = if cwd.is_empty() { "/" } else { &cwd };
+        self.format
+            .replace("{cwd}", cwd)
+            .replace("{branch}", branch.unwrap_or_default())
+            .trim()
+            .into()
+    }
+}
+
+/// Preset of vim window width
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum WidthPreset {
+    /// Width which fits content of vim
+    Fit,
+    /// Fixed width
+    Width(Width),
+}
+
+impl Default for VimConfig {
+    fn default() -> Self {
+        Self {
+            column_width_koeff: None,
+            min_width: None,
+            max_width: Some(Width::Fraction(1.0)),
+            presets: vec![
+                WidthPreset::Fit,
+                WidthPreset::Width(Width::Fraction(0.5)),
+                WidthPreset::Width(Width::Fraction(1.0)),
+            ],
+            filetype_widths: HashMap::new(),
+            animate: 0,
+            gaps: 16.0,
+            border: 0.0,
+            watch: true,
+            follow: false,
+            fit: false,
+        }
+    }
+}
+
+impl TryFrom<String> for WidthPreset {
+    type Error = String;
+
+    fn try_from(value: String) -> std::result::Result<Self, Self::Error> {
+        match value.as_str() {
+            "fit" => Ok(WidthPreset::Fit),
+            _ => Ok(WidthPreset::Width(value.parse()?)),
+        }
+    }
+}
+
+impl From<WidthPreset> for String {
+    fn from(value: WidthPreset) -> Self {
+        match value {
+            WidthPreset::Fit => "fit".into(),
+            WidthPreset::Width(width) => width.to_string(),
+        }
+    }
+}
+
+impl Width {
+    /// Get width in pixels for output of given width
+    pub fn resolve(&self, output: i64) -> i64 {
+        match self {
+            Width::Pixels(px) => *px,
+            Width::Fraction(f) => (output as f64 * f).round() as i64,
+        }
+    }
+}
+
+impl FromStr for Width {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let err = || format!("Invalid width '{s}', expected '1200' or '90%'");
+        match s.strip_suffix('%') {
+            Some(percents) => percents
+                .trim()
+                .parse::<f64>()
+                .map(|percents| Width::Fraction(percents / 100.0))
+                .map_err(|_| err()),
+            None => s
+                .strip_suffix("px")
+                .unwrap_or(s)
+                .trim()
+                .parse()
+                .map(Width::Pixels)
+                .map_err(|_| err()),
+        }
+    }
+}
+
+impl fmt::Display for Width {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Width::Pixels(px) => write!(f, "{px}"),
+            Width::Fraction(fraction) => write!(f, "{}%", fraction * 100.0),
+        }
+    }
+}
+
+impl TryFrom<String> for Width {
+    type Error = String;
+
+    fn try_from(value: String) -> std::result::Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<Width> for String {
+    fn from(value: Width) -> Self {
+        value.to_string()
+    }
+}
+
+impl Default for LaunchConfig {
+    fn default() -> Self {
+        Self {
+            scope: false,
+            slice: "app.slice".into(),
+            match_repo: false,
+            terminal: [
+                "kitty",
+                "--override=env={env:NAME=VAL}",
+                "--directory={cwd}",
+            ]
+            .into_iter()
+            .map(String::from)
+            .collect(),
+            editor: vec!["neovide".into()],
+        }
+    }
+}
+
+impl LaunchConfig {
+    /// Expand command template of terminal or editor
+    ///
+    /// Argument with `{cwd}` gets the working directory and is dropped if
+    /// there is no one. Argument with `{env:PATTERN}` is repeated for each
+    /// variable, `NAME` and `VAL` of pattern are replaced with name and value
+    /// of variable. Returns whenever the variables were passed as arguments,
+    /// otherwise they should be set to the process environment.
+    pub fn expand(
+        template: &[String],
+        cwd: Option<&str>,
+        env: &HashMap<String, String>,
+    ) -> (Vec<String>, bool) {
+        let mut args = Vec::new();
+        let mut env_passed = false;
+        let mut vars: Vec<_> = env.iter().collect();
+        vars.sort();
+        for arg in template {
+            let env_pattern = arg.find("{env:").and_then(|start| {
+                let end = start + arg[start..].find('}')?;
+                Some((start, end))
+            });
+            if let Some((start, end)) = env_pattern {
+                env_passed = true;
+                let (prefix, suffix) = (&arg[..start], &arg[end + 1..]);
+                // Placeholder keeps `VAL` within names untouched
+                let pattern = arg[start + 5..end].replace("VAL", "\0");
+                for (name, val) in vars.iter() {
+                    let var = pattern.replace("NAME", name).replace('\0', val);
+                    args.push(format!("{prefix}{var}{suffix}"));
+                }
+            } else if arg.contains("{cwd}") {
+                if let Some(cwd) = cwd {
+                    args.push(arg.replace("{cwd}", cwd));
+                }
+            } else {
+                args.push(arg.clone());
+            }
+        }
+        (args, env_passed)
+    }
+}
+
+impl Default for PickerConfig {
+    fn default() -> Self {
+        Self {
+            enable: false,
+            command: vec!["fuzzel".into(), "--dmenu".into()],
+        }
+    }
+}
+
+impl Default for ContainerConfig {
+    fn default() -> Self {
+        Self { enable: true }
+    }
+}
+
+impl Default for SshConfig {
+    fn default() -> Self {
+        Self {
+            enable: true,
+            command: vec![
+                "ssh".into(),
+                "-t".into(),
+                "{destination}".into(),
+                "cd {cwd} && exec $SHELL".into(),
+            ],
+        }
+    }
+}
+
+impl SshConfig {
+    /// Expand command template for given destination and remote cwd
+    pub fn expand(&self, destination: &str, cwd: Option<&str>) -> Vec<String> {
+        let cwd = cwd
+            .map(|cwd| format!("'{}'", cwd.replace('\'', "'\\''")))
+            .unwrap_or_else(|| "~".into());
+        self.command
+            .iter()
+            .map(|arg| {
+                arg.replace("{destination}", destination)
+                    .replace("{cwd}", &cwd)
+            })
+            .collect()
+    }
+}
+
+impl Default for EnvConfig {
+    fn default() -> Self {
+        Self {
+            refresh: false,
+            venv: false,
+            allow: Vec::new(),
+            deny: Vec::new(),
+            secrets: ["*TOKEN*", "*SECRET*", "*KEY*", "*PASSWORD*"]
+                .into_iter()
+                .map(String::from)
+                .collect(),
+        }
+    }
+}
+
+impl EnvConfig {
+    /// Whenever the variable may be inherited by launched program
+    pub fn is_allowed(&self, name: &str) -> bool {
+        (self.allow.is_empty()
+            || self.allow.iter().any(|pattern| glob_match(pattern, name)))
+            && !self.deny.iter().any(|pattern| glob_match(pattern, name))
+    }
+
+    /// Whenever the value of variable should not be shown
+    pub fn is_secret(&self, name: &str) -> bool {
+        self.secrets.iter().any(|pattern| glob_match(pattern, name))
+    }
+
+    /// Replace values of secret variables found within `value` with [MASK]
+    pub fn redact(&self, value: &mut serde_json::Value) {
+        use serde_json::Value;
+        match value {
+            Value::Object(map) => {
+                for (name, value) in map.iter_mut() {
+                    if value.is_string() && self.is_secret(name) {
+                        *value = MASK.into();
+                    } else {
+                        self.redact(value);
+                    }
+                }
+            }
+            Value::Array(values) => {
+                values.iter_mut().for_each(|value| self.redact(value))
+            }
+            // Variables passed as `NAME=value`, e.g. `env=NAME=value` option
+            // of kitty
+            Value::String(text) => {
+                let assignment = text.strip_prefix("env=").unwrap_or(text);
+                if let Some((name, _)) = assignment.split_once('=') {
+                    if self.is_secret(name) {
+                        let prefix = &text[..text.len() - assignment.len()];
+                        *text = format!("{prefix}{name}={MASK}");
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Match `text` against glob `pattern` supporting `*` and `?` wildcards
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    fn matches(pattern: &[char], text: &[char]) -> bool {
+        match pattern.split_first() {
+            None => text.is_empty(),
+            Some(('*', rest)) => {
+                (0..=text.len()).any(|skip| matches(rest, &text[skip..]))
+            }
+            Some((p, rest)) => match text.split_first() {
+                Some((t, text)) => (*p == '?' || p == t) && matches(rest, text),
+                None => false,
+            },
+        }
+    }
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    matches(&pattern, &text)
+}
+
+impl Config {
+    /// Load configuration
+    ///
+    /// If `path` is specified the file must exist. Otherwise the default
+    /// location is used and missing file results in default configuration.
+    /// The `profile` is merged into configuration if given.
+    pub fn load(path: Option<&PathBuf>, profile: Option<&str>) -> Result<Self> {
+        let (path, required) = match path {
+            Some(path) => (path.clone(), true),
+            None => (Self::default_path(), false),
+        };
+        let mut value: serde_json::Value = match File::open(&path) {
+            Ok(file) => serde_json::from_reader(io::BufReader::new(file))?,
+            Err(err) if !required && err.kind() == io::ErrorKind::NotFound => {
+                serde_json::Value::Object(Default::default())
+            }
+            Err(err) => Err(err)?,
+        };
+        if let Some(profile) = profile {
+            let overrides = value
+                .get("profiles")
+                .and_then(|profiles| profiles.get(profile))
+                .cloned()
+                .ok_or_else(|| format!("Unknown profile '{profile}'"))?;
+            merge(&mut value, overrides);
+        }
+        Ok(serde_json::from_value(value)?)
+    }
+
+    /// Default path of configuration file
+    pub fn default_path() -> PathBuf {
+        std::env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .or_else(|| {
+                std::env::var_os("HOME")
+                    .map(|home| PathBuf::from(home).join(".config"))
+            })
+            .unwrap_or_else(|| PathBuf::from("."))
+            .join("niri-integration")
+            .join("config.json")
+    }
+}
+
+/// Merge `overrides` into `value` recursively
+///
+/// Objects are merged key by key, other values are replaced.
+fn merge(value: &mut serde_json::Value, overrides: serde_json::Value) {
+    match (value, overrides) {
+        (serde_json::Value::Object(value), serde_json::Value::Object(over)) => {
+            for (key, item) in over {
+                match value.get_mut(&key) {
+                    Some(current) => merge(current, item),
+                    None => {
+                        value.insert(key, item);
+                    }
+                }
+            }
+        }
+        (value, overrides) => *value = overrides,
+    }
+}
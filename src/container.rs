@@ -0,0 +1,62 @@
+//! Detection of distrobox and toolbox containers
+//!
+//! Both tools run containers within host pid namespace, so the processes of
+//! containers are visible in host's `/proc` together with their environment.
+
+use super::pstree;
+use std::{fs, path::Path};
+
+/// Kind of container tool
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ContainerKind {
+    Distrobox,
+    Toolbox,
+}
+
+/// Container the process runs within
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Container {
+    pub kind: ContainerKind,
+    pub name: String,
+}
+
+impl Container {
+    /// Detect container of process
+    pub fn detect(pid: i32) -> Option<Self> {
+        let environ = pstree::environ(pid).ok()?;
+        if let Some(name) = environ.get("CONTAINER_ID") {
+            return Some(Self {
+                kind: ContainerKind::Distrobox,
+                name: name.clone(),
+            });
+        }
+        let root = Path::new("/proc").join(pid.to_string()).join("root");
+        if environ.contains_key("TOOLBOX_PATH")
+            || root.join("run/.toolboxenv").exists()
+        {
+            let name = Self::read_name(&root)?;
+            return Some(Self {
+                kind: ContainerKind::Toolbox,
+                name,
+            });
+        }
+        None
+    }
+
+    /// Read container name from `.containerenv` file created by podman
+    fn read_name(root: &Path) -> Option<String> {
+        let env = fs::read_to_string(root.join("run/.containerenv")).ok()?;
+        env.lines().find_map(|line| {
+            Some(line.strip_prefix("name=")?.trim_matches('"').to_string())
+        })
+    }
+
+    /// Command which enters the container
+    pub fn enter_command(&self) -> Vec<String> {
+        let tool = match self.kind {
+            ContainerKind::Distrobox => "distrobox",
+            ContainerKind::Toolbox => "toolbox",
+        };
+        vec![tool.into(), "enter".into(), self.name.clone()]
+    }
+}
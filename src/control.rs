@@ -0,0 +1,377 @@
+//! Control socket of daemon
+//!
+//! Clients send requests with command line arguments of the command to run
+//! within daemon. Two encodings are supported, the encoding is chosen by the
+//! first byte of connection:
+//!
+//! * JSON - one request object per line, e.g. `{"args":["vim","shift"]}`;
+//! * msgpack - each request is msgpack map prefixed with 4-byte big-endian
+//!   length. This is used by the neovim plugin on the hot path.
+//!
+//! Replies have form `{"version":1,"ok":true}` or
+//! `{"version":1,"ok":false,"error":"..."}` in the encoding of request.
+//!
+//! Requests may carry `version` of protocol the client speaks. The daemon
+//! refuses requests of other versions, so clients upgraded together with the
+//! package get clear error instead of subtle breakage. Request with empty
+//! `args` is a ping. Request with `shutdown` stops the daemon, it is accepted
+//! only from client of other protocol version which replaces the daemon.
+//!
+//! The daemon accepts the control socket passed by systemd socket activation,
+//! so it may be started lazily with units like:
+//!
+//! ```ini
+//! # niri-integration.socket
+//! [Socket]
+//! ListenStream=%t/niri-integration.sock
+//!
+//! # niri-integration.service
+//! [Service]
+//! ExecStart=niri-integration daemon
+//! ```
+
+use crate::error::{Error, Result};
+use rmpv::Value;
+use serde::{Deserialize, Serialize};
+use std::{
+    io::{self, BufRead, BufReader, Read, Write},
+    os::unix::{
+        io::{FromRawFd, RawFd},
+        net::{UnixListener, UnixStream},
+    },
+    path::{Path, PathBuf},
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    thread,
+    time::Duration,
+};
+
+/// Maximal size of msgpack frame
+const MAX_FRAME: usize = 1 << 20;
+/// Version of control protocol
+pub const PROTOCOL_VERSION: u32 = 1;
+/// Interval of checks whenever old daemon stops
+const SHUTDOWN_POLL: Duration = Duration::from_millis(20);
+/// Number of checks whenever old daemon stops
+const SHUTDOWN_ATTEMPTS: u32 = 50;
+/// First descriptor passed by systemd socket activation
+const LISTEN_FDS_START: RawFd = 3;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Request {
+    /// Protocol version of client, 0 if unknown
+    #[serde(default)]
+    pub version: u32,
+    /// Command line arguments without program name
+    #[serde(default)]
+    pub args: Vec<String>,
+    /// Stop the daemon after reply
+    #[serde(default)]
+    pub shutdown: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Reply {
+    #[serde(default)]
+    pub version: u32,
+    pub ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl From<Result<()>> for Reply {
+    fn from(value: Result<()>) -> Self {
+        match value {
+            Ok(()) => Self {
+                version: PROTOCOL_VERSION,
+                ok: true,
+                error: None,
+            },
+            Err(err) => Self {
+                version: PROTOCOL_VERSION,
+                ok: false,
+                error: Some(err.to_string()),
+            },
+        }
+    }
+}
+
+/// Default path of control socket
+pub fn default_path() -> PathBuf {
+    std::env::var_os("XDG_RUNTIME_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(std::env::temp_dir)
+        .join("niri-integration.sock")
+}
+
+/// Bind control socket removing stale one
+///
+/// Running daemon of other protocol version is asked to stop.
+pub fn bind(path: &Path) -> Result<UnixListener> {
+    if let Ok(stream) = UnixStream::connect(path) {
+        let ping = call(&stream, &Request::default())?;
+        if ping.version == PROTOCOL_VERSION {
+            Err(format!("Daemon already listens {}", path.display()))?
+        }
+        let shutdown = Request {
+            version: PROTOCOL_VERSION,
+            shutdown: true,
+            ..Default::default()
+        };
+        let reply = call(&stream, &shutdown)?;
+        if !reply.ok {
+            Err(format!(
+                "Daemon refuses to stop: {}",
+                reply.error.unwrap_or_default()
+            ))?
+        }
+        // Old daemon removes its socket once it stops accepting
+        let stopped = (0..SHUTDOWN_ATTEMPTS).any(|_| {
+            thread::sleep(SHUTDOWN_POLL);
+            UnixStream::connect(path).is_err()
+        });
+        if !stopped {
+            Err(format!("Daemon at {} does not stop", path.display()))?
+        }
+    }
+    let _ = std::fs::remove_file(path);
+    Ok(UnixListener::bind(path)?)
+}
+
+/// Listen control socket
+///
+/// The socket passed by systemd socket activation is preferred, otherwise
+/// `path` is bound.
+pub fn listen(path: &Path) -> Result<UnixListener> {
+    match activated()? {
+        Some(listener) => {
+            log::info!("Using control socket passed by systemd");
+            Ok(listener)
+        }
+        None => bind(path),
+    }
+}
+
+/// Take listening socket passed by systemd
+///
+/// Follows `sd_listen_fds(3)`: the first passed descriptor is taken when
+/// `LISTEN_PID` is pid of this process. The variables are left in place,
+/// spawned programs do not consider themselves activated as their pid
+/// differs.
+fn activated() -> Result<Option<UnixListener>> {
+    let var = |name| std::env::var(name).ok().and_then(|v| v.parse().ok());
+    let pid: Option<u32> = var("LISTEN_PID");
+    let fds: Option<i32> = var("LISTEN_FDS");
+    if pid != Some(std::process::id()) || fds.unwrap_or(0) < 1 {
+        return Ok(None);
+    }
+    // SAFETY: systemd passes ownership of the descriptor to this process
+    let inherited = unsafe { UnixListener::from_raw_fd(LISTEN_FDS_START) };
+    // Duplicate is close-on-exec, so spawned programs do not inherit socket
+    let listener = inherited.try_clone()?;
+    drop(inherited);
+    Ok(Some(listener))
+}
+
+/// Send JSON request over connection and wait for reply
+fn call(stream: &UnixStream, request: &Request) -> Result<Reply> {
+    let mut line = serde_json::to_string(request)?;
+    line.push('\n');
+    (&*stream).write_all(line.as_bytes())?;
+    let mut line = String::new();
+    BufReader::new(stream).read_line(&mut line)?;
+    Ok(serde_json::from_str(&line)?)
+}
+
+/// Check request against protocol and run it
+pub(crate) fn dispatch<H>(request: Request, handler: &H) -> Reply
+where
+    H: Fn(Request) -> Reply,
+{
+    if request.shutdown {
+        // Only the client replacing daemon may stop it
+        if request.version == 0 || request.version == PROTOCOL_VERSION {
+            Reply::from(Err(
+                "Daemon stops for other protocol version only".into()
+            ))
+        } else {
+            Reply::from(Ok(()))
+        }
+    } else if request.version != 0 && request.version != PROTOCOL_VERSION {
+        Reply::from(Err(format!(
+            "Control protocol mismatch: daemon speaks {PROTOCOL_VERSION}, \
+             client speaks {}. Restart the daemon",
+            request.version
+        )
+        .into()))
+    } else if request.args.is_empty() {
+        Reply::from(Ok(()))
+    } else {
+        handler(request)
+    }
+}
+
+/// Serve connections of control socket until shutdown is requested
+///
+/// Each connection is served in its own thread. Once the reply to shutdown
+/// request is written, the socket is removed and accepting stops.
+pub fn serve<H>(listener: UnixListener, handler: H) -> Result<()>
+where
+    H: Fn(Request) -> Reply + Send + Sync + 'static,
+{
+    let path = listener.local_addr()?.as_pathname().map(Path::to_path_buf);
+    let handler = Arc::new(handler);
+    let stop = Arc::new(AtomicBool::new(false));
+    for stream in listener.incoming() {
+        let stream = stream?;
+        if stop.load(Ordering::Relaxed) {
+            break;
+        }
+        let (handler, stop, path) =
+            (handler.clone(), stop.clone(), path.clone());
+        thread::spawn(move || {
+            // Broken client must not break daemon
+            if let Ok(true) = serve_connection(stream, &*handler) {
+                stop.store(true, Ordering::Relaxed);
+                wake(path.as_deref());
+            }
+        });
+    }
+    unlink(path.as_deref());
+    Ok(())
+}
+
+/// Wake accept loop of socket, so it notices the stop
+pub(crate) fn wake(path: Option<&Path>) {
+    if let Some(path) = path {
+        let _ = UnixStream::connect(path);
+    }
+}
+
+/// Remove socket of stopped daemon
+///
+/// Clients replacing daemon wait for this before binding own socket.
+pub(crate) fn unlink(path: Option<&Path>) {
+    if let Some(path) = path {
+        let _ = std::fs::remove_file(path);
+    }
+}
+
+/// Serve requests of connection
+///
+/// Returns whenever shutdown request was replied.
+fn serve_connection<H>(stream: UnixStream, handler: &H) -> Result<bool>
+where
+    H: Fn(Request) -> Reply,
+{
+    let mut writer = stream.try_clone()?;
+    let mut reader = BufReader::new(stream);
+    let json = match reader.fill_buf()?.first() {
+        Some(byte) => *byte == b'{',
+        None => return Ok(false),
+    };
+    if json {
+        for line in reader.lines() {
+            let request: Request = serde_json::from_str(&line?)?;
+            let shutdown = request.shutdown;
+            let reply = dispatch(request, handler);
+            writer.write_all(serde_json::to_string(&reply)?.as_bytes())?;
+            writer.write_all(b"\n")?;
+            if shutdown && reply.ok {
+                return Ok(true);
+            }
+        }
+    } else {
+        while let Some(request) = read_frame(&mut reader)? {
+            let request = request_from_value(request)?;
+            let shutdown = request.shutdown;
+            let reply = dispatch(request, handler);
+            write_frame(&mut writer, &reply_to_value(&reply))?;
+            if shutdown && reply.ok {
+                return Ok(true);
+            }
+        }
+    }
+    Ok(false)
+}
+
+fn read_frame<R: Read>(reader: &mut R) -> Result<Option<Value>> {
+    let mut len = [0; 4];
+    match reader.read_exact(&mut len) {
+        Ok(()) => (),
+        Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => {
+            return Ok(None);
+        }
+        Err(err) => Err(err)?,
+    }
+    let mut frame = vec![0; frame_len(len)?];
+    reader.read_exact(&mut frame)?;
+    Ok(Some(decode_frame(&frame)?))
+}
+
+fn write_frame<W: Write>(writer: &mut W, value: &Value) -> Result<()> {
+    writer.write_all(&encode_frame(value)?)?;
+    Ok(())
+}
+
+/// Length of msgpack frame from its prefix
+pub(crate) fn frame_len(prefix: [u8; 4]) -> Result<usize> {
+    let len = u32::from_be_bytes(prefix) as usize;
+    if len > MAX_FRAME {
+        Err(format!("Too large control frame: {len}"))?
+    }
+    Ok(len)
+}
+
+/// Decode msgpack frame without length prefix
+pub(crate) fn decode_frame(frame: &[u8]) -> Result<Value> {
+    let value =
+        rmpv::decode::read_value(&mut &frame[..]).map_err(|e| e.to_string())?;
+    Ok(value)
+}
+
+/// Encode msgpack frame with length prefix
+pub(crate) fn encode_frame(value: &Value) -> Result<Vec<u8>> {
+    let mut frame = vec![0; 4];
+    rmpv::encode::write_value(&mut frame, value).map_err(|e| e.to_string())?;
+    let len = (frame.len() - 4) as u32;
+    frame[..4].copy_from_slice(&len.to_be_bytes());
+    Ok(frame)
+}
+
+pub(crate) fn request_from_value(value: Value) -> Result<Request> {
+    let err = || Error::from("Invalid msgpack control request");
+    let map = value.as_map().ok_or_else(err)?;
+    let field = |name| {
+        map.iter()
+            .find(|(k, _)| k.as_str() == Some(name))
+            .map(|(_, v)| v)
+    };
+    let args = match field("args") {
+        Some(args) => args
+            .as_array()
+            .ok_or_else(err)?
+            .iter()
+            .map(|arg| arg.as_str().map(String::from).ok_or_else(err))
+            .collect::<Result<_>>()?,
+        None => Vec::new(),
+    };
+    Ok(Request {
+        version: field("version").and_then(Value::as_u64).unwrap_or(0) as u32,
+        args,
+        shutdown: field("shutdown").and_then(Value::as_bool).unwrap_or(false),
+    })
+}
+
+pub(crate) fn reply_to_value(reply: &Reply) -> Value {
+    let mut map: Vec<(Value, Value)> = vec![
+        ("version".into(), reply.version.into()),
+        ("ok".into(), reply.ok.into()),
+    ];
+    if let Some(error) = &reply.error {
+        map.push(("error".into(), error.as_str().into()));
+    }
+    Value::Map(map)
+}
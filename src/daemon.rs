@@ -0,0 +1,551 @@
+//! Long-running daemon which listens niri event stream and applies automatic
+//! rules to windows.
+
+#[cfg(feature = "async-daemon")]
+use super::aio;
+use super::{
+    Launcher, config::PairPlacement, control, niri, operation::Operation,
+    pstree, source, state::State, vim,
+};
+use crate::{error::Result, niri::Socket};
+use niri_ipc::{Action, Event, Request, Window, WorkspaceReferenceArg};
+use std::{
+    collections::HashMap, path::Path, sync::Arc, thread, time::Duration,
+};
+
+/// Time given to automatic rule to apply
+const RULE_TIMEOUT: Duration = Duration::from_secs(2);
+/// Maximum number of windows remembered in focus history
+const FOCUS_HISTORY_SIZE: usize = 64;
+/// Number of attempts to subscribe to nvim of neovide window
+const WATCH_ATTEMPTS: u32 = 10;
+/// Interval between attempts, nvim may start listening after window is shown
+const WATCH_INTERVAL: Duration = Duration::from_millis(500);
+
+pub struct Daemon {
+    /// Launcher of daemon, shared with threads running remote commands
+    launcher: Arc<Launcher>,
+    windows: HashMap<u64, Window>,
+    focused: Option<u64>,
+    previous: Option<u64>,
+    /// Terminals hidden by swallowing rule by id of swallowing window
+    swallowed: HashMap<u64, Swallowed>,
+}
+
+/// Terminal hidden while GUI program launched from it runs
+struct Swallowed {
+    /// Id of terminal window
+    terminal: u64,
+    /// Id of workspace terminal was taken from
+    workspace: u64,
+}
+
+impl Daemon {
+    pub fn new(launcher: &Launcher) -> Self {
+        Self {
+            launcher: Arc::new(launcher.clone()),
+            windows: HashMap::new(),
+            focused: None,
+            previous: None,
+            swallowed: HashMap::new(),
+        }
+    }
+
+    /// Run event loop until niri closes the event stream
+    ///
+    /// The control socket is served in background meanwhile.
+    #[cfg(not(feature = "async-daemon"))]
+    pub fn run(&mut self, soc: &mut Socket) -> Result<()> {
+        let listener = control::listen(&control::default_path())?;
+        let mut events = self.launcher.connect_niri()?;
+        events.send(Request::EventStream)??;
+        let mut read_event = events.read_events();
+        // Detached, so closed event stream stops the daemon while serving
+        // thread still waits for clients
+        let launcher = self.launcher.clone();
+        thread::spawn(move || {
+            Self::stopped(control::serve(listener, move |request| {
+                launcher.handle_control(request)
+            }))
+        });
+        loop {
+            let event = read_event()?;
+            self.handle_event(event, soc);
+        }
+    }
+
+    /// Run event loop until niri closes the event stream
+    ///
+    /// Control clients are served by tasks of async runtime meanwhile.
+    /// Events are handled outside of the runtime, so rules may keep using
+    /// blocking niri and nvim requests.
+    #[cfg(feature = "async-daemon")]
+    pub fn run(&mut self, soc: &mut Socket) -> Result<()> {
+        let listener = control::listen(&control::default_path())?;
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .enable_io()
+            .build()?;
+        runtime.block_on(async {
+            let path = self.launcher.path.as_deref();
+            let client = aio::NiriClient::connect(path).await?;
+            let mut events = client.event_stream().await?;
+            let launcher = self.launcher.clone();
+            runtime.spawn(async move {
+                let handler = move |request| launcher.handle_control(request);
+                Self::stopped(aio::serve(listener, handler).await)
+            });
+            while let Some(event) = events.next().await? {
+                tokio::task::block_in_place(|| self.handle_event(event, soc));
+            }
+            Ok(())
+        })
+    }
+
+    /// Stop daemon once control socket is not served anymore
+    ///
+    /// Serving ends when daemon of other version replaces this one. The event
+    /// loop waits for niri events meanwhile, so the process exits from here.
+    fn stopped(served: Result<()>) {
+        match served {
+            Ok(()) => {
+                log::info!("Daemon is replaced, stopping");
+                std::process::exit(0)
+            }
+            Err(err) => log::error!("Control socket fails: {err}"),
+        }
+    }
+
+    fn handle_event(&mut self, event: Event, soc: &mut Socket) {
+        match event {
+            Event::WindowsChanged { windows } => {
+                for window in windows.iter() {
+                    if !self.windows.contains_key(&window.id) {
+                        self.watch_vim(window);
+                    }
+                }
+                self.windows =
+                    windows.into_iter().map(|win| (win.id, win)).collect();
+            }
+            Event::WindowOpenedOrChanged { window } => {
+                let opened = !self.windows.contains_key(&window.id);
+                self.windows.insert(window.id, window.clone());
+                if opened {
+                    self.on_window_opened(window, soc);
+                }
+            }
+            Event::WindowClosed { id } => {
+                self.windows.remove(&id);
+                self.swallowed
+                    .retain(|_, swallowed| swallowed.terminal != id);
+                if let Some(swallowed) = self.swallowed.remove(&id) {
+                    let _ = Self::unswallow(swallowed, soc);
+                }
+                let _ = State::update(|state| {
+                    state.focus_history.retain(|win| *win != id);
+                    state.marks.retain(|_, win| *win != id);
+                    Ok(())
+                });
+            }
+            Event::WindowFocusChanged { id } => {
+                if id != self.focused {
+                    self.previous = self.focused;
+                    self.focused = id;
+                    if let Some(id) = id {
+                        self.follow_vim(id);
+                        self.retitle_kitty(id);
+                        let _ = State::update(|state| {
+                            let history = &mut state.focus_history;
+                            history.retain(|win| *win != id);
+                            history.insert(0, id);
+                            history.truncate(FOCUS_HISTORY_SIZE);
+                            Ok(())
+                        });
+                    }
+                }
+            }
+            Event::WindowLayoutsChanged { changes } => {
+                for (id, layout) in changes {
+                    let Some(window) = self.windows.get_mut(&id) else {
+                        continue;
+                    };
+                    let width = window.layout.window_size.0;
+                    window.layout = layout;
+                    if window.layout.window_size.0 != width {
+                        self.fit_vim(id);
+                    }
+                }
+            }
+            Event::WorkspaceActivated { id, focused: true } => {
+                // Focused window stays the same when view returns to its
+                // workspace, but the view may be scrolled away meanwhile
+                let focused = self
+                    .focused
+                    .and_then(|win| self.windows.get(&win))
+                    .filter(|win| win.workspace_id == Some(id));
+                if let Some(window) = focused {
+                    self.follow_vim(window.id);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn on_window_opened(&mut self, window: Window, soc: &mut Socket) {
+        if self.launcher.config.pair.enable
+            && source::is(&self.launcher.config.sources, &window, "kitty")
+        {
+            // Rules must not break the daemon
+            let _ = self.pair_terminal(&window, soc);
+        }
+        if self.launcher.config.swallow.enable {
+            if let Err(err) = self.swallow_terminal(&window, soc) {
+                log::debug!("Window {} swallows nothing: {err}", window.id);
+            }
+        }
+        self.watch_vim(&window);
+    }
+
+    /// Sync width of neovide window whenever nvim changes its layout
+    ///
+    /// Each watched nvim is waited by own thread until it exits.
+    fn watch_vim(&self, window: &Window) {
+        if !self.launcher.config.vim.watch
+            || !source::is(&self.launcher.config.sources, window, "neovide")
+        {
+            return;
+        }
+        let window = window.clone();
+        let launcher = self.launcher.clone();
+        thread::spawn(move || {
+            let watch = (0..WATCH_ATTEMPTS).find_map(|_| {
+                let watch = vim::LayoutWatch::new(&window);
+                if watch.is_err() {
+                    thread::sleep(WATCH_INTERVAL);
+                }
+                watch.ok()
+            });
+            let Some(mut watch) = watch else {
+                log::warn!("Can not watch nvim of window {}", window.id);
+                return;
+            };
+            let request = control::Request {
+                args: ["--window", &window.id.to_string(), "vim", "sync"]
+                    .map(String::from)
+                    .into(),
+                ..Default::default()
+            };
+            while watch.wait() {
+                let reply = launcher.handle_control(request.clone());
+                if let Some(error) = reply.error {
+                    log::warn!("Can not sync window {}: {error}", window.id);
+                }
+            }
+        });
+    }
+
+    /// Shift view to the current split of vim in focused neovide window
+    ///
+    /// The shift may be animated, so it does not hold the event loop.
+    fn follow_vim(&self, id: u64) {
+        let Some(window) = self.windows.get(&id) else {
+            return;
+        };
+        if !self.launcher.config.vim.follow
+            || !source::is(&self.launcher.config.sources, window, "neovide")
+        {
+            return;
+        }
+        let request = control::Request {
+            args: ["--window", &id.to_string(), "vim", "shift"]
+                .map(String::from)
+                .into(),
+            ..Default::default()
+        };
+        let launcher = self.launcher.clone();
+        thread::spawn(move || {
+            let reply = launcher.handle_control(request);
+            if let Some(error) = reply.error {
+                log::warn!("Can not shift view to vim of window {id}: {error}");
+            }
+        });
+    }
+
+    /// Resize vim splits to the width of neovide window changed by niri
+    fn fit_vim(&self, id: u64) {
+        let Some(window) = self.windows.get(&id) else {
+            return;
+        };
+        if !self.launcher.config.vim.fit
+            || !source::is(&self.launcher.config.sources, window, "neovide")
+        {
+            return;
+        }
+        let request = control::Request {
+            args: ["--window", &id.to_string(), "vim", "fit"]
+                .map(String::from)
+                .into(),
+            ..Default::default()
+        };
+        let launcher = self.launcher.clone();
+        thread::spawn(move || {
+            let reply = launcher.handle_control(request);
+            if let Some(error) = reply.error {
+                log::warn!("Can not fit vim to window {id}: {error}");
+            }
+        });
+    }
+
+    /// Set titles of tabs of focused kitty from their cwd and git branch
+    fn retitle_kitty(&self, id: u64) {
+        let Some(window) = self.windows.get(&id) else {
+            return;
+        };
+        if !self.launcher.config.title.enable
+            || !source::is(&self.launcher.config.sources, window, "kitty")
+        {
+            return;
+        }
+        let request = control::Request {
+            args: ["--window", &id.to_string(), "retitle"]
+                .map(String::from)
+                .into(),
+            ..Default::default()
+        };
+        let launcher = self.launcher.clone();
+        thread::spawn(move || {
+            let reply = launcher.handle_control(request);
+            if let Some(error) = reply.error {
+                log::warn!("Can not retitle kitty of window {id}: {error}");
+            }
+        });
+    }
+
+    /// Auto-pairing rule: keep terminal next to the editor of its project
+    fn pair_terminal(&self, term: &Window, soc: &mut Socket) -> Result<()> {
+        Operation::new("pair terminal", RULE_TIMEOUT).run(soc, |op, soc| {
+            // Kitty may not start listening socket yet
+            let data = op.wait_for(|| {
+                Ok(self.launcher.get_launching_data_from_kitty(term).ok())
+            })?;
+            let cwd = data.cwd.ok_or("Terminal does not have cwd")?;
+            let editor = self.windows.values().find(|win| {
+                win.id != term.id
+                    && source::is(&self.launcher.config.sources, win, "neovide")
+                    && win.workspace_id.is_some()
+                    && win.workspace_id == term.workspace_id
+                    && self
+                        .launcher
+                        .get_launching_data_from_vim((*win).clone())
+                        .ok()
+                        .and_then(|data| data.cwd)
+                        .map(|project| Path::new(&cwd).starts_with(project))
+                        .unwrap_or(false)
+            });
+            let editor = match editor {
+                Some(editor) => editor.id,
+                None => return Ok(()),
+            };
+
+            // Whatever happens - the new terminal should stay focused
+            let id = term.id;
+            op.on_rollback(move |soc| {
+                niri::action(soc, Action::FocusWindow { id })
+            });
+
+            // Niri opens new window right after the focused column, so
+            // terminal launched from editor is already adjacent. Focus moves
+            // to the terminal only after it is opened.
+            if self.focused != Some(editor) {
+                self.move_next_to(term.id, editor, soc)?;
+            }
+            op.check()?;
+            if self.launcher.config.pair.placement == PairPlacement::Consume {
+                niri::action(
+                    soc,
+                    Action::ConsumeOrExpelWindowLeft { id: Some(term.id) },
+                )?;
+            }
+            niri::action(soc, Action::FocusWindow { id: term.id })
+        })
+    }
+
+    /// Swallowing rule: hide terminal the GUI window was launched from
+    ///
+    /// The terminal is the kitty window whose process is an ancestor of the
+    /// window's one. It is moved to the empty workspace at the end of its
+    /// output until the window closes.
+    fn swallow_terminal(
+        &mut self,
+        window: &Window,
+        soc: &mut Socket,
+    ) -> Result<()> {
+        let sources = &self.launcher.config.sources;
+        let app_id = window.app_id.as_deref().unwrap_or_default();
+        if source::is(sources, window, "kitty")
+            || !regex::Regex::new(&self.launcher.config.swallow.app_id)?
+                .is_match(app_id)
+        {
+            return Ok(());
+        }
+        let pid = window.pid.ok_or("Window does not have pid")?;
+        let ancestors = pstree::ancestors(pid)?;
+        // Single kitty instance may serve several windows, the one focused
+        // before the program opened its window most likely launched it
+        let mut terminals: Vec<&Window> = self
+            .windows
+            .values()
+            .filter(|win| win.id != window.id && win.pid != Some(pid))
+            .filter(|win| win.pid.is_some_and(|pid| ancestors.contains(&pid)))
+            .filter(|win| source::is(sources, win, "kitty"))
+            .collect();
+        let recent = [self.focused, self.previous];
+        terminals.sort_by_key(|win| !recent.contains(&Some(win.id)));
+        let terminal = terminals.first().ok_or("No terminal to swallow")?;
+        let workspace = terminal.workspace_id.ok_or("Terminal is hidden")?;
+        let workspaces = niri::workspaces(soc)?;
+        let output = workspaces
+            .iter()
+            .find(|ws| ws.id == workspace)
+            .and_then(|ws| ws.output.clone());
+        let hidden = workspaces
+            .iter()
+            .filter(|ws| ws.output == output)
+            .max_by_key(|ws| ws.idx)
+            .filter(|ws| ws.id != workspace)
+            .ok_or("No workspace to hide terminal on")?;
+        log::debug!("Window {} swallows terminal {}", window.id, terminal.id);
+        niri::action(
+            soc,
+            Action::MoveWindowToWorkspace {
+                window_id: Some(terminal.id),
+                reference: WorkspaceReferenceArg::Id(hidden.id),
+                focus: false,
+            },
+        )?;
+        let swallowed = Swallowed {
+            terminal: terminal.id,
+            workspace,
+        };
+        self.swallowed.insert(window.id, swallowed);
+        Ok(())
+    }
+
+    /// Bring swallowed terminal back to its workspace and focus it
+    fn unswallow(swallowed: Swallowed, soc: &mut Socket) -> Result<()> {
+        let id = swallowed.terminal;
+        niri::action(
+            soc,
+            Action::MoveWindowToWorkspace {
+                window_id: Some(id),
+                reference: WorkspaceReferenceArg::Id(swallowed.workspace),
+                focus: true,
+            },
+        )?;
+        niri::action(soc, Action::FocusWindow { id })
+    }
+
+    /// Move column of `window` to be right after the column of `target`
+    fn move_next_to(
+        &self,
+        window: u64,
+        target: u64,
+        soc: &mut Socket,
+    ) -> Result<()> {
+        let offset = match self.column_offset(target, window, soc)? {
+            Some(offset) => offset,
+            None => return Ok(()),
+        };
+        niri::action(soc, Action::FocusWindow { id: window })?;
+        let (moves, action) = if offset > 0 {
+            (offset - 1, Action::MoveColumnLeft {})
+        } else {
+            (-offset, Action::MoveColumnRight {})
+        };
+        for _ in 0..moves {
+            niri::action(soc, action.clone())?;
+        }
+        Ok(())
+    }
+
+    /// Find how many columns `to` is placed right of `from`
+    ///
+    /// Columns are taken from scrolling layout positions of windows. Returns
+    /// negative value when `to` is placed left of `from` and [None] if
+    /// windows are on different workspaces or any of them is floating.
+    fn column_offset(
+        &self,
+        from: u64,
+        to: u64,
+        soc: &mut Socket,
+    ) -> Result<Option<i64>> {
+        let windows = niri::windows(soc)?;
+        let column = |id| {
+            let win = windows.iter().find(|win| win.id == id)?;
+            let (column, _) = win.layout.pos_in_scrolling_layout?;
+            Some((win.workspace_id?, column as i64))
+        };
+        Ok(match (column(from), column(to)) {
+            (Some((from_ws, from)), Some((to_ws, to))) if from_ws == to_ws => {
+                Some(to - from)
+            }
+            _ => None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::niri::{MockNiri, test_window};
+    use clap::Parser;
+    use serde_json::json;
+
+    fn window(id: u64, workspace: u64, column: usize) -> Window {
+        test_window(json!({
+            "id": id,
+            "workspace_id": workspace,
+            "layout": {"pos_in_scrolling_layout": [column, 1]},
+        }))
+    }
+
+    #[test]
+    fn column_offset() {
+        let launcher =
+            Launcher::try_parse_from(["niri-integration", "daemon"]).unwrap();
+        let daemon = Daemon::new(&launcher);
+        // The last window is on other workspace, the one before is floating
+        let mut niri = MockNiri {
+            windows: vec![
+                window(1, 1, 1),
+                window(2, 1, 2),
+                window(3, 1, 3),
+                window(4, 2, 1),
+                test_window(json!({"id": 5})),
+            ],
+            ..Default::default()
+        };
+        assert_eq!(daemon.column_offset(1, 3, &mut niri).unwrap(), Some(2));
+        assert_eq!(daemon.column_offset(3, 2, &mut niri).unwrap(), Some(-1));
+        assert_eq!(daemon.column_offset(1, 4, &mut niri).unwrap(), None);
+        assert_eq!(daemon.column_offset(1, 5, &mut niri).unwrap(), None);
+        // Focus is not moved to find columns
+        assert!(niri.actions.is_empty());
+    }
+
+    #[test]
+    fn move_next_to() {
+        let launcher =
+            Launcher::try_parse_from(["niri-integration", "daemon"]).unwrap();
+        let daemon = Daemon::new(&launcher);
+        let mut niri = MockNiri {
+            windows: vec![window(1, 1, 1), window(2, 1, 2), window(3, 1, 3)],
+            ..Default::default()
+        };
+        daemon.move_next_to(3, 1, &mut niri).unwrap();
+        // Only the moved column is focused
+        assert!(matches!(
+            niri.actions.as_slice(),
+            [Action::FocusWindow { id: 3 }, Action::MoveColumnLeft {}]
+        ));
+    }
+}
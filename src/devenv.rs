@@ -0,0 +1,33 @@
+//! Re-establishing of nix develop and direnv environments
+//!
+//! Variables of such environments copied from another process may be stale,
+//! so instead the launched program is wrapped with the tool which loads the
+//! environment again.
+
+use std::{collections::HashMap, path::Path};
+
+/// Get the command prefix which re-establishes environment for `cwd`
+pub fn wrapper(
+    env: &HashMap<String, String>,
+    cwd: Option<&str>,
+) -> Option<Vec<String>> {
+    let cwd = cwd?;
+    if env.contains_key("DIRENV_DIR") {
+        Some(vec!["direnv".into(), "exec".into(), cwd.into()])
+    } else if env.contains_key("IN_NIX_SHELL") {
+        let flake = find_flake(Path::new(cwd))?;
+        Some(vec![
+            "nix".into(),
+            "develop".into(),
+            flake.to_str()?.into(),
+            "--command".into(),
+        ])
+    } else {
+        None
+    }
+}
+
+/// Find the closest directory with `flake.nix`
+fn find_flake(cwd: &Path) -> Option<&Path> {
+    cwd.ancestors().find(|dir| dir.join("flake.nix").is_file())
+}
@@ -0,0 +1,68 @@
+//! Dry-run mode
+//!
+//! Read-only queries are performed as usual, while mutating requests to niri,
+//! kitty and vim as well as launches of programs and changes of state are
+//! printed instead. Values of secret variables are masked according to
+//! `env.secrets` of configuration.
+
+use crate::config::EnvConfig;
+use serde::Serialize;
+use std::{fmt, process, sync::Mutex};
+
+/// Configuration of secrets, set while dry-run mode is on
+static ENV: Mutex<Option<EnvConfig>> = Mutex::new(None);
+
+/// Turn dry-run mode on for the rest of process
+pub fn enable(env: EnvConfig) {
+    if let Ok(mut enabled) = ENV.lock() {
+        *enabled = Some(env);
+    }
+}
+
+/// Whenever dry-run mode is on
+pub fn enabled() -> bool {
+    ENV.lock().is_ok_and(|env| env.is_some())
+}
+
+/// Whenever the request should be skipped, it is printed then
+pub fn skip(request: fmt::Arguments) -> bool {
+    let enabled = enabled();
+    if enabled {
+        println!("dry-run: {request}");
+    }
+    enabled
+}
+
+/// Whenever the request should be skipped, it is printed with secrets masked
+pub fn skip_masked<T: Serialize>(what: &str, request: &T) -> bool {
+    let Ok(env) = ENV.lock() else {
+        return false;
+    };
+    let Some(env) = env.as_ref() else {
+        return false;
+    };
+    let mut request = serde_json::to_value(request).unwrap_or_default();
+    env.redact(&mut request);
+    println!("dry-run: {what} {request}");
+    true
+}
+
+/// Whenever the launch of program should be skipped, it is printed then
+pub fn skip_launch(proc: &process::Command) -> bool {
+    if !enabled() {
+        return false;
+    }
+    // Removed variables are shown as null
+    let env: serde_json::Map<_, _> = proc
+        .get_envs()
+        .map(|(name, value)| {
+            let value = value.map(|value| value.to_string_lossy().into_owned());
+            (name.to_string_lossy().into_owned(), value.into())
+        })
+        .collect();
+    let args: Vec<_> = std::iter::once(proc.get_program())
+        .chain(proc.get_args())
+        .map(|arg| arg.to_string_lossy())
+        .collect();
+    skip_masked("launch", &serde_json::json!({ "env": env, "args": args }))
+}
@@ -1,8 +1,8 @@
 //! Collection of own error-related types
 
-use neovim_lib;
-use std::{fmt, io};
+use std::{fmt, io, path::PathBuf};
 use daemonize;
+use serde::Serialize;
 use serde_json;
 use regex;
 
@@ -13,8 +13,8 @@ use regex;
 pub enum Error {
     /// An [io::Error] variant
     Io(io::Error),
-    /// A [neovim_lib::CallError] variant
-    Neovim(neovim_lib::CallError),
+    /// A [nvim_rs::error::CallError] variant
+    Neovim(Box<nvim_rs::error::CallError>),
     /// A [niri_ipc::Reply] variant
     Str(String),
     /// A [daemonize::Error] variant
@@ -22,8 +22,23 @@ pub enum Error {
     /// A [serde_json::Error]
     Json(serde_json::Error),
     /// A [regex::Error]
-    Regex(regex::Error)
-
+    Regex(regex::Error),
+    /// Failure to launch external program
+    Exec(ExecError),
+    /// There is no niri window with requested id
+    WindowNotFound(u64),
+    /// There is no niri window matching selector
+    NoMatchingWindow(String),
+    /// Failed to connect to niri socket
+    NiriUnreachable(io::Error),
+    /// There is no focused niri window
+    NoFocusedWindow,
+    /// Focused application does not support requested operation
+    Unsupported(&'static str),
+    /// Failed to communicate with kitty over its socket
+    Kitty(io::Error),
+    /// An error annotated with context of failed operation
+    Context(Context, Box<Error>),
 }
 
 impl fmt::Display for Error {
@@ -35,6 +50,22 @@ impl fmt::Display for Error {
             Error::Daemonize(ref e) => e.fmt(f),
             Error::Json(ref e) => e.fmt(f),
             Error::Regex(ref e) => e.fmt(f),
+            Error::Exec(ref e) => e.fmt(f),
+            Error::WindowNotFound(id) => {
+                write!(f, "Window id {id} not found")
+            }
+            Error::NoMatchingWindow(ref selector) => {
+                write!(f, "No window matches '{selector}'")
+            }
+            Error::NiriUnreachable(ref e) => {
+                write!(f, "Can not connect to niri: {e}")
+            }
+            Error::NoFocusedWindow => write!(f, "No focused niri window"),
+            Error::Unsupported(op) => {
+                write!(f, "Focused application does not support {op}")
+            }
+            Error::Kitty(ref e) => e.fmt(f),
+            Error::Context(ref ctx, ref e) => write!(f, "{ctx}: {e}"),
         }
     }
 }
@@ -49,6 +80,210 @@ impl std::error::Error for Error {
             Error::Daemonize(ref e) => e.description(),
             Error::Json(ref e) => e.description(),
             Error::Regex(ref e) => e.description(),
+            Error::Exec(ref e) => e.source.description(),
+            Error::WindowNotFound(_) => "window id not found",
+            Error::NoMatchingWindow(_) => "no matching window",
+            Error::NiriUnreachable(ref e) => e.description(),
+            Error::NoFocusedWindow => "no focused niri window",
+            Error::Unsupported(_) => "unsupported application",
+            Error::Kitty(ref e) => e.description(),
+            Error::Context(_, ref e) => e.description(),
+        }
+    }
+
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match *self {
+            Error::Context(_, ref e) => Some(e.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+/// Context of failed operation
+///
+/// Describes what was done when error occurred: the operation itself, the
+/// niri window and the socket or file it was done with.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Context {
+    /// Short description of operation, like "matching kitty"
+    pub operation: String,
+    /// Id of niri window operation was performed for
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub window: Option<u64>,
+    /// Path to socket or file operation was performed with
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path: Option<PathBuf>,
+}
+
+impl Context {
+    /// Create context of operation
+    pub fn new<S: Into<String>>(operation: S) -> Self {
+        Self {
+            operation: operation.into(),
+            ..Default::default()
+        }
+    }
+
+    /// Set niri window of operation
+    pub fn window(mut self, id: u64) -> Self {
+        self.window = Some(id);
+        self
+    }
+
+    /// Set socket or file path of operation
+    pub fn path<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.path = Some(path.into());
+        self
+    }
+}
+
+impl fmt::Display for Context {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.operation)?;
+        if let Some(path) = &self.path {
+            write!(f, " {}", path.display())?;
+        }
+        if let Some(id) = self.window {
+            write!(f, " for window {id}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Extension of results to annotate errors with [Context]
+pub trait ResultExt<T> {
+    /// Annotate error with context of operation
+    fn context(self, ctx: impl FnOnce() -> Context) -> Result<T>;
+}
+
+impl<T, E: Into<Error>> ResultExt<T> for std::result::Result<T, E> {
+    fn context(self, ctx: impl FnOnce() -> Context) -> Result<T> {
+        self.map_err(|err| Error::Context(ctx(), Box::new(err.into())))
+    }
+}
+
+/// Details of failed launch of external program
+#[derive(Debug)]
+pub struct ExecError {
+    /// Command line of program
+    pub cmdline: String,
+    /// Working directory of program if set
+    pub cwd: Option<PathBuf>,
+    /// Number of environment variables set for program
+    pub envs: usize,
+    /// The original error
+    pub source: io::Error,
+}
+
+impl fmt::Display for ExecError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Failed to launch `{}`: {}", self.cmdline, self.source)?;
+        if let Some(cwd) = &self.cwd {
+            write!(f, " (cwd: {}", cwd.display())?;
+        } else {
+            write!(f, " (cwd: inherited")?;
+        }
+        write!(f, ", {} environment variables set)", self.envs)
+    }
+}
+
+/// Exit code of generic failure
+pub const EXIT_FAILURE: i32 = 1;
+/// Exit code of window requested with `--window` is absent
+pub const EXIT_WINDOW_NOT_FOUND: i32 = 3;
+/// Exit code of niri socket is unreachable
+pub const EXIT_NIRI_UNREACHABLE: i32 = 4;
+/// Exit code of there is no focused window
+pub const EXIT_NO_FOCUSED_WINDOW: i32 = 5;
+/// Exit code of focused application does not support operation
+pub const EXIT_UNSUPPORTED: i32 = 6;
+/// Exit code of kitty socket failure
+pub const EXIT_KITTY: i32 = 7;
+/// Exit code of neovim failure
+pub const EXIT_NEOVIM: i32 = 8;
+/// Exit code of external program could not be executed
+pub const EXIT_SPAWN: i32 = 126;
+/// Exit code of external program not found
+pub const EXIT_SPAWN_NOT_FOUND: i32 = 127;
+
+impl Error {
+    /// The innermost error stripped of all [contexts](Context)
+    pub fn root(&self) -> &Error {
+        match self {
+            Error::Context(_, e) => e.root(),
+            e => e,
+        }
+    }
+
+    /// Short machine-readable name of error class
+    pub fn kind(&self) -> &'static str {
+        match self.root() {
+            Error::Io(_) => "io",
+            Error::Neovim(_) => "neovim",
+            Error::Str(_) => "message",
+            Error::Daemonize(_) => "daemonize",
+            Error::Json(_) => "json",
+            Error::Regex(_) => "regex",
+            Error::Exec(_) => "exec",
+            Error::WindowNotFound(_) | Error::NoMatchingWindow(_) => {
+                "window_not_found"
+            }
+            Error::NiriUnreachable(_) => "niri_unreachable",
+            Error::NoFocusedWindow => "no_focused_window",
+            Error::Unsupported(_) => "unsupported",
+            Error::Kitty(_) => "kitty",
+            Error::Context(..) => unreachable!(),
+        }
+    }
+
+    /// Machine-readable representation of error
+    ///
+    /// The object contains error `kind`, `message` of the innermost error,
+    /// `exit_code` and the `context` list from outermost to innermost.
+    pub fn to_json(&self) -> serde_json::Value {
+        let mut context = Vec::new();
+        let mut err = self;
+        while let Error::Context(ctx, source) = err {
+            context.push(ctx);
+            err = source;
+        }
+        serde_json::json!({
+            "kind": self.kind(),
+            "message": err.to_string(),
+            "exit_code": self.exit_code(),
+            "context": context,
+        })
+    }
+
+    /// Exit code of process failed with this error
+    ///
+    /// | Code | Failure                                          |
+    /// |------|--------------------------------------------------|
+    /// | 1    | Generic failure                                  |
+    /// | 2    | Invalid command line arguments                   |
+    /// | 3    | Window requested with `--window` does not exist  |
+    /// | 4    | Niri is unreachable                              |
+    /// | 5    | There is no focused window                       |
+    /// | 6    | Focused application does not support operation  |
+    /// | 7    | Kitty socket failure                             |
+    /// | 8    | Neovim failure                                   |
+    /// | 126  | External program could not be executed           |
+    /// | 127  | External program not found                       |
+    pub fn exit_code(&self) -> i32 {
+        match self.root() {
+            Error::Exec(e) if e.source.kind() == io::ErrorKind::NotFound => {
+                EXIT_SPAWN_NOT_FOUND
+            }
+            Error::Exec(_) => EXIT_SPAWN,
+            Error::WindowNotFound(_) | Error::NoMatchingWindow(_) => {
+                EXIT_WINDOW_NOT_FOUND
+            }
+            Error::NiriUnreachable(_) => EXIT_NIRI_UNREACHABLE,
+            Error::NoFocusedWindow => EXIT_NO_FOCUSED_WINDOW,
+            Error::Unsupported(_) => EXIT_UNSUPPORTED,
+            Error::Kitty(_) => EXIT_KITTY,
+            Error::Neovim(_) => EXIT_NEOVIM,
+            _ => EXIT_FAILURE,
         }
     }
 }
@@ -68,8 +303,8 @@ impl From<Error> for io::Error {
     }
 }
 
-impl From<neovim_lib::CallError> for Error {
-    fn from(value: neovim_lib::CallError) -> Self {
+impl From<Box<nvim_rs::error::CallError>> for Error {
+    fn from(value: Box<nvim_rs::error::CallError>) -> Self {
         Self::Neovim(value)
     }
 }
@@ -0,0 +1,160 @@
+//! Guarded launching of external programs
+//!
+//! Plain [CommandExt::exec] loses all the context on failure. These helpers
+//! check the program before launching and report the command line and the
+//! environment summary on error.
+
+use crate::error::{Error, ExecError, Result};
+use std::{
+    env,
+    ffi::OsStr,
+    io,
+    os::unix::process::CommandExt,
+    path::{Path, PathBuf},
+    process,
+};
+
+/// Find program in directories of `PATH` of the command
+fn find_program(proc: &process::Command) -> Option<PathBuf> {
+    let program = Path::new(proc.get_program());
+    if program.components().count() > 1 {
+        return program.is_file().then(|| program.into());
+    }
+    let path = proc
+        .get_envs()
+        .find(|(k, _)| *k == OsStr::new("PATH"))
+        .and_then(|(_, v)| v.map(OsStr::to_os_string))
+        .or_else(|| env::var_os("PATH"))?;
+    env::split_paths(&path)
+        .map(|dir| dir.join(program))
+        .find(|candidate| candidate.is_file())
+}
+
+fn make_error(proc: &process::Command, source: io::Error) -> Error {
+    let cmdline = std::iter::once(proc.get_program())
+        .chain(proc.get_args())
+        .map(|arg| arg.to_string_lossy())
+        .collect::<Vec<_>>()
+        .join(" ");
+    Error::Exec(ExecError {
+        cmdline,
+        cwd: proc.get_current_dir().map(Path::to_path_buf),
+        envs: proc.get_envs().count(),
+        source,
+    })
+}
+
+fn check(proc: &process::Command) -> Result<()> {
+    if find_program(proc).is_none() {
+        return Err(make_error(
+            proc,
+            io::Error::new(io::ErrorKind::NotFound, "program not found"),
+        ));
+    }
+    Ok(())
+}
+
+/// Variables carrying activation token of launched program
+const ACTIVATION_VARS: [&str; 2] =
+    ["XDG_ACTIVATION_TOKEN", "DESKTOP_STARTUP_ID"];
+
+/// Pass own activation token to the command
+///
+/// Niri gives activation token to programs spawned by binds, so the window of
+/// program launched by us may take focus with it. The token inherited from
+/// the base window is already used and would leave the new window unfocused,
+/// so it is never passed.
+pub fn pass_activation_token(proc: &mut process::Command) {
+    for var in ACTIVATION_VARS {
+        match env::var_os(var) {
+            Some(token) => proc.env(var, token),
+            None => proc.env_remove(var),
+        };
+    }
+}
+
+/// Whenever user's systemd instance is available
+fn has_user_systemd() -> bool {
+    let runtime = env::var_os("XDG_RUNTIME_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| {
+            PathBuf::from(format!("/run/user/{}", nix::unistd::getuid()))
+        });
+    runtime.join("systemd").join("private").exists()
+        && find_program(&process::Command::new("systemd-run")).is_some()
+}
+
+/// Keep only characters allowed in systemd unit names
+fn unit_escape(name: &str) -> String {
+    name.chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '_' | '.' | ':' => c,
+            _ => '_',
+        })
+        .collect()
+}
+
+/// Name of transient scope unit of the command
+///
+/// Follows `app-<launcher>-<app>-<random>` convention of desktop
+/// environments, the directory name of cwd is appended to the app, e.g.
+/// `app-niri_integration-kitty-myproject-1234.scope`. The pid of launcher
+/// makes the name unique.
+fn unit_name(proc: &process::Command) -> String {
+    let program = Path::new(proc.get_program())
+        .file_name()
+        .unwrap_or(proc.get_program())
+        .to_string_lossy();
+    let mut app = unit_escape(&program);
+    let dir = proc
+        .get_current_dir()
+        .and_then(Path::file_name)
+        .map(OsStr::to_string_lossy);
+    if let Some(dir) = dir {
+        app = format!("{app}-{}", unit_escape(&dir));
+    }
+    format!("app-niri_integration-{app}-{}.scope", process::id())
+}
+
+/// Wrap the command to run within transient systemd scope in `slice`
+///
+/// The unit name is derived from program and its cwd. Returns the command as
+/// is if systemd is not available.
+pub fn scoped(proc: process::Command, slice: &str) -> process::Command {
+    if !has_user_systemd() {
+        return proc;
+    }
+    let mut scoped = process::Command::new("systemd-run");
+    scoped
+        .args(["--user", "--scope", "--quiet", "--collect"])
+        .arg(format!("--slice={slice}"))
+        .arg(format!("--unit={}", unit_name(&proc)))
+        .arg("--")
+        .arg(proc.get_program())
+        .args(proc.get_args());
+    for (name, value) in proc.get_envs() {
+        match value {
+            Some(value) => scoped.env(name, value),
+            None => scoped.env_remove(name),
+        };
+    }
+    if let Some(cwd) = proc.get_current_dir() {
+        scoped.current_dir(cwd);
+    }
+    scoped
+}
+
+/// Replace current process with the command
+///
+/// Returns only on failure.
+pub fn exec(proc: &mut process::Command) -> Result<()> {
+    check(proc)?;
+    let err = proc.exec();
+    Err(make_error(proc, err))
+}
+
+/// Launch the command as child process
+pub fn spawn(proc: &mut process::Command) -> Result<process::Child> {
+    check(proc)?;
+    proc.spawn().map_err(|err| make_error(proc, err))
+}
@@ -0,0 +1,48 @@
+//! Cheap detection of git repository of directory
+//!
+//! Only the filesystem is inspected: the closest `.git` is searched up from
+//! the directory and its `HEAD` is read. Worktrees and submodules with `.git`
+//! file pointing to the actual git directory are supported.
+
+use serde::Serialize;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// Git repository of directory
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Repo {
+    /// Root of working tree
+    pub root: PathBuf,
+    /// Checked out branch, [None] for detached head
+    pub branch: Option<String>,
+}
+
+impl Repo {
+    /// Detect repository the directory belongs to
+    pub fn detect(cwd: &Path) -> Option<Self> {
+        let root = cwd.ancestors().find(|dir| dir.join(".git").exists())?;
+        let git_dir = Self::git_dir(root)?;
+        let head = fs::read_to_string(git_dir.join("HEAD")).ok()?;
+        let branch = head
+            .trim()
+            .strip_prefix("ref: refs/heads/")
+            .map(String::from);
+        Some(Self {
+            root: root.into(),
+            branch,
+        })
+    }
+
+    /// Resolve `.git` of working tree root to the git directory
+    fn git_dir(root: &Path) -> Option<PathBuf> {
+        let dotgit = root.join(".git");
+        if dotgit.is_dir() {
+            return Some(dotgit);
+        }
+        let content = fs::read_to_string(&dotgit).ok()?;
+        let dir = content.trim().strip_prefix("gitdir:")?.trim();
+        Some(root.join(dir))
+    }
+}
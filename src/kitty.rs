@@ -1,170 +1,490 @@
+use crate::{dryrun, trace};
 use serde::{Deserialize, Serialize};
 use std::io::{BufReader, BufWriter, prelude::*};
 use std::{
-    collections, io,
+    cell::RefCell,
+    collections::{self, HashMap, VecDeque},
+    io,
     os::unix::net::UnixStream,
     path::{Path, PathBuf},
 };
 
+thread_local! {
+    /// Connections kept open between commands, by path of socket
+    static SESSIONS: RefCell<Option<HashMap<PathBuf, UnixStream>>> =
+        const { RefCell::new(None) };
+}
+
+/// Keep kitty connections of this thread open between commands
+///
+/// Used by batch and repl modes, which run many commands in one process.
+pub fn keep_sessions() {
+    SESSIONS.with_borrow_mut(|sessions| {
+        sessions.get_or_insert_default();
+    });
+}
+
+/// Whenever connection is open and has no unread data
+fn is_open(stream: &UnixStream) -> bool {
+    let mut byte = [0];
+    stream.set_nonblocking(true).is_ok()
+        && matches!(
+            stream.peek(&mut byte),
+            Err(ref err) if err.kind() == io::ErrorKind::WouldBlock
+        )
+        && stream.set_nonblocking(false).is_ok()
+}
+
+/// Command serialized as payload of kitty remote control frame
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct CommandPacked {
+    /// Name of command, e.g. `ls`
     pub cmd: String,
+    /// Version of kitty protocol
     pub version: KittyVersion,
+    /// Do not wait for response of kitty
     #[serde(skip_serializing_if = "Option::is_none")]
     pub no_response: Option<bool>,
+    /// Arguments of command
     #[serde(skip_serializing_if = "Option::is_none")]
     pub payload: Option<serde_json::Value>,
 }
 
+/// Version of kitty as `[major, minor, patch]`
 pub type KittyVersion = Vec<i8>;
 
+/// Kitty remote control command
 #[derive(Debug, Serialize, Deserialize, Clone)]
 #[serde(rename_all = "kebab-case")]
 pub enum Command {
+    /// Run mappable action
     Action(Action),
+    /// List OS windows, tabs and windows
     Ls(Ls),
+    /// Launch program in new window
     Launch(Launch),
+    /// Send text to window
+    SendText(SendText),
+    /// Set the title of window
+    SetWindowTitle(SetTitle),
+    /// Set the title of tab
+    SetTabTitle(SetTitle),
+    /// Resize window within layout
+    ResizeWindow(ResizeWindow),
+    /// Switch layout of tab
+    GotoLayout(GotoLayout),
+    /// Switch tab to previously used layout
+    LastUsedLayout(LastUsedLayout),
+    /// Focus tab
+    FocusTab(FocusTab),
+    /// Close window
+    CloseWindow(CloseWindow),
 }
 
+/// Arguments of `action` command
 #[derive(Debug, Serialize, Deserialize, Clone, Default)]
 pub struct Action {
-    pub action: Vec<String>,
+    /// Action with optional arguments, e.g. `goto_tab 1`
+    pub action: String,
+    /// Query of window to run action in
     #[serde(rename = "match")]
     #[serde(skip_serializing_if = "Option::is_none")]
     pub match_window: Option<String>,
+    /// Run action in window command is sent from
     #[serde(rename = "self")]
     #[serde(skip_serializing_if = "Option::is_none")]
     pub self_window: Option<bool>,
 }
 
+/// Arguments of `ls` command
 #[derive(Debug, Serialize, Deserialize, Clone, Default)]
 pub struct Ls {
+    /// List all environment variables, not only the changed ones
     #[serde(skip_serializing_if = "Option::is_none")]
     pub all_env_vars: Option<bool>,
+    /// Query of windows to list
     #[serde(rename = "match")]
     #[serde(skip_serializing_if = "Option::is_none")]
     pub match_window: Option<String>,
+    /// Query of tabs to list
     #[serde(skip_serializing_if = "Option::is_none")]
     pub match_tab: Option<String>,
+    /// List only window command is sent from
     #[serde(rename = "self")]
     #[serde(skip_serializing_if = "Option::is_none")]
     pub self_window: Option<bool>,
 }
 
+/// Arguments of `launch` command
 #[derive(Debug, Serialize, Deserialize, Clone, Default)]
 pub struct Launch {
+    /// Program with arguments, shell if empty
     pub args: Vec<String>,
+    /// Query of tab to launch window in
     #[serde(rename = "match")]
     #[serde(skip_serializing_if = "Option::is_none")]
     pub match_tab: Option<String>,
+    /// Title of new window
     #[serde(skip_serializing_if = "Option::is_none")]
     pub window_title: Option<String>,
+    /// Working directory of new window
     #[serde(skip_serializing_if = "Option::is_none")]
     pub cwd: Option<PathBuf>,
+    /// Environment variables as `NAME=value`
     #[serde(skip_serializing_if = "Option::is_none")]
     pub env: Option<Vec<String>>,
+    /// User variables as `NAME=value`
     #[serde(skip_serializing_if = "Option::is_none")]
     pub var: Option<Vec<String>>,
+    /// Title of new tab
     #[serde(skip_serializing_if = "Option::is_none")]
     pub tab_title: Option<String>,
+    /// Where to launch program
     #[serde(rename = "type")]
     #[serde(skip_serializing_if = "Option::is_none")]
     pub launch_type: Option<LaunchType>,
+    /// Keep focus on current window
     #[serde(skip_serializing_if = "Option::is_none")]
     pub keep_focus: Option<bool>,
+    /// Use colors of current window
     #[serde(skip_serializing_if = "Option::is_none")]
     pub copy_colors: Option<bool>,
+    /// Use command line of current window
     #[serde(skip_serializing_if = "Option::is_none")]
     pub copy_cmdline: Option<bool>,
+    /// Use environment of current window
     #[serde(skip_serializing_if = "Option::is_none")]
     pub copy_env: Option<bool>,
+    /// Keep window open after program exits
     #[serde(skip_serializing_if = "Option::is_none")]
     pub hold: Option<bool>,
+    /// Where to place new window in layout
     #[serde(skip_serializing_if = "Option::is_none")]
     pub location: Option<LaunchLocation>,
+    /// Allow program to control kitty
     #[serde(skip_serializing_if = "Option::is_none")]
     pub allow_remote_control: Option<bool>,
+    /// Passwords allowed to control kitty
     #[serde(skip_serializing_if = "Option::is_none")]
     pub remote_control_password: Option<Vec<String>>,
+    /// Source of data piped to program, e.g. `@screen`
     #[serde(skip_serializing_if = "Option::is_none")]
     pub stdin_source: Option<String>,
+    /// Keep formatting of data piped to program
     #[serde(skip_serializing_if = "Option::is_none")]
     pub stdin_add_formatting: Option<bool>,
+    /// Spacing of window, e.g. `padding-left=10`
     #[serde(skip_serializing_if = "Option::is_none")]
     pub spacing: Option<Vec<String>>,
+    /// Marker to highlight text with
     #[serde(skip_serializing_if = "Option::is_none")]
     pub marker: Option<String>,
+    /// Path of logo image to show in window
     #[serde(skip_serializing_if = "Option::is_none")]
     pub logo: Option<PathBuf>,
+    /// Position of logo, e.g. `bottom-right`
     #[serde(skip_serializing_if = "Option::is_none")]
     pub logo_position: Option<String>,
+    /// Opacity of logo
     #[serde(skip_serializing_if = "Option::is_none")]
     pub logo_alpha: Option<f32>,
+    /// Launch in tab of window command is sent from
     #[serde(rename = "self")]
     #[serde(skip_serializing_if = "Option::is_none")]
     pub self_tab: Option<bool>,
+    /// Title of new OS window
     #[serde(skip_serializing_if = "Option::is_none")]
     pub os_window_title: Option<String>,
+    /// Name of new OS window
     #[serde(skip_serializing_if = "Option::is_none")]
     pub os_window_name: Option<String>,
+    /// Class of new OS window
     #[serde(skip_serializing_if = "Option::is_none")]
     pub os_window_class: Option<String>,
+    /// Colors of window as `name=value`
     #[serde(skip_serializing_if = "Option::is_none")]
     pub color: Option<Vec<String>>,
+    /// Paths of watchers of new window
     #[serde(skip_serializing_if = "Option::is_none")]
     pub watcher: Option<Vec<PathBuf>>,
+    /// Part of space in layout taken by new window
     #[serde(skip_serializing_if = "Option::is_none")]
     pub bias: Option<f32>,
 }
 
+/// Arguments of `send-text` command
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct SendText {
+    /// Text in form `text:<text>` or `base64:<data>`
+    pub data: String,
+    /// Query of window to send text to
+    #[serde(rename = "match")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub match_window: Option<String>,
+    /// Query of tab to send text to
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub match_tab: Option<String>,
+    /// Send text to all windows
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub all: Option<bool>,
+    /// Do not send text to active window
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exclude_active: Option<bool>,
+}
+
+/// Arguments of `set-window-title` and `set-tab-title` commands
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct SetTitle {
+    /// New title
+    pub title: String,
+    /// Query of window or tab to set title of
+    #[serde(rename = "match")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub match_window: Option<String>,
+    /// Keep title only until program sets its own one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub temporary: Option<bool>,
+}
+
+/// Arguments of `resize-window` command
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct ResizeWindow {
+    /// Query of window to resize
+    #[serde(rename = "match")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub match_window: Option<String>,
+    /// Number of cells to resize by
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub increment: Option<i32>,
+    /// One of `horizontal`, `vertical` or `reset`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub axis: Option<String>,
+    /// Resize window command is sent from
+    #[serde(rename = "self")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub self_window: Option<bool>,
+}
+
+/// Arguments of `goto-layout` command
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct GotoLayout {
+    /// Name of layout, e.g. `stack`
+    pub layout: String,
+    /// Query of tab to switch layout in
+    #[serde(rename = "match")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub match_tab: Option<String>,
+}
+
+/// Arguments of `last-used-layout` command
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct LastUsedLayout {
+    /// Query of tab to switch layout in
+    #[serde(rename = "match")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub match_tab: Option<String>,
+    /// Switch layout in all matched tabs
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub all: Option<bool>,
+}
+
+/// Arguments of `focus-tab` command
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct FocusTab {
+    /// Query of tab to focus
+    #[serde(rename = "match")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub match_tab: Option<String>,
+}
+
+/// Arguments of `close-window` command
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct CloseWindow {
+    /// Query of windows to close
+    #[serde(rename = "match")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub match_window: Option<String>,
+}
+
+/// Where `launch` command starts program
 #[derive(Debug, Serialize, Deserialize, Clone)]
 #[serde(rename_all = "kebab-case")]
 pub enum LaunchType {
+    /// New window in current tab
     Window,
+    /// New tab
     Tab,
+    /// New OS window
     OsWindow,
+    /// Overlay over current window
     Overlay,
+    /// Overlay over main window of current window
     OverlayMain,
+    /// Background process without window
     Background,
+    /// Output of program to clipboard
     Clipboard,
+    /// Output of program to primary selection
     Primary,
 }
 
+/// Place of new window in layout
 #[derive(Debug, Serialize, Deserialize, Clone)]
 #[serde(rename_all = "kebab-case")]
 pub enum LaunchLocation {
+    /// After current window
     After,
+    /// Before current window
     Before,
+    /// Default place of layout
     Default,
+    /// First window of layout
     First,
+    /// Horizontal split of current window
     Hsplit,
+    /// Last window of layout
     Last,
+    /// Next to current window
     Neighbor,
+    /// Split of current window in direction of longer side
     Split,
+    /// Vertical split of current window
     Vsplit,
 }
 
+/// Kitty OS window as listed by `ls`
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct OsWindow {
+    /// Whenever window is active within kitty
     pub is_active: bool,
+    /// Whenever window has keyboard focus
     pub is_focused: bool,
+    /// Tabs of window
     pub tabs: Vec<Tab>,
 }
 
+/// Kitty tab as listed by `ls`
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Tab {
+    /// Id of tab
+    #[serde(default)]
+    pub id: u64,
+    /// Whenever tab is active within its OS window
     pub is_active: bool,
+    /// Whenever tab has keyboard focus
     pub is_focused: bool,
+    /// Name of current layout
+    #[serde(default)]
+    pub layout: String,
+    /// Windows of tab
     pub windows: Vec<Window>,
 }
 
+/// Kitty window as listed by `ls`
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Window {
+    /// Id of window
+    #[serde(default)]
+    pub id: u64,
+    /// Whenever window is active within its tab
     pub is_active: bool,
+    /// Whenever window has keyboard focus
     pub is_focused: bool,
+    /// Title of window
+    #[serde(default)]
+    pub title: String,
+    /// Command line of program window is launched with
     pub cmdline: Vec<String>,
+    /// Working directory of window
     pub cwd: PathBuf,
+    /// Environment of window
     pub env: std::collections::HashMap<String, String>,
+    /// Processes in foreground of window terminal
+    #[serde(default)]
+    pub foreground_processes: Vec<Process>,
+}
+
+/// Process running in kitty window
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Process {
+    /// Process id
+    pub pid: i32,
+    /// Working directory of process
+    #[serde(default)]
+    pub cwd: Option<PathBuf>,
+    /// Command line of process
+    #[serde(default)]
+    pub cmdline: Vec<String>,
+}
+
+impl OsWindow {
+    /// Get the active window of active tab
+    pub fn active_window(&self) -> Option<&Window> {
+        let tab = self.tabs.iter().find(|tab| tab.is_active)?;
+        tab.windows.iter().find(|window| window.is_active)
+    }
+
+    /// Get the title of OS window, the one of its active window
+    pub fn title(&self) -> Option<&str> {
+        self.active_window().map(|window| window.title.as_str())
+    }
+}
+
+/// Options of ssh which consume the next argument
+const SSH_OPTIONS_WITH_VALUE: &str = "BbcDEeFIiJLlmOoPpQRSWw";
+
+impl Window {
+    /// Get the cwd which is semantically correct for this window
+    ///
+    /// Kitty tracks the cwd of foreground processes using shell integration, so
+    /// within `su` sessions this is the cwd of the process user works with.
+    /// The local cwd of `ssh` process is meaningless, so for remote sessions
+    /// and as fall-back the cwd of the window itself is used, which is
+    /// reported by remote shell integration.
+    pub fn effective_cwd(&self) -> &Path {
+        if self.ssh_destination().is_some() {
+            return self.cwd.as_path();
+        }
+        self.foreground_processes
+            .iter()
+            .rev()
+            .find_map(|process| process.cwd.as_deref())
+            .unwrap_or(self.cwd.as_path())
+    }
+
+    /// Get the destination of ssh session running in foreground of window
+    pub fn ssh_destination(&self) -> Option<String> {
+        self.foreground_processes
+            .iter()
+            .find_map(|process| process.ssh_destination())
+    }
+}
+
+impl Process {
+    /// Get the destination if process is ssh client
+    pub fn ssh_destination(&self) -> Option<String> {
+        let mut args = self.cmdline.iter();
+        let exe = Path::new(args.next()?).file_name()?.to_str()?;
+        if exe == "kitten" {
+            // kitten ssh [options] destination
+            if args.next()? != "ssh" {
+                return None;
+            }
+        } else if exe != "ssh" {
+            return None;
+        }
+        while let Some(arg) = args.next() {
+            if let Some(opts) = arg.strip_prefix('-') {
+                // Option with value in the next argument, e.g. `-p 22`
+                if opts.len() == 1 && SSH_OPTIONS_WITH_VALUE.contains(opts) {
+                    args.next();
+                }
+            } else {
+                return Some(arg.clone());
+            }
+        }
+        None
+    }
 }
 
 impl From<Command> for CommandPacked {
@@ -187,111 +507,258 @@ impl From<Command> for CommandPacked {
     }
 }
 
+/// Transport of kitty remote control commands
+///
+/// Implemented by the real [KittySocket], by [MockKitty] and by recorded
+/// [trace::Replay], so the logic built on top of commands is testable.
+pub trait KittyTransport {
+    /// Send command and wait for its response
+    fn request(&mut self, cmd: Command) -> io::Result<serde_json::Value>;
+}
+
+impl KittyTransport for KittySocket {
+    fn request(&mut self, cmd: Command) -> io::Result<serde_json::Value> {
+        KittySocket::request(self, cmd)
+    }
+}
+
+impl KittyTransport for trace::Replay {
+    fn request(&mut self, cmd: Command) -> io::Result<serde_json::Value> {
+        self.respond("kitty", &cmd)
+            .map_err(|err| io::Error::other(err.to_string()))
+    }
+}
+
+/// In-memory kitty serving fixed windows and recording other commands
+#[cfg(any(test, feature = "lib"))]
+#[derive(Debug, Clone, Default)]
+pub struct MockKitty {
+    /// OS windows returned by `ls`
+    pub windows: Vec<OsWindow>,
+    /// Commands other than `ls` received so far
+    pub commands: Vec<Command>,
+}
+
+#[cfg(any(test, feature = "lib"))]
+impl KittyTransport for MockKitty {
+    fn request(&mut self, cmd: Command) -> io::Result<serde_json::Value> {
+        match cmd {
+            Command::Ls(_) => Ok(serde_json::to_value(&self.windows)?),
+            cmd => {
+                self.commands.push(cmd);
+                Ok(serde_json::Value::Null)
+            }
+        }
+    }
+}
+
+/// Frame `cmd` as kitty remote control escape sequence
+pub(crate) fn encode(cmd: Command, no_response: bool) -> Vec<u8> {
+    let mut cmd = CommandPacked::from(cmd);
+    cmd.no_response = Some(no_response);
+    // Payload may carry environment with secrets, so it is not logged
+    log::trace!("kitty request {}", cmd.cmd);
+    let cmd = serde_json::to_string(&cmd).unwrap();
+    let mut frame = Vec::with_capacity(cmd.len() + 14);
+    frame.push(0x1b);
+    frame.extend_from_slice(b"P@kitty-cmd");
+    frame.extend_from_slice(cmd.as_bytes());
+    frame.push(0x1b);
+    frame.push(b'\\');
+    frame
+}
+
+/// Check the 12 bytes starting response frame
+pub(crate) fn check_head(head: &[u8; 12]) -> io::Result<()> {
+    if head[0] != 0x1b {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "Got invalid head escape byte from kitty",
+        ));
+    }
+
+    if &head[1..] != b"P@kitty-cmd" {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "Got invalid head escape sequence from kitty",
+        ));
+    }
+    Ok(())
+}
+
+/// Check response body read up to escape byte and the byte following it
+pub(crate) fn check_tail(data: &[u8], tail: &[u8; 1]) -> io::Result<()> {
+    if data.last() != Some(&0x1b) || tail[0] != b'\\' {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "Got invalid tail escape sequence from kitty",
+        ));
+    }
+    Ok(())
+}
+
+/// Parse body of response frame
+pub(crate) fn parse_response(data: &[u8]) -> io::Result<serde_json::Value> {
+    let mut rsp: collections::HashMap<String, serde_json::Value> =
+        serde_json::from_slice(data)?;
+    let ok = rsp.remove("ok");
+    if let Some(ok) = ok {
+        if ok != true {
+            Err(io::Error::new(io::ErrorKind::Other, "Got error from kitty"))
+        } else {
+            let data = rsp.remove("data");
+            if let Some(data) = data {
+                if let Some(data) = data.as_str() {
+                    Ok(serde_json::from_str(&data)?)
+                } else {
+                    Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "Kitty returns invalid data in 'data' field",
+                    ))
+                }
+            } else {
+                // Commands without output
+                Ok(serde_json::Value::Null)
+            }
+        }
+    } else {
+        Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "Kitty returns invalid response w/o 'ok' field",
+        ))
+    }
+}
+
+/// Connection to kitty remote control socket
 pub struct KittySocket {
     socket: UnixStream,
+    /// Reader must outlive single response to not lose buffered data of the
+    /// next one
+    reader: BufReader<UnixStream>,
+    /// Sent requests which responses are not read yet
+    pending: VecDeque<Command>,
 }
 
 impl KittySocket {
-    pub fn connect(socket: impl AsRef<Path>) -> io::Result<Self> {
+    /// Connect kitty or reuse the kept connection to the same socket
+    pub fn connect(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref();
+        // Kept connection with unread data is left by failed command
+        let kept = SESSIONS.with_borrow(|sessions| {
+            let stream = sessions.as_ref()?.get(path)?;
+            is_open(stream).then(|| stream.try_clone().ok()).flatten()
+        });
+        let socket = match kept {
+            Some(socket) => socket,
+            None => {
+                let socket = UnixStream::connect(path)?;
+                SESSIONS.with_borrow_mut(|sessions| {
+                    if let (Some(sessions), Ok(kept)) =
+                        (sessions, socket.try_clone())
+                    {
+                        sessions.insert(path.into(), kept);
+                    }
+                });
+                socket
+            }
+        };
         Ok(Self {
-            socket: UnixStream::connect(socket)?,
+            reader: BufReader::new(socket.try_clone()?),
+            socket,
+            pending: VecDeque::new(),
         })
     }
 
     fn send_with(&mut self, cmd: Command, no_response: bool) -> io::Result<()> {
-        let mut cmd = CommandPacked::from(cmd);
-        cmd.no_response = Some(no_response);
-        let cmd = serde_json::to_string(&cmd).unwrap();
-        {
-            let mut writer = BufWriter::new(&mut self.socket);
-            //let mut writer = BufWriter::new(std::fs::File::create("/tmp/test.txt")?);
-            writer.write_all(&vec![0x1b])?;
-            writer.write_all(b"P@kitty-cmd")?;
-            writer.write_all(cmd.as_bytes())?;
-            writer.write_all(&vec![0x1b])?;
-            writer.write_all(b"\\")?;
-        }
+        let mut writer = BufWriter::new(&mut self.socket);
+        writer.write_all(&encode(cmd, no_response))?;
+        writer.flush()
+    }
 
-        Ok(())
+    fn read_response(&mut self) -> io::Result<serde_json::Value> {
+        let reader = &mut self.reader;
+        let mut head = [0; 12];
+        reader.read_exact(&mut head)?;
+        check_head(&head)?;
+
+        let mut data = Vec::new();
+        reader.read_until(0x1b, &mut data)?;
+        let mut tail = [0; 1];
+        reader.read_exact(&mut tail)?;
+        check_tail(&data, &tail)?;
+        data.pop();
+
+        parse_response(&data)
     }
 
+    /// Send command and wait for its response
     pub fn request(&mut self, cmd: Command) -> io::Result<serde_json::Value> {
-        self.send_with(cmd, false)?;
-        let mut reader = BufReader::new(&mut self.socket);
-        let mut esc = [0; 12];
-        reader.read_exact(&mut esc)?;
-        if esc[0] != 0x1b {
-            return Err(io::Error::new(
-                io::ErrorKind::InvalidData,
-                "Got invalid head escape byte from kitty",
-            ));
+        if Self::skip_dry_run(&cmd) {
+            return Ok(serde_json::Value::Null);
         }
+        self.collect()?;
+        self.send_with(cmd.clone(), false)?;
+        let response = self.read_response()?;
+        trace::record("kitty", &cmd, &response);
+        Ok(response)
+    }
 
-        if &esc[1..] != b"P@kitty-cmd" {
-            return Err(io::Error::new(
-                io::ErrorKind::InvalidData,
-                "Got invalid head escape sequence from kitty",
-            ));
+    /// Send command without waiting for its response
+    ///
+    /// The response should be read later with [Self::collect]. This allows to
+    /// issue several commands without paying round-trip for each one.
+    pub fn post(&mut self, cmd: Command) -> io::Result<()> {
+        if Self::skip_dry_run(&cmd) {
+            return Ok(());
         }
+        self.send_with(cmd.clone(), false)?;
+        self.pending.push_back(cmd);
+        Ok(())
+    }
 
-        let mut data = String::new();
-
-        let mut escaped = false;
-        for byte in reader.bytes() {
-            let byte = byte?;
-            if escaped {
-                if byte != b'\\' {
-                    return Err(io::Error::new(
-                        io::ErrorKind::InvalidData,
-                        "Got invalid tail escape sequence from kitty",
-                    ));
-                } else {
-                    break;
-                }
-            }
-            if byte == 0x1b {
-                escaped = true;
-            } else {
-                data.push(byte.into());
-            }
+    /// Read responses of all commands sent with [Self::post]
+    pub fn collect(&mut self) -> io::Result<Vec<serde_json::Value>> {
+        let mut responses = Vec::with_capacity(self.pending.len());
+        while let Some(cmd) = self.pending.pop_front() {
+            let response = self.read_response()?;
+            trace::record("kitty", &cmd, &response);
+            responses.push(response);
         }
+        Ok(responses)
+    }
 
-        let mut rsp: collections::HashMap<String, serde_json::Value> =
-            serde_json::from_str(&data)?;
-        let ok = rsp.remove("ok");
-        if let Some(ok) = ok {
-            if ok != true {
-                Err(io::Error::new(
-                    io::ErrorKind::Other,
-                    "Got error from kitty",
-                ))
-            } else {
-                let data = rsp.remove("data");
-                if let Some(data) = data {
-                    if let Some(data) = data.as_str() {
-                        Ok(serde_json::from_str(&data)?)
-                    } else {
-                        Err(io::Error::new(
-                            io::ErrorKind::InvalidData,
-                            "Kitty returns invalid data in 'data' field",
-                        ))
-                    }
-                } else {
-                    Err(io::Error::new(
-                        io::ErrorKind::InvalidData,
-                        "Kitty returns invalid response w/o 'data' field",
-                    ))
-                }
-            }
-        } else {
-            Err(io::Error::new(
-                io::ErrorKind::InvalidData,
-                "Kitty returns invalid response w/o 'ok' field",
-            ))
+    /// Send fire-and-forget command
+    ///
+    /// Kitty does not reply to such commands at all.
+    pub fn send(&mut self, cmd: Command) -> io::Result<()> {
+        if Self::skip_dry_run(&cmd) {
+            return Ok(());
         }
+        self.send_with(cmd.clone(), true)?;
+        trace::record("kitty", &cmd, &serde_json::Value::Null);
+        Ok(())
     }
 
-    pub fn send(&mut self, cmd: Command) -> io::Result<()> {
-        self.send_with(cmd, true)
+    /// Whenever the mutating command should be skipped in dry-run mode
+    fn skip_dry_run(cmd: &Command) -> bool {
+        !matches!(cmd, Command::Ls(_)) && dryrun::skip_masked("kitty", cmd)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn action_matches_window() {
+        let cmd = CommandPacked::from(Command::Action(Action {
+            action: "goto_tab 1".into(),
+            match_window: Some("id:3".into()),
+            ..Default::default()
+        }));
+        assert_eq!(cmd.cmd, "action");
+        let payload = cmd.payload.unwrap();
+        assert_eq!(payload["match"], "id:3");
+        assert!(payload.get("match_window").is_none());
     }
 }
@@ -8,32 +8,75 @@
 //!
 #![warn(missing_docs)]
 
-use clap::Subcommand;
+pub use builder::{LauncherBuilder, WindowContext};
+use clap::{CommandFactory, Subcommand};
 pub use clap::{Parser, ValueEnum};
-use error::Result;
-use niri_ipc::{Request, Response, socket::Socket};
+use error::{Context, Error, Result, ResultExt};
+use niri::Socket;
+use niri_ipc::{Request, Response};
 use regex;
 use std::ffi::OsString;
 use std::fmt::Display;
-use std::fs::File;
-use std::io::BufRead;
-use std::str;
 use std::{
-    collections::HashMap, io, os::unix::process::CommandExt, path::PathBuf,
+    collections::{HashMap, HashSet},
+    io,
+    path::{Path, PathBuf},
+    time::Duration,
 };
 
+#[cfg(all(feature = "async-daemon", feature = "lib"))]
+pub mod aio;
+#[cfg(all(feature = "async-daemon", not(feature = "lib")))]
+mod aio;
+pub mod builder;
+pub mod config;
+mod container;
+mod control;
+mod daemon;
+mod devenv;
+mod dryrun;
 pub mod error;
+mod exec;
+mod git;
+#[cfg(feature = "lib")]
+pub mod kitty;
+#[cfg(not(feature = "lib"))]
 mod kitty;
-mod pstree;
+mod logging;
+#[cfg(feature = "lib")]
+pub mod niri;
+#[cfg(not(feature = "lib"))]
+mod niri;
+mod operation;
+mod placement;
+pub mod pstree;
+mod source;
+mod state;
+pub mod trace;
+#[cfg(feature = "lib")]
+pub mod vim;
+#[cfg(not(feature = "lib"))]
 mod vim;
+mod vimserver;
 
 /// Top-level arguments structure
-#[derive(Parser, Debug)]
+#[derive(Parser, Debug, Clone)]
 #[command(
     author = "Yury Shvedov (github:ein-shved)",
     version = "0.1",
     about = "Niri launcher",
-    long_about = "Simple utility to smartly launch several tools withing niri."
+    long_about = "Simple utility to smartly launch several tools withing niri.",
+    after_long_help = "Exit codes:
+  1    generic failure
+  2    invalid command line arguments
+  3    window requested with --window does not exist
+  4    niri is unreachable
+  5    there is no focused window
+  6    focused application does not support operation
+  7    kitty socket failure
+  8    neovim failure
+  126  external program could not be executed
+  127  external program not found"
 )]
 pub struct Launcher {
     /// The procedure to run
@@ -47,38 +90,201 @@ pub struct Launcher {
     /// Optional template of kitty socket
     ///
     /// Will accept environment variables in view `${ENV}` and `{pid}` construction
-    /// which will be replaced with pid of target kitty process
-    #[arg(short, long, default_value = "${XDG_RUNTIME_DIR}/kitty-{pid}")]
-    kitty_socket: String,
+    /// which will be replaced with pid of target kitty process. Defaults to
+    /// `kitty-socket` of configuration or `${XDG_RUNTIME_DIR}/kitty-{pid}`.
+    #[arg(short, long)]
+    kitty_socket: Option<String>,
 
     /// Whenever to launch tool regardless to current focused window
     ///
-    /// Launching tool will be run with default cwd withing default environment
+    /// Launching tool will be run with default cwd withing default environment.
+    /// The default cwd is the one of focused workspace in `workspace-dirs` of
+    /// configuration, if any.
     #[arg(short, long, default_value = "false")]
     fresh: bool,
 
-    /// Optional niri window id to base window
+    /// Pick directory of launched program among zoxide ones when there is no
+    /// cwd to inherit (e.g. with `--fresh`)
+    #[arg(long, default_value = "false")]
+    pick_dir: bool,
+
+    /// Optional selector of base window
     ///
-    /// By default this uses focused window
+    /// Accepts niri window id, `focused`, `pointer`, `app-id:ID` or
+    /// `title:/REGEX/`. Among several matching windows the focused or the
+    /// most recently focused one is chosen. With `pick` the window is chosen
+    /// interactively by niri or with configured picker. By default this uses
+    /// focused window.
     #[arg(short, long)]
-    window: Option<u64>,
+    window: Option<WindowSelector>,
+
+    /// Nvim instance to drive when base window hosts several of them
+    ///
+    /// Accepts pid of nvim or path of its socket, see `vim instances`. By
+    /// default nvim in foreground of focused kitty window or the only nvim of
+    /// neovide is driven.
+    #[arg(long)]
+    instance: Option<String>,
 
     /// Whether to daemonize process
     #[arg(short, long, default_value = "false")]
     daemonize: bool,
+
+    /// Increase verbosity of logging, may be repeated (`-vv`)
+    #[arg(short, long, action = clap::ArgAction::Count)]
+    verbose: u8,
+
+    /// Append log to file instead of stderr
+    #[arg(long)]
+    log_file: Option<PathBuf>,
+
+    /// Print mutating requests to niri, kitty and vim and launches of
+    /// programs instead of performing them
+    #[arg(long, default_value = "false")]
+    dry_run: bool,
+
+    /// Record exchanges with niri, kitty and vim to JSON-lines file
+    ///
+    /// Values of secret variables are masked. Useful for bug reports.
+    #[arg(long)]
+    trace_ipc: Option<PathBuf>,
+
+    /// Report failures with desktop notification
+    ///
+    /// Useful when launched from keybinding, there is no terminal to show
+    /// errors then. Uses `notify-send`.
+    #[arg(long, default_value = "false")]
+    notify: bool,
+
+    /// Optional path to configuration file
+    ///
+    /// By default `${XDG_CONFIG_HOME}/niri-integration/config.json` is used if
+    /// exists
+    #[arg(short, long)]
+    config_path: Option<PathBuf>,
+
+    /// Profile of configuration to apply
+    ///
+    /// By default `NIRI_INTEGRATION_PROFILE` environment variable is used if
+    /// set.
+    #[arg(long)]
+    profile: Option<String>,
+
+    /// Re-establish nix develop and direnv environments of launched programs
+    ///
+    /// Instead of copying variables of such environments verbatim, launched
+    /// program is wrapped with `direnv exec` or `nix develop`.
+    #[arg(long, default_value = "false")]
+    refresh_env: bool,
+
+    /// Re-activate python virtualenv found in inherited environment
+    ///
+    /// Makes `bin` directory of `VIRTUAL_ENV` first in `PATH` of launched
+    /// program.
+    #[arg(long, default_value = "false")]
+    inherit_venv: bool,
+
+    /// Launch programs within transient systemd scopes
+    ///
+    /// Uses `systemd-run --user --scope`, falls back to direct launch if user
+    /// systemd instance is not available.
+    #[arg(long, default_value = "false")]
+    scope: bool,
+
+    /// Multiplier of textwidth of each vim column
+    ///
+    /// Overrides the calibrated value.
+    #[arg(long)]
+    column_width_koeff: Option<f64>,
+
+    /// Minimal width of vim window: pixels (`1200`) or percents of output
+    /// width (`50%`)
+    #[arg(long)]
+    min_width: Option<config::Width>,
+
+    /// Maximal width of vim window: pixels (`1200`) or percents of output
+    /// width (`100%`)
+    #[arg(long)]
+    max_width: Option<config::Width>,
+
+    /// Output format of informational commands and of reported errors
+    #[arg(long, value_enum, default_value_t = Format::Text)]
+    format: Format,
+
+    /// Loaded configuration
+    #[arg(skip)]
+    config: config::Config,
+
+    /// Launched programs are spawned instead of replacing this process
+    #[arg(skip)]
+    spawn: bool,
 }
 
 /// The list of supported commands
 #[derive(Subcommand, Debug, Clone)]
 #[command(about, long_about)]
 pub enum Command {
-    /// Check niri availability.
+    /// Check environment health.
     ///
-    /// Exits with success if niri is available and panics if niri is
-    /// unavailable.
+    /// Checks niri reachability, the base window (focused or selected with
+    /// `--window`), expansion of kitty socket template, remote control
+    /// sockets of all kitty windows and nvim sockets under the base window.
+    /// Prints pass/fail report of each check (as JSON with `--format json`)
+    /// and exits with success only if all checks passed. Exits with code 4 if
+    /// niri is unreachable and 3 if window with requested id does not exist.
     #[command(about, long_about)]
     Test,
 
+    /// Explain how launching data is inherited from the base window.
+    ///
+    /// Runs the same pipeline as launching commands and prints each decision:
+    /// base window, its class and pid, tried sockets, found cwd, and
+    /// suggestions of how to fix inheritance failures.
+    #[command(about, long_about)]
+    Doctor,
+
+    /// Print shell completions.
+    ///
+    /// Generates completion script for the given shell to stdout. Use it
+    /// like `niri-integration completions bash > niri-integration.bash`.
+    #[command(about, long_about)]
+    Completions {
+        /// Shell to generate completions for
+        shell: clap_complete::Shell,
+    },
+
+    /// Print kitty configuration required by this tool.
+    ///
+    /// Prints `allow_remote_control`, `listen_on` and `shell_integration`
+    /// lines of kitty.conf matching the configured `--kitty-socket`
+    /// template.
+    #[command(about, long_about)]
+    GenerateKittyConf,
+
+    /// Run several commands over the single niri connection.
+    ///
+    /// Each argument is a single command line like `switch left`. Without
+    /// arguments commands are read from stdin one per line, empty lines and
+    /// lines starting with `#` are skipped. Command lines are split by
+    /// whitespace. Execution stops on the first failed command. Connections
+    /// to kitty and nvim are reused by further commands, launched programs
+    /// do not replace this process.
+    #[command(about, long_about)]
+    Batch {
+        /// Command lines to run
+        commands: Vec<String>,
+    },
+
+    /// Run commands interactively.
+    ///
+    /// Reads command lines from stdin with the same grammar as `batch` and
+    /// executes them over the single niri connection. Failed commands are
+    /// reported and do not stop the session. Type `help` to list commands and
+    /// `exit` or Ctrl-D to quit. Like in `batch`, connections are reused and
+    /// launched programs do not replace this process.
+    #[command(about, long_about)]
+    Repl,
+
     /// Run new kitty instance.
     ///
     /// If current focused window have usable environment data (e.g. another kitty
@@ -90,24 +296,335 @@ pub enum Command {
     ///
     /// If current focused window have usable environment data (e.g. kitty
     /// window) - this will print environment to use with new window. Usable for development
-    /// purposes.
+    /// purposes. Values of variables which look like secrets are masked.
+    #[command(about, long_about)]
+    Env {
+        /// Print values of secret variables as is
+        #[arg(long, default_value = "false")]
+        show_secrets: bool,
+
+        /// Print only difference with the current environment
+        ///
+        /// New and changed variables are prefixed with `+`, removed and
+        /// previous values of changed variables are prefixed with `-`.
+        #[arg(long, default_value = "false")]
+        diff: bool,
+    },
+
+    /// Print waybar module JSON describing base window.
+    ///
+    /// Includes detected application, cwd, git branch and number of modified
+    /// vim buffers.
+    #[command(about, long_about)]
+    Status {
+        /// Keep printing status on each focus change, for waybar's continuous
+        /// mode
+        #[arg(long, default_value = "false")]
+        follow: bool,
+    },
+
+    /// Print working directory of base window.
+    ///
+    /// Only the directory is printed, so it may back shell function like
+    /// `cdw() { cd "$(niri-integration cwd)"; }`.
+    #[command(about, long_about)]
+    Cwd,
+
+    /// Open path with application chosen by its type.
+    ///
+    /// Relative path is resolved against cwd of focused window. Directories
+    /// are opened in kitty, text files in vim and everything else with
+    /// `xdg-open`. Applications inherit environment of focused window.
+    #[command(about, long_about)]
+    Open {
+        /// Path to open
+        path: PathBuf,
+    },
+
+    /// Open path from wayland clipboard.
+    ///
+    /// The clipboard is read with `wl-paste`. It may contain `file:line:col`
+    /// reference (e.g. from compiler output), then the file is opened in vim
+    /// at that position. Otherwise it works as `open` command.
     #[command(about, long_about)]
-    Env,
+    OpenClipboard {
+        /// Read primary selection instead of clipboard
+        #[arg(long, default_value = "false")]
+        primary: bool,
+    },
 
     /// Vim-related commands.
     #[command(subcommand, about, long_about)]
     Vim(Vim),
 
+    /// Switch focus to the window in direction.
+    Switch {
+        #[command(subcommand)]
+        direction: Direction,
+
+        /// Wrap to the opposite edge when there is no window in direction
+        /// instead of doing nothing or focusing another monitor
+        #[arg(long, default_value = "false", conflicts_with = "monitor")]
+        wrap: bool,
+
+        /// Focus monitor in direction skipping application's windows
+        #[arg(long, default_value = "false")]
+        monitor: bool,
+    },
+
+    /// Move focused window in direction.
+    Move {
+        #[command(subcommand)]
+        direction: Direction,
+
+        /// Move column to monitor in direction skipping application's windows
+        #[arg(long, default_value = "false")]
+        monitor: bool,
+    },
+
+    /// Resize focused window in direction.
+    ///
+    /// Within vim the current split is resized, otherwise the niri window.
+    /// Right and down grow the window, left and up shrink it.
+    #[command(about, long_about)]
+    Resize {
+        #[command(subcommand)]
+        direction: Direction,
+
+        /// Amount of change either in pixels (`50`) or in percents (`10%`)
+        #[arg(long, default_value = "5%")]
+        amount: config::Width,
+    },
+
+    /// Swap focused window with its neighbour in direction.
+    ///
+    /// Within vim the buffers of splits are exchanged, at the vim border (and
+    /// for other applications) niri windows are swapped.
+    #[command(subcommand, about, long_about)]
+    Swap(Direction),
+
+    /// Send keys to focused application.
+    ///
+    /// Vim receives keys in its notation (e.g. `<Esc>:w<CR>`), kitty receives
+    /// text as is.
+    #[command(about, long_about)]
+    Send {
+        /// Keys to send
+        keys: String,
+    },
+
+    /// Tab operations within focused application.
+    ///
+    /// Vim tabpages or kitty tabs are used. Otherwise new tab toggles tabbed
+    /// display of niri column, next and previous tabs are niri workspaces.
+    #[command(subcommand, about, long_about)]
+    Tab(Tab),
+
+    /// Toggle floating of focused window.
+    ///
+    /// The geometry of floating window is remembered and restored when the
+    /// window floats again. Neovide width is synchronised after the change.
+    #[command(about, long_about)]
+    Float,
+
+    /// Toggle fullscreen of focused window.
+    ///
+    /// Splits of neovide are fitted to the new window size.
+    #[command(about, long_about)]
+    Fullscreen,
+
+    /// Toggle zoom of focused window.
+    ///
+    /// Vim split is maximized within vim and its layout is restored on the
+    /// next zoom. Kitty toggles the stack layout. Otherwise niri column is
+    /// maximized.
+    #[command(about, long_about)]
+    Zoom,
+
+    /// Equalize sizes of splits within focused application.
+    ///
+    /// Vim splits are equalized with `<C-w>=`, kitty layout is reset.
+    /// Otherwise the height of niri window is reset.
+    #[command(about, long_about)]
+    Equalize,
+
+    #[command(about, long_about)]
+    Close {
+        /// Discard unsaved changes, close kitty window without confirmation
+        #[arg(long, default_value = "false", conflicts_with = "write")]
+        force: bool,
+
+        /// Save changes before closing vim window
+        #[arg(long, default_value = "false")]
+        write: bool,
+    },
+
+    /// Run daemon which applies automatic rules to niri windows.
+    ///
+    /// Daemon listens niri event stream. When kitty with a project cwd appears
+    /// while neovide of the same project exists on the workspace - the
+    /// terminal is moved into (or next to) editor's column depending on the
+    /// `pair` section of configuration. With `vim.follow` enabled the view is
+    /// shifted to the current vim split whenever neovide gets focus. With
+    /// `vim.fit` enabled vim splits are resized whenever niri changes width
+    /// of neovide. With `swallow.enable` kitty is hidden while GUI program
+    /// launched from it runs.
+    ///
+    /// The control socket passed by systemd socket activation is used if any,
+    /// so daemon may be started lazily on the first request.
+    #[command(about, long_about)]
+    Daemon,
+
+    /// Focus previously focused window, even on another workspace.
+    ///
+    /// Focus history is tracked by daemon, so it should be running.
+    #[command(about, long_about)]
+    FocusPrevious,
+
+    /// Walk through windows in most recently used order, like alt-tab.
+    ///
+    /// Consecutive invocations continue walking while the focus stays on the
+    /// window chosen by the previous one. Focus history is tracked by daemon.
+    #[command(about, long_about)]
+    Cycle {
+        #[command(subcommand)]
+        direction: CycleDirection,
+
+        /// Regular expression to cycle only through matching app ids
+        #[arg(long)]
+        app_id: Option<String>,
+    },
+
+    /// Named marks of niri windows.
     #[command(subcommand, about, long_about)]
-    Switch(Direction),
+    Mark(Mark),
 
+    /// Workspace-related commands.
     #[command(subcommand, about, long_about)]
-    Move(Direction),
+    Workspace(Workspace),
+
+    /// Print ids of niri windows matching process or directory.
+    ///
+    /// The window matches the pid if the process is the window's one or its
+    /// descendant. The window matches the cwd if any of its processes works
+    /// within this directory.
+    #[command(about, long_about)]
+    Which {
+        /// Pid of process to find window of
+        #[arg(long, conflicts_with = "cwd", required_unless_present = "cwd")]
+        pid: Option<i32>,
 
+        /// Working directory to find windows of
+        #[arg(long)]
+        cwd: Option<PathBuf>,
+    },
+
+    /// Focus window working in directory.
+    ///
+    /// Windows match the directory the same way as existing kitty is matched
+    /// by `kitty` command, see `launch.match-repo` of configuration. The
+    /// matching tab of kitty is focused too.
+    #[command(about, long_about)]
+    FocusCwd {
+        /// Directory to find window of
+        path: PathBuf,
+
+        /// Launch terminal in the directory if no window works there
+        #[arg(long, default_value = "false")]
+        launch: bool,
+    },
+
+    /// Set titles of kitty tabs from their cwd and git branch.
+    ///
+    /// Titles of all tabs of kitty of base window are set according to the
+    /// `title` section of configuration. Daemon does it whenever kitty gets
+    /// focus when `title.enable` is set.
     #[command(about, long_about)]
+    Retitle,
+}
+
+#[derive(Subcommand, Debug, Clone)]
+#[command(about, long_about)]
+pub enum Mark {
+    /// Mark focused window with name, replacing the previous owner of mark
+    Set {
+        /// Name of mark
+        name: String,
+    },
+    /// Focus window marked with name
+    Go {
+        /// Name of mark
+        name: String,
+    },
+    /// Remove mark
+    Unset {
+        /// Name of mark
+        name: String,
+    },
+    /// Print marks and their windows
+    List,
+}
+
+#[derive(Subcommand, Debug, Clone)]
+#[command(about, long_about)]
+pub enum CycleDirection {
+    /// Focus less recently used window
+    Next,
+    /// Focus more recently used window
+    Prev,
+}
+
+#[derive(Subcommand, Debug, Clone)]
+#[command(about, long_about)]
+pub enum Tab {
+    /// Open new tab
+    New,
+    /// Switch to the next tab
+    Next,
+    /// Switch to the previous tab
+    Prev,
+    /// Close current tab
     Close,
 }
 
+#[derive(Subcommand, Debug, Clone)]
+#[command(about, long_about)]
+pub enum Workspace {
+    /// Close scratch terminals on current workspace.
+    ///
+    /// Scratch terminal is a kitty with only idle shells working in home
+    /// directory.
+    Clean {
+        /// Minimal time in minutes since the last terminal activity
+        #[arg(long, default_value = "10")]
+        idle: u64,
+    },
+    /// Focus workspace by name (or index, if numeric)
+    Focus {
+        /// Name or index of workspace
+        reference: niri_ipc::WorkspaceReferenceArg,
+    },
+    /// Set name of the workspace
+    SetName {
+        /// New name of the workspace
+        name: String,
+
+        /// Name or index of workspace to rename instead of the focused one
+        #[arg(long)]
+        workspace: Option<niri_ipc::WorkspaceReferenceArg>,
+    },
+    /// Remove name of the workspace
+    UnsetName {
+        /// Name or index of workspace instead of the focused one
+        #[arg(long)]
+        workspace: Option<niri_ipc::WorkspaceReferenceArg>,
+    },
+    /// Focus previously focused workspace
+    Previous,
+    /// Print workspaces, useful for status bars with `--format json`
+    List,
+}
+
 #[derive(Subcommand, Debug, Clone, Default)]
 #[command(about, long_about)]
 pub enum Vim {
@@ -121,10 +638,97 @@ pub enum Vim {
     /// Synchronise vim window size and offset with its content
     ///
     /// This designed to be called automatically by vim itself
-    Sync,
+    Sync {
+        /// Synchronise window height with the number of vim lines too
+        ///
+        /// Useful for floating neovide windows and short scratch buffers.
+        #[arg(long, default_value = "false")]
+        height: bool,
+
+        /// Fit the widest of vim tabpages instead of the current one
+        #[arg(long, default_value = "false")]
+        all_tabs: bool,
+    },
 
     /// Shift vim window if it can not fit screen size
-    Shift,
+    Shift {
+        /// Animate the shift during given number of milliseconds
+        ///
+        /// Overrides `vim.animate` of configuration.
+        #[arg(long)]
+        animate: Option<u64>,
+    },
+
+    /// Open files in vim of base window.
+    ///
+    /// Files are opened in the detected neovide instance which is focused
+    /// then. If there is no instance - new neovide is launched with files.
+    Open {
+        /// Files to open
+        files: Vec<PathBuf>,
+    },
+
+    /// Focus vim which has the file opened.
+    ///
+    /// All neovide windows are inspected. The window showing the file becomes
+    /// focused both in vim and in niri.
+    Focus {
+        /// File to look for
+        file: PathBuf,
+    },
+
+    /// Resize vim splits to fit the current niri window width
+    ///
+    /// Columns get width proportional to their textwidth. This is designed to
+    /// be called by vim on `VimResized`.
+    Fit,
+
+    /// Switch vim window width to the next of configured presets
+    ///
+    /// Presets are configured in `vim.presets` section of configuration.
+    /// Preset `fit` is the width which fits content of vim.
+    CycleWidth,
+
+    /// Print computed columns of vim windows
+    ///
+    /// Prints start and end of each column, its textwidth and number of
+    /// windows in it. Usable for tuning width synchronisation.
+    Columns,
+
+    /// Print nvim instances reachable within base window
+    ///
+    /// Prints pid and socket of each instance, either is accepted by
+    /// `--instance`.
+    Instances,
+
+    /// Interactively calibrate width synchronisation
+    ///
+    /// Measures pixels-per-symbol of focused neovide and lets user adjust the
+    /// width until all splits fit. The result is stored in state file and used
+    /// by further invocations.
+    CalibrateWizard,
+
+    /// Print Lua plugin calling `vim sync` automatically
+    ///
+    /// The plugin sets up `WinResized`, `WinNew` and `WinClosed` autocmds of
+    /// neovide which invoke this binary with id of neovide window. It is not
+    /// needed when daemon runs with `vim.watch` enabled.
+    Plugin {
+        /// Install plugin to the path instead of printing, e.g.
+        /// `~/.config/nvim/plugin/niri-integration.lua`
+        #[arg(long)]
+        install: Option<PathBuf>,
+    },
+}
+
+/// Output format of informational commands
+#[derive(ValueEnum, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Format {
+    /// Human-readable text
+    #[default]
+    Text,
+    /// JSON
+    Json,
 }
 
 #[derive(Subcommand, Debug, Clone)]
@@ -134,6 +738,14 @@ pub enum Direction {
     Down,
     Left,
     Right,
+    /// The first window or column
+    First,
+    /// The last window or column
+    Last,
+    /// The next window in order, continues to the next column
+    Next,
+    /// The previous window in order, continues to the previous column
+    Prev,
 }
 
 pub struct NiriActionDirection {
@@ -141,6 +753,10 @@ pub struct NiriActionDirection {
     down: niri_ipc::Action,
     left: niri_ipc::Action,
     right: niri_ipc::Action,
+    first: niri_ipc::Action,
+    last: niri_ipc::Action,
+    next: niri_ipc::Action,
+    prev: niri_ipc::Action,
 }
 
 #[derive(Default)]
@@ -148,6 +764,7 @@ enum Application {
     #[default]
     None,
     Vim(vim::Vim),
+    LegacyVim(vimserver::VimServer),
     Kitty(kitty::KittySocket),
 }
 
@@ -156,11 +773,117 @@ struct LaunchingData {
     pub env: HashMap<String, String>,
     pub cwd: Option<String>,
     pub application: Application,
+    /// Destination of ssh session if the source is remote
+    pub ssh: Option<String>,
+    /// Container the source runs within
+    pub container: Option<container::Container>,
+    /// Git repository of cwd
+    pub git: Option<git::Repo>,
+    /// Pids of nvim processes in foreground of base window
+    pub foreground_nvim: Vec<i32>,
+    /// Pids of processes in foreground of base window if source knows them
+    pub foreground: Option<Vec<i32>>,
 }
 
 impl Launcher {
+    /// Output format chosen by user
+    pub fn format(&self) -> Format {
+        self.format
+    }
+
     /// Run chosen subcommand
-    pub fn run(self) -> Result<()> {
+    pub fn run(mut self) -> Result<()> {
+        logging::init(self.verbose, self.log_file.as_deref())?;
+        log::debug!("Running {:?}", self.command);
+        if let Command::Batch { .. } | Command::Repl = self.command {
+            // Further commands need this process and its connections
+            self.spawn = true;
+            kitty::keep_sessions();
+            vim::keep_sessions();
+        }
+        let result = self
+            .load_config()
+            .and_then(|()| self.resolve_window())
+            .and_then(|()| self.run_command());
+        if let Err(err) = &result {
+            log::error!("{err}");
+            if self.notify || self.config.notify {
+                self.notify_error(err);
+            }
+        }
+        result
+    }
+
+    /// Load configuration and merge command line options into it
+    fn load_config(&mut self) -> Result<()> {
+        let profile = self
+            .profile
+            .clone()
+            .or_else(|| std::env::var("NIRI_INTEGRATION_PROFILE").ok());
+        self.config = config::Config::load(
+            self.config_path.as_ref(),
+            profile.as_deref(),
+        )?;
+        self.config.env.refresh |= self.refresh_env;
+        self.config.env.venv |= self.inherit_venv;
+        self.config.launch.scope |= self.scope;
+        self.config.picker.enable |= self.pick_dir;
+        let vim = &mut self.config.vim;
+        vim.column_width_koeff =
+            self.column_width_koeff.or(vim.column_width_koeff);
+        vim.min_width = self.min_width.or(vim.min_width);
+        vim.max_width = self.max_width.or(vim.max_width);
+        self.merge_command_options();
+        if let Some(path) = &self.trace_ipc {
+            trace::enable(path, self.config.env.clone())?;
+        }
+        if self.dry_run {
+            dryrun::enable(self.config.env.clone());
+        }
+        Ok(())
+    }
+
+    /// Merge options of chosen subcommand into configuration
+    fn merge_command_options(&mut self) {
+        if let Command::Vim(Vim::Shift {
+            animate: Some(animate),
+        }) = self.command
+        {
+            self.config.vim.animate = animate;
+        }
+    }
+
+    /// Run commands which do not need niri
+    ///
+    /// Returns [None] if command needs niri.
+    fn run_offline(&self, command: &Command) -> Option<Result<()>> {
+        match command {
+            Command::Completions { shell } => {
+                let mut command = Self::command();
+                let name = command.get_name().to_string();
+                clap_complete::generate(
+                    *shell,
+                    &mut command,
+                    name,
+                    &mut io::stdout(),
+                );
+                Some(Ok(()))
+            }
+            Command::Vim(Vim::Plugin { install }) => {
+                Some(Self::vim_plugin(install.as_deref()))
+            }
+            Command::GenerateKittyConf => {
+                print!("{}", self.kitty_conf());
+                Some(Ok(()))
+            }
+            _ => None,
+        }
+    }
+
+    fn run_command(&self) -> Result<()> {
+        if let Some(result) = self.run_offline(&self.command) {
+            return result;
+        }
         if self.daemonize {
             use daemonize::Stdio;
             daemonize::Daemonize::new()
@@ -168,77 +891,342 @@ impl Launcher {
                 .stderr(Stdio::keep())
                 .start()?;
         }
-        let mut socket = if let Some(path) = self.path.as_ref() {
-            Socket::connect_to(path)?
-        } else {
-            Socket::connect()?
-        };
-        let data = self.get_launching_data(&mut socket);
-        match &self.command {
-            Command::Test => Ok(()),
-            Command::Kitty => self.run_kitty(data, &mut socket),
-            Command::Env => Self::print_env(data),
-            Command::Vim(Vim::Run) => Self::run_vim(data, &mut socket),
-            Command::Vim(Vim::Sync) => Self::sync_vim(data, &mut socket),
-            Command::Vim(Vim::Shift) => Self::shift_vim(data, &mut socket),
-            Command::Switch(direction) => {
-                Self::switch(data, &mut socket, &direction)
+        let mut socket = self.connect_niri()?;
+        self.execute(&self.command, &mut socket)
+    }
+
+    /// Execute command over established niri connection
+    ///
+    /// Short commands see [snapshot](niri::Snapshot) of niri state, commands
+    /// which run other commands or follow niri query it each time.
+    fn execute(&self, command: &Command, socket: &mut Socket) -> Result<()> {
+        if let Some(result) = self.run_offline(command) {
+            return result;
+        }
+        match command {
+            Command::Daemon
+            | Command::Batch { .. }
+            | Command::Repl
+            | Command::Status { follow: true } => {
+                self.execute_on(command, socket)
+            }
+            _ => self.execute_on(command, &mut niri::Snapshot::new(socket)),
+        }
+    }
+
+    fn execute_on(&self, command: &Command, socket: &mut Socket) -> Result<()> {
+        let data = self.get_launching_data(socket)?;
+        match command {
+            Command::Test => self.test(socket),
+            Command::Doctor => self.doctor(data, socket),
+            Command::Completions { .. }
+            | Command::Vim(Vim::Plugin { .. })
+            | Command::GenerateKittyConf => unreachable!(),
+            Command::Batch { commands } => self.batch(socket, commands),
+            Command::Repl => self.repl(socket),
+            Command::Kitty => {
+                let data = self.maybe_workspace_cwd(data, socket);
+                self.run_kitty(self.maybe_pick_cwd(data), socket)
+            }
+            Command::Open { path } => self.open(data, socket, path, None),
+            Command::OpenClipboard { primary } => {
+                self.open_clipboard(data, socket, *primary)
+            }
+            Command::Status { follow } => self.status(data, socket, *follow),
+            Command::Cwd => {
+                println!("{}", data.cwd.ok_or("Base window has no cwd")?);
+                Ok(())
+            }
+            Command::Env { show_secrets, diff } => {
+                self.print_env(data, *show_secrets, *diff)
+            }
+            Command::Vim(Vim::Run) => {
+                let data = self.maybe_workspace_cwd(data, socket);
+                self.run_vim(self.maybe_pick_cwd(data), socket)
+            }
+            Command::Vim(Vim::Sync { height, all_tabs }) => {
+                Self::sync_vim(data, socket, *height, *all_tabs)
             }
-            Command::Move(direction) => {
-                Self::move_window(data, &mut socket, &direction)
+            Command::Vim(Vim::Shift { .. }) => Self::shift_vim(data, socket),
+            Command::Vim(Vim::Open { files }) => {
+                self.open_vim(data, socket, files, None)
+            }
+            Command::Vim(Vim::Focus { file }) => self.focus_vim(socket, file),
+            Command::Vim(Vim::Fit) => Self::fit_vim(data),
+            Command::Vim(Vim::CycleWidth) => self.cycle_vim_width(data, socket),
+            Command::Vim(Vim::Columns) => self.print_vim_columns(data),
+            Command::Vim(Vim::Instances) => self.print_vim_instances(socket),
+            Command::Vim(Vim::CalibrateWizard) => {
+                Self::calibrate_vim(data, socket)
+            }
+            Command::Switch {
+                direction,
+                monitor: true,
+                ..
+            } => niri::action(
+                socket,
+                NiriActionDirection::new_focus_monitor().mk_action(direction),
+            ),
+            Command::Switch {
+                direction, wrap, ..
+            } => Self::switch(data, socket, direction, *wrap),
+            Command::Move {
+                direction,
+                monitor: true,
+            } => niri::action(
+                socket,
+                NiriActionDirection::new_move_monitor().mk_action(direction),
+            ),
+            Command::Move { direction, .. } => {
+                Self::move_window(data, socket, direction)
+            }
+            Command::Swap(direction) => Self::swap(data, socket, direction),
+            Command::Resize { direction, amount } => {
+                Self::resize(data, socket, direction, amount)
+            }
+            Command::Equalize => Self::equalize(data, socket),
+            Command::Zoom => Self::zoom(data, socket),
+            Command::Fullscreen => Self::fullscreen(data, socket),
+            Command::Float => self.float(data, socket),
+            Command::Tab(tab) => Self::tab(data, socket, tab),
+            Command::Send { keys } => Self::send_keys(data, keys),
+            Command::Close { force, write } => {
+                self.close(data, socket, *force, *write)
+            }
+            Command::Daemon => daemon::Daemon::new(self).run(socket),
+            Command::FocusPrevious => Self::focus_previous(socket),
+            Command::Mark(mark) => self.mark(socket, mark),
+            Command::Cycle { direction, app_id } => {
+                Self::cycle(socket, direction, app_id.as_deref())
+            }
+            Command::Workspace(Workspace::Clean { idle }) => {
+                self.clean_workspace(socket, *idle)
+            }
+            Command::Workspace(Workspace::List) => {
+                self.print_workspaces(socket)
+            }
+            Command::Workspace(workspace) => {
+                niri::action(socket, workspace.niri_action()?)
+            }
+            Command::Which { pid, cwd } => {
+                self.which(socket, *pid, cwd.as_deref())
+            }
+            Command::Retitle => self.retitle(socket),
+            Command::FocusCwd { path, launch } => {
+                self.focus_cwd(socket, path, *launch)
             }
-            Command::Close => Self::close(data, &mut socket),
         }
     }
 
-    fn get_kitty_socket(&self, pid: i32) -> Result<kitty::KittySocket> {
+    /// Show desktop notification about failure of command
+    fn notify_error(&self, err: &error::Error) {
+        let command = format!("{:?}", self.command);
+        let command = command
+            .split([' ', '(', '{'])
+            .next()
+            .unwrap_or_default()
+            .to_lowercase();
+        // Failed notification must not hide the original error
+        let _ = std::process::Command::new("notify-send")
+            .args(["--app-name=niri-integration", "--urgency=critical"])
+            .arg(format!("niri-integration {command} failed"))
+            .arg(err.to_string())
+            .status();
+    }
+
+    /// Run command received by daemon over control socket
+    ///
+    /// Global options and configuration of daemon are kept, only the base
+    /// window, nvim instance and command are taken from request.
+    fn handle_control(&self, request: control::Request) -> control::Reply {
+        let args =
+            std::iter::once("niri-integration".to_string()).chain(request.args);
+        let request = match Self::try_parse_from(args) {
+            Ok(request) => request,
+            Err(err) => {
+                return control::Reply::from(Err(err.to_string().into()));
+            }
+        };
+        let mut launcher = self.clone();
+        launcher.command = request.command;
+        launcher.window = request.window;
+        launcher.instance = request.instance;
+        launcher.fresh = request.fresh;
+        launcher.format = request.format;
+        launcher.daemonize = request.daemonize;
+        launcher.merge_command_options();
+        // Only short non-interactive commands may run within daemon
+        let remote = !launcher.daemonize
+            && matches!(
+                launcher.command,
+                Command::Vim(Vim::Sync { .. })
+                    | Command::Vim(Vim::Shift { .. })
+                    | Command::Vim(Vim::Fit)
+                    | Command::Vim(Vim::CycleWidth)
+                    | Command::Switch { .. }
+                    | Command::Move { .. }
+                    | Command::Close { .. }
+                    | Command::Equalize
+                    | Command::Zoom
+                    | Command::Fullscreen
+                    | Command::Float
+                    | Command::Tab(_)
+                    | Command::Send { .. }
+                    | Command::Swap(_)
+                    | Command::Resize { .. }
+                    | Command::Workspace(_)
+                    | Command::FocusPrevious
+                    | Command::Cycle { .. }
+                    | Command::Mark(_)
+                    | Command::Retitle
+            );
+        if remote {
+            let result = launcher
+                .resolve_window()
+                .and_then(|()| launcher.run_command());
+            if let Err(err) = &result {
+                log::error!("{err}");
+            }
+            control::Reply::from(result)
+        } else {
+            control::Reply::from(Err("Command can not run remotely".into()))
+        }
+    }
+
+    fn connect_niri(&self) -> Result<niri_ipc::socket::Socket> {
+        use niri_ipc::socket::Socket;
+        if let Some(path) = self.path.as_ref() {
+            Socket::connect_to(path)
+                .map_err(Error::NiriUnreachable)
+                .context(|| Context::new("connect").path(path))
+        } else {
+            Socket::connect().map_err(Error::NiriUnreachable)
+        }
+    }
+
+    /// Template of kitty socket
+    fn kitty_socket(&self) -> &str {
+        self.kitty_socket
+            .as_deref()
+            .or(self.config.kitty_socket.as_deref())
+            .unwrap_or("${XDG_RUNTIME_DIR}/kitty-{pid}")
+    }
+
+    /// Expand kitty socket template for kitty process `pid`
+    fn kitty_socket_path(&self, pid: i32) -> PathBuf {
         let pidre = regex::Regex::new(r"\{pid\}").unwrap();
         let envre = regex::Regex::new(r"\$\{([^\{\}\s]*)\}").unwrap();
 
         let path =
-            envre.replace_all(&self.kitty_socket, |caps: &regex::Captures| {
+            envre.replace_all(self.kitty_socket(), |caps: &regex::Captures| {
                 let var = std::env::var_os(&caps[1].to_string())
                     .unwrap_or(OsString::from(""));
                 String::from(var.to_str().unwrap())
             });
 
         let path = pidre.replace_all(&path, format!("{pid}"));
+        PathBuf::from(path.to_string())
+    }
 
-        Ok(kitty::KittySocket::connect(PathBuf::from(
-            path.to_string(),
-        ))?)
+    fn get_kitty_socket(&self, pid: i32) -> Result<kitty::KittySocket> {
+        let path = self.kitty_socket_path(pid);
+        log::debug!("Connecting to kitty socket {}", path.display());
+        kitty::KittySocket::connect(&path)
+            .map_err(Error::Kitty)
+            .context(|| Context::new("connect").path(path))
     }
 
     fn get_launching_data_no_default(
         &self,
         socket: &mut Socket,
     ) -> Result<LaunchingData> {
-        let window = self.get_base_window(socket).ok_or(io::Error::new(
-            io::ErrorKind::NotFound,
-            "No focused niri window",
-        ))?;
+        let window = self
+            .get_base_window(socket)?
+            .ok_or(Error::NoFocusedWindow)?;
         let class = window.app_id.as_ref().ok_or(io::Error::new(
             io::ErrorKind::NotFound,
             "Focused niri window does not have class",
         ))?;
-        if class == "kitty" {
-            self.get_launching_data_from_kitty(&window)
-        } else if class == "neovide" {
-            self.get_launching_data_from_vim(window)
-        } else {
-            Err(io::Error::new(
-                io::ErrorKind::Unsupported,
-                format!("Can not get launching data from {class}"),
-            ))?
+        let source = source::select(&self.config.sources, &window)?;
+        log::debug!(
+            "Base window {} of {class}, source {}",
+            window.id,
+            source.name()
+        );
+        let id = window.id;
+        let mut data = source.extract(self, &window).context(|| {
+            Context::new(format!("{} source", source.name())).window(id)
+        })?;
+        let extractor = source::extractor(&self.config.sources, &window)?;
+        if let Some(command) = extractor {
+            let context = || Context::new("external extractor").window(id);
+            data = source::run_extractor(command, &window, data)
+                .context(context)?;
         }
+        if !source.may_host_vim() {
+            return Ok(data);
+        }
+        // Neovim may run within terminal, it is driven when in foreground
+        if self.instance.is_some() || !data.foreground_nvim.is_empty() {
+            let choice = self.vim_choice(data.foreground_nvim.clone());
+            match vim::Vim::new(window.clone(), &choice) {
+                Ok(vim) => return Ok(data.set_vim(self.configure_vim(vim)?)),
+                Err(err) => log::debug!("No nvim to drive in {id}: {err}"),
+            }
+        }
+        // Classic vim may run within terminal or as gui
+        let server = match (&data.foreground, window.pid) {
+            (Some(pids), _) => vimserver::VimServer::discover_among(pids),
+            (None, Some(pid)) => vimserver::VimServer::discover(pid),
+            (None, None) => Ok(None),
+        };
+        match server {
+            Ok(Some(server)) => Ok(data.set_legacy_vim(server)),
+            _ => Ok(data),
+        }
+    }
+
+    /// Last-resort way to get launching data from unknown application
+    ///
+    /// Takes environment and cwd of the deepest shell-like descendant of the
+    /// window's process or of the process itself.
+    fn get_launching_data_from_proc(
+        &self,
+        window: &niri_ipc::Window,
+    ) -> Result<LaunchingData> {
+        let tree = pstree::build_process_tree(Some(window.pid.ok_or(
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                "Focused niri window does not have pid",
+            ),
+        )?))?;
+        let pid = tree.deepest_shell().unwrap_or(&tree.root).record.pid;
+        Ok(LaunchingData::default()
+            .maybe_cwd(pstree::cwd(pid).ok())
+            .set_envs(pstree::environ(pid)?.into_iter())
+            .maybe_container(container::Container::detect(pid)))
     }
 
-    fn get_launching_data(&self, socket: &mut Socket) -> LaunchingData {
+    fn get_launching_data(&self, socket: &mut Socket) -> Result<LaunchingData> {
         if self.fresh {
-            LaunchingData::default()
+            return Ok(LaunchingData::default());
+        }
+        let data = match self.get_launching_data_no_default(socket) {
+            Ok(data) => data,
+            Err(err) if matches!(err.root(), Error::WindowNotFound(_)) => {
+                return Err(err);
+            }
+            Err(err) => {
+                log::info!("No launching data, using default: {err}");
+                LaunchingData::default()
+            }
+        }
+        .filter_envs(|name| self.config.env.is_allowed(name))
+        .detect_git();
+        if self.config.env.venv {
+            Ok(data.activate_venv())
         } else {
-            self.get_launching_data_no_default(socket)
-                .unwrap_or(LaunchingData::default())
+            Ok(data)
         }
     }
 
@@ -253,13 +1241,35 @@ impl Launcher {
         let mut kitty = self.get_kitty_socket(pid)?;
         let r = kitty::Command::Ls(kitty::Ls::default());
         let r = kitty.request(r)?;
-        let windows: Vec<kitty::OsWindow> = serde_json::from_value(r).unwrap();
-        let window = Self::find_kitty_focused_window(windows).ok_or(
-            io::Error::new(io::ErrorKind::NotFound, "No focused kitty window"),
-        )?;
+        let windows: Vec<kitty::OsWindow> = serde_json::from_value(r)?;
+        let window = Self::find_kitty_os_window(windows, niri_window)
+            .and_then(|os_window| os_window.active_window().cloned())
+            .ok_or(io::Error::new(
+                io::ErrorKind::NotFound,
+                "No active kitty window",
+            ))?;
+        let ssh = window.ssh_destination();
+        let container = window
+            .foreground_processes
+            .iter()
+            .find_map(|process| container::Container::detect(process.pid));
+        let foreground: Vec<i32> = window
+            .foreground_processes
+            .iter()
+            .map(|process| process.pid)
+            .collect();
+        let nvim = foreground
+            .iter()
+            .copied()
+            .filter(|pid| pstree::comm(*pid).is_ok_and(|comm| comm == "nvim"))
+            .collect();
         Ok(LaunchingData::default()
-            .maybe_cwd(window.cwd.to_str())
+            .maybe_cwd(window.effective_cwd().to_str())
             .set_envs(window.env.into_iter())
+            .maybe_ssh(ssh)
+            .maybe_container(container)
+            .set_foreground_nvim(nvim)
+            .set_foreground(foreground)
             .set_kitty(kitty))
     }
 
@@ -267,63 +1277,393 @@ impl Launcher {
         &self,
         window: niri_ipc::Window,
     ) -> Result<LaunchingData> {
-        let mut vim = vim::Vim::new(window)?;
+        let choice = self.vim_choice(Vec::new());
+        let mut vim = self.configure_vim(vim::Vim::new(window, &choice)?)?;
         let pid = vim.get_pid()?;
-        let environ = File::open(format!("/proc/{pid}/environ"))?;
-        let lines = io::BufReader::new(environ).split(0x0);
-        let launching_data =
-            lines.fold(LaunchingData::default(), |launching_data, line| {
-                if let Ok(line) = line {
-                    if let Ok(line) = str::from_utf8(&line) {
-                        if let Some((k, v)) = line.split_once("=") {
-                            launching_data.add_env(k, v)
-                        } else {
-                            launching_data
-                        }
-                    } else {
-                        launching_data
-                    }
-                } else {
-                    launching_data
-                }
-            });
+        let launching_data = LaunchingData::default()
+            .set_envs(pstree::environ(pid)?.into_iter());
         Ok(launching_data.maybe_cwd(vim.get_cwd().ok()).set_vim(vim))
     }
 
+    /// Choice of nvim instance among `foreground` ones or by `--instance`
+    fn vim_choice(&self, foreground: Vec<i32>) -> vim::Choice {
+        vim::Choice {
+            selector: self.instance.clone(),
+            foreground,
+        }
+    }
+
+    /// Apply configuration and calibration to vim
+    fn configure_vim(&self, mut vim: vim::Vim) -> Result<vim::Vim> {
+        if let Some(calibration) = state::State::load()?.calibration {
+            vim.set_calibration(&calibration);
+        }
+        if let Some(koeff) = self.config.vim.column_width_koeff {
+            vim.set_column_width_koeff(koeff);
+        }
+        vim.set_width_limits(
+            self.config.vim.min_width,
+            self.config.vim.max_width,
+        );
+        vim.set_filetype_widths(self.config.vim.filetype_widths.clone());
+        vim.set_animation(Duration::from_millis(self.config.vim.animate));
+        vim.set_paddings(self.config.vim.gaps, self.config.vim.border);
+        Ok(vim)
+    }
+
     fn run_kitty(&self, data: LaunchingData, soc: &mut Socket) -> Result<()> {
+        if let (Some(ssh), true) = (&data.ssh, self.config.ssh.enable) {
+            let mut proc = Self::templated_command(
+                &self.config.launch.terminal,
+                None,
+                &data.env,
+            )?;
+            proc.args(self.config.ssh.expand(ssh, data.cwd.as_deref()));
+            return self.launch(proc, soc);
+        }
         if let Some(window) = self.find_kitty_for(&data, soc).unwrap_or(None) {
-            soc.send(niri_ipc::Request::Action(
-                niri_ipc::Action::FocusWindow { id: window.id },
-            ))??;
+            niri::action(soc, niri_ipc::Action::FocusWindow { id: window.id })?;
         } else {
-            let mut proc = std::process::Command::new("kitty");
-
-            data.env.into_iter().fold(&mut proc, |proc, (name, val)| {
-                proc.arg("-o").arg(format!("env={name}={val}"))
-            });
-
-            data.cwd.map(|workdir| {
-                proc.arg("-d").arg(format!("{}", workdir));
-            });
+            let mut proc = Self::templated_command(
+                &self.config.launch.terminal,
+                data.cwd.as_deref(),
+                &data.env,
+            )?;
+
+            if let (Some(container), true) =
+                (&data.container, self.config.container.enable)
+            {
+                proc.args(container.enter_command());
+            } else if let Some(wrapper) = self.get_env_wrapper(&data) {
+                let shell = data
+                    .env
+                    .get("SHELL")
+                    .cloned()
+                    .or_else(|| std::env::var("SHELL").ok())
+                    .unwrap_or_else(|| "sh".into());
+                proc.args(wrapper).arg(shell);
+            }
 
-            Err(proc.exec())?;
+            self.launch(proc, soc)?;
         }
         Ok(())
     }
 
-    fn find_kitty_for(
-        &self,
-        data: &LaunchingData,
-        soc: &mut Socket,
-    ) -> Result<Option<niri_ipc::Window>> {
-        if let Application::Kitty(_) = data.application {
-            return Ok(None);
+    /// Build command of terminal or editor from template
+    fn templated_command(
+        template: &[String],
+        cwd: Option<&str>,
+        env: &HashMap<String, String>,
+    ) -> Result<std::process::Command> {
+        let (args, env_passed) =
+            config::LaunchConfig::expand(template, cwd, env);
+        let (program, args) =
+            args.split_first().ok_or("Empty command template")?;
+        let mut proc = std::process::Command::new(program);
+        proc.args(args);
+        if !env_passed {
+            proc.envs(env);
         }
+        Ok(proc)
+    }
 
-        let ws = soc.send(niri_ipc::Request::Workspaces)??;
-        let ws = match ws {
-            niri_ipc::Response::Workspaces(ws) => Some(ws),
-            _ => None,
+    fn open(
+        &self,
+        mut data: LaunchingData,
+        soc: &mut Socket,
+        path: &Path,
+        position: Option<(i64, i64)>,
+    ) -> Result<()> {
+        let path = match &data.cwd {
+            Some(cwd) => Path::new(cwd).join(path),
+            None => std::path::absolute(path)?,
+        };
+        if path.is_dir() {
+            data.cwd = Some(path.to_string_lossy().into_owned());
+            self.run_kitty(data, soc)
+        } else if !path.exists() || Self::is_text_file(&path) {
+            self.open_vim(data, soc, &[path], position)
+        } else {
+            let mut proc = std::process::Command::new("xdg-open");
+            data.env
+                .iter()
+                .fold(&mut proc, |proc, (name, val)| proc.env(name, val));
+            data.cwd.map(|workdir| {
+                proc.current_dir(workdir);
+            });
+            proc.arg(path);
+            self.launch(proc, soc)
+        }
+    }
+
+    fn open_clipboard(
+        &self,
+        data: LaunchingData,
+        soc: &mut Socket,
+        primary: bool,
+    ) -> Result<()> {
+        let mut proc = std::process::Command::new("wl-paste");
+        proc.arg("--no-newline");
+        if primary {
+            proc.arg("--primary");
+        }
+        let output = exec::spawn(proc.stdout(std::process::Stdio::piped()))?
+            .wait_with_output()?;
+        if !output.status.success() {
+            Err("Can not read clipboard")?
+        }
+        let text = String::from_utf8_lossy(&output.stdout);
+        let (path, position) =
+            Self::parse_file_reference(&text).ok_or("No path in clipboard")?;
+        self.open(data, soc, &path, position)
+    }
+
+    /// Parse `path[:line[:column]]` reference, `file://` urls are accepted
+    fn parse_file_reference(
+        text: &str,
+    ) -> Option<(PathBuf, Option<(i64, i64)>)> {
+        let text = text.lines().next()?.trim();
+        let text = text.strip_prefix("file://").unwrap_or(text);
+        if text.is_empty() {
+            return None;
+        }
+        // Existing file may have colons in name
+        if Path::new(text).exists() {
+            return Some((text.into(), None));
+        }
+        let re = regex::Regex::new(r"^(.+?):(\d+)(?::(\d+))?:?").ok()?;
+        match re.captures(text) {
+            Some(caps) => {
+                let line = caps[2].parse().ok()?;
+                let column = caps
+                    .get(3)
+                    .and_then(|column| column.as_str().parse().ok())
+                    .unwrap_or(1);
+                Some((caps[1].into(), Some((line, column))))
+            }
+            None => Some((text.into(), None)),
+        }
+    }
+
+    /// Whenever the file looks like text: its beginning is a valid UTF-8
+    /// without NUL bytes
+    fn is_text_file(path: &Path) -> bool {
+        use std::io::Read;
+        let mut head = Vec::new();
+        let read = std::fs::File::open(path)
+            .and_then(|file| file.take(8192).read_to_end(&mut head));
+        if read.is_err() || head.contains(&0) {
+            return false;
+        }
+        match std::str::from_utf8(&head) {
+            Ok(_) => true,
+            // The last character may be cut by the read limit
+            Err(err) => err.error_len().is_none(),
+        }
+    }
+
+    /// Use directory configured for focused workspace if there is no cwd
+    fn maybe_workspace_cwd(
+        &self,
+        data: LaunchingData,
+        soc: &mut Socket,
+    ) -> LaunchingData {
+        if data.cwd.is_some() || self.config.workspace_dirs.is_empty() {
+            return data;
+        }
+        let workspaces = match niri::workspaces(soc) {
+            Ok(workspaces) => workspaces,
+            Err(err) => {
+                log::debug!("No workspaces to get directory of: {err}");
+                return data;
+            }
+        };
+        let dir = workspaces
+            .iter()
+            .find(|workspace| workspace.is_focused)
+            .and_then(|workspace| self.config.workspace_dir(workspace));
+        match dir {
+            Some(dir) if dir.is_dir() => {
+                log::debug!("Using directory of workspace {}", dir.display());
+                data.maybe_cwd(dir.to_str()).detect_git()
+            }
+            Some(dir) => {
+                log::warn!("Workspace directory {} is missing", dir.display());
+                data
+            }
+            None => data,
+        }
+    }
+
+    /// Let user pick cwd if there is none and picker is enabled
+    ///
+    /// Cancelled or failed picking keeps the data as is.
+    fn maybe_pick_cwd(&self, data: LaunchingData) -> LaunchingData {
+        if data.cwd.is_some() || !self.config.picker.enable {
+            return data;
+        }
+        match self.pick_directory() {
+            Ok(Some(dir)) => data.maybe_cwd(Some(dir)),
+            _ => data,
+        }
+    }
+
+    /// Pick directory among zoxide ones with configured picker
+    fn pick_directory(&self) -> Result<Option<String>> {
+        use std::process::Stdio;
+        let zoxide = exec::spawn(
+            std::process::Command::new("zoxide")
+                .args(["query", "-l"])
+                .stdout(Stdio::piped()),
+        )?
+        .wait_with_output()?;
+        self.run_picker(&zoxide.stdout)
+    }
+
+    /// Let user choose one of lines with configured picker
+    fn run_picker(&self, choices: &[u8]) -> Result<Option<String>> {
+        use std::io::Write;
+        use std::process::Stdio;
+        let (program, args) = self
+            .config
+            .picker
+            .command
+            .split_first()
+            .ok_or("Picker command is empty")?;
+        let mut picker = exec::spawn(
+            std::process::Command::new(program)
+                .args(args)
+                .stdin(Stdio::piped())
+                .stdout(Stdio::piped()),
+        )?;
+        // Dropping stdin closes it, so picker sees the end of choices
+        picker
+            .stdin
+            .take()
+            .ok_or("Picker has no stdin")?
+            .write_all(choices)?;
+        let output = picker.wait_with_output()?;
+        let choice = String::from_utf8_lossy(&output.stdout).trim().to_string();
+        Ok((output.status.success() && !choice.is_empty()).then_some(choice))
+    }
+
+    /// Let user pick base window
+    ///
+    /// Niri interactive selection is used if available, otherwise windows are
+    /// listed in configured picker.
+    fn pick_window(&self, soc: &mut Socket) -> Result<u64> {
+        match niri::pick_window(soc) {
+            Ok(window) => Ok(window.ok_or("Window picking cancelled")?.id),
+            Err(err) => {
+                log::info!("Niri can not pick window, using picker: {err}");
+                let choices: String = niri::windows(soc)?
+                    .iter()
+                    .map(|window| {
+                        format!(
+                            "{}\t{}\t{}\n",
+                            window.id,
+                            window.app_id.as_deref().unwrap_or_default(),
+                            window.title.as_deref().unwrap_or_default()
+                        )
+                    })
+                    .collect();
+                let choice = self
+                    .run_picker(choices.as_bytes())?
+                    .ok_or("Window picking cancelled")?;
+                let id = choice.split('\t').next().unwrap_or_default();
+                Ok(id.parse().map_err(|_| format!("Invalid window '{id}'"))?)
+            }
+        }
+    }
+
+    /// Resolve interactive `--window` selectors once before running command
+    fn resolve_window(&mut self) -> Result<()> {
+        if let Some(WindowSelector::Pick) = self.window {
+            let mut socket = self.connect_niri()?;
+            let id = self.pick_window(&mut socket)?;
+            self.window = Some(WindowSelector::Id(id));
+        }
+        Ok(())
+    }
+
+    /// Launch program replacing this process
+    ///
+    /// When any placement rule may apply to the window of program, it is
+    /// spawned instead and its window is placed once shown.
+    fn launch(
+        &self,
+        mut proc: std::process::Command,
+        soc: &mut Socket,
+    ) -> Result<()> {
+        exec::pass_activation_token(&mut proc);
+        let mut proc = if self.config.launch.scope {
+            exec::scoped(proc, &self.config.launch.slice)
+        } else {
+            proc
+        };
+        if dryrun::skip_launch(&proc) {
+            return Ok(());
+        }
+        let origin = self.placement_origin(soc);
+        let rules = origin
+            .as_ref()
+            .map(|origin| origin.candidates(&self.config.placement))
+            .unwrap_or_default();
+        let Some(origin) = origin.filter(|_| !rules.is_empty()) else {
+            if self.spawn {
+                exec::spawn(&mut proc)?;
+                return Ok(());
+            }
+            return exec::exec(&mut proc);
+        };
+        exec::spawn(&mut proc)?;
+        // The program is already running, failed placement is not fatal
+        if let Err(err) = origin.place(soc, &rules) {
+            log::warn!("Can not place launched window: {err}");
+        }
+        Ok(())
+    }
+
+    /// State of niri launch is made from, if there are placement rules
+    fn placement_origin(&self, soc: &mut Socket) -> Option<placement::Origin> {
+        if self.config.placement.is_empty() {
+            return None;
+        }
+        let base = self.get_base_window(soc).ok().flatten();
+        let source = base
+            .as_ref()
+            .and_then(|base| source::select(&self.config.sources, base).ok())
+            .map(|source| source.name());
+        match placement::Origin::new(soc, base, source) {
+            Ok(origin) => Some(origin),
+            Err(err) => {
+                log::warn!("Can not get origin of launch: {err}");
+                None
+            }
+        }
+    }
+
+    fn get_env_wrapper(&self, data: &LaunchingData) -> Option<Vec<String>> {
+        if self.config.env.refresh {
+            devenv::wrapper(&data.env, data.cwd.as_deref())
+        } else {
+            None
+        }
+    }
+
+    fn find_kitty_for(
+        &self,
+        data: &LaunchingData,
+        soc: &mut Socket,
+    ) -> Result<Option<niri_ipc::Window>> {
+        if let Application::Kitty(_) = data.application {
+            return Ok(None);
+        }
+
+        let ws = niri::request(soc, niri_ipc::Request::Workspaces)?;
+        let ws = match ws {
+            niri_ipc::Response::Workspaces(ws) => Some(ws),
+            _ => None,
         };
 
         if ws.is_none() {
@@ -344,7 +1684,7 @@ impl Launcher {
         }
         let ws = ws.unwrap().id;
 
-        let wins = soc.send(niri_ipc::Request::Windows)??;
+        let wins = niri::request(soc, niri_ipc::Request::Windows)?;
         let wins = match wins {
             niri_ipc::Response::Windows(wins) => Some(wins),
             _ => None,
@@ -369,174 +1709,1594 @@ impl Launcher {
         Ok(win)
     }
 
+    fn get_kitty_windows(&self, pid: i32) -> Result<Vec<kitty::OsWindow>> {
+        let mut kitty = self.get_kitty_socket(pid)?;
+        let r = kitty.request(kitty::Command::Ls(kitty::Ls::default()))?;
+        Ok(serde_json::from_value(r)?)
+    }
+
+    fn is_kitty_scratch(&self, pid: i32, idle: Duration) -> Result<bool> {
+        let home = std::env::var("HOME").map_err(|e| e.to_string())?;
+        let windows = self.get_kitty_windows(pid)?;
+        Ok(windows
+            .iter()
+            .flat_map(|os_window| os_window.tabs.iter())
+            .flat_map(|tab| tab.windows.iter())
+            .all(|window| {
+                window.effective_cwd() == Path::new(&home)
+                    && !window.foreground_processes.is_empty()
+                    && window.foreground_processes.iter().all(|process| {
+                        pstree::is_shell(process.pid)
+                            && pstree::tty_idle(process.pid)
+                                .map(|time| time >= idle)
+                                .unwrap_or(false)
+                    })
+            }))
+    }
+
+    fn status(
+        &self,
+        data: LaunchingData,
+        soc: &mut Socket,
+        follow: bool,
+    ) -> Result<()> {
+        self.print_status(data, soc)?;
+        if !follow {
+            return Ok(());
+        }
+        let mut events = self.connect_niri()?;
+        events.send(Request::EventStream)??;
+        let mut read_event = events.read_events();
+        loop {
+            let changed = match read_event()? {
+                niri_ipc::Event::WindowFocusChanged { .. } => true,
+                niri_ipc::Event::WindowOpenedOrChanged { window } => {
+                    window.is_focused
+                }
+                _ => false,
+            };
+            if changed {
+                let data = self.get_launching_data(soc)?;
+                self.print_status(data, soc)?;
+            }
+        }
+    }
+
+    fn print_status(
+        &self,
+        mut data: LaunchingData,
+        soc: &mut Socket,
+    ) -> Result<()> {
+        let app = match data.application {
+            Application::Vim(_) | Application::LegacyVim(_) => "vim".into(),
+            Application::Kitty(_) => "kitty".into(),
+            Application::None => self
+                .get_base_window(soc)?
+                .and_then(|window| window.app_id)
+                .unwrap_or_default(),
+        };
+        let modified = match data.get_vim() {
+            Some(vim) => vim.modified_buffers().unwrap_or(0),
+            None => 0,
+        };
+        let mut text = app.clone();
+        let mut tooltip = vec![];
+        let mut class = vec![app];
+        if let Some(cwd) = &data.cwd {
+            let dir = Path::new(cwd).file_name().unwrap_or_default();
+            text += &format!(" {}", dir.to_string_lossy());
+            tooltip.push(format!("cwd: {cwd}"));
+        }
+        if let Some(repo) = &data.git {
+            tooltip.push(format!("repo: {}", repo.root.display()));
+            if let Some(branch) = &repo.branch {
+                text += &format!(" ({branch})");
+                tooltip.push(format!("branch: {branch}"));
+            }
+        }
+        if modified > 0 {
+            text += &format!(" [+{modified}]");
+            tooltip.push(format!("modified buffers: {modified}"));
+            class.push("modified".into());
+        }
+        let status = serde_json::json!({
+            "text": text.trim(),
+            "tooltip": tooltip.join("\n"),
+            "class": class,
+        });
+        println!("{status}");
+        Ok(())
+    }
+
+    fn focus_previous(soc: &mut Socket) -> Result<()> {
+        let history = state::State::load()?.focus_history;
+        let focused = niri::focused_window(soc)?.map(|win| win.id);
+        let windows: Vec<u64> =
+            niri::windows(soc)?.iter().map(|win| win.id).collect();
+        let id = history
+            .into_iter()
+            .find(|id| Some(*id) != focused && windows.contains(id))
+            .ok_or("No previously focused window")?;
+        niri::action(soc, niri_ipc::Action::FocusWindow { id })
+    }
+
+    fn cycle(
+        soc: &mut Socket,
+        direction: &CycleDirection,
+        app_id: Option<&str>,
+    ) -> Result<()> {
+        // Saved before focusing: daemon updates the state on focus change
+        let id = state::State::update(|state| {
+            let focused = niri::focused_window(soc)?.map(|win| win.id);
+            let re = app_id.map(regex::Regex::new).transpose()?;
+            let windows = niri::windows(soc)?;
+            let cycle = &mut state.cycle;
+            let continued = focused.is_some()
+                && cycle.windows.get(cycle.position).copied() == focused
+                && cycle.app_id.as_deref() == app_id;
+            if !continued {
+                // Windows never focused while daemon runs are the least recent
+                let mut ids = state.focus_history.clone();
+                ids.extend(windows.iter().map(|win| win.id));
+                let mut seen = HashSet::new();
+                ids.retain(|id| Some(*id) != focused && seen.insert(*id));
+                ids.splice(0..0, focused);
+                *cycle = state::Cycle {
+                    windows: ids,
+                    position: 0,
+                    app_id: app_id.map(String::from),
+                };
+            }
+            let matches = |id: &u64| {
+                Some(*id) == focused
+                    || windows.iter().any(|win| {
+                        win.id == *id
+                            && re.as_ref().is_none_or(|re| {
+                                win.app_id
+                                    .as_deref()
+                                    .is_some_and(|a| re.is_match(a))
+                            })
+                    })
+            };
+            cycle.windows.retain(matches);
+            let len = cycle.windows.len();
+            if len < 2 {
+                Err("No other window to cycle to")?
+            }
+            let position = focused
+                .and_then(|id| cycle.windows.iter().position(|win| *win == id))
+                .unwrap_or(len - 1);
+            cycle.position = match direction {
+                CycleDirection::Next => (position + 1) % len,
+                CycleDirection::Prev => (position + len - 1) % len,
+            };
+            Ok(cycle.windows[cycle.position])
+        })?;
+        niri::action(soc, niri_ipc::Action::FocusWindow { id })
+    }
+
+    fn mark(&self, soc: &mut Socket, mark: &Mark) -> Result<()> {
+        // Marks of windows closed while daemon was not running are stale
+        let windows = niri::windows(soc)?;
+        let alive = |id: &u64| windows.iter().any(|win| win.id == *id);
+        match mark {
+            Mark::Set { name } => {
+                let window =
+                    niri::focused_window(soc)?.ok_or(Error::NoFocusedWindow)?;
+                state::State::update(|state| {
+                    state.marks.retain(|_, id| alive(id));
+                    state.marks.insert(name.clone(), window.id);
+                    Ok(())
+                })
+            }
+            Mark::Go { name } => {
+                let id = state::State::load()?
+                    .marks
+                    .get(name)
+                    .copied()
+                    .filter(alive)
+                    .ok_or_else(|| format!("No window marked '{name}'"))?;
+                niri::action(soc, niri_ipc::Action::FocusWindow { id })
+            }
+            Mark::Unset { name } => state::State::update(|state| {
+                state.marks.retain(|_, id| alive(id));
+                state
+                    .marks
+                    .remove(name)
+                    .ok_or_else(|| format!("No window marked '{name}'"))?;
+                Ok(())
+            }),
+            Mark::List => {
+                let state = state::State::load()?;
+                let mut marks: Vec<_> =
+                    state.marks.iter().filter(|&(_, id)| alive(id)).collect();
+                marks.sort();
+                match self.format {
+                    Format::Json => {
+                        let marks: Vec<_> = marks
+                            .into_iter()
+                            .map(|(name, id)| {
+                                let window =
+                                    windows.iter().find(|win| win.id == *id);
+                                let app_id = window.map(|win| &win.app_id);
+                                let title = window.map(|win| &win.title);
+                                serde_json::json!({
+                                    "name": name,
+                                    "id": id,
+                                    "app-id": app_id,
+                                    "title": title,
+                                })
+                            })
+                            .collect();
+                        println!("{}", serde_json::to_string_pretty(&marks)?)
+                    }
+                    Format::Text => {
+                        for (name, id) in marks {
+                            let title = windows
+                                .iter()
+                                .find(|win| win.id == *id)
+                                .and_then(|win| win.title.as_deref())
+                                .unwrap_or("");
+                            println!("{name:<12}  {id:>6}  {title}");
+                        }
+                    }
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Check environment and print report of all checks
+    fn test(&self, soc: &mut Socket) -> Result<()> {
+        let mut checks = Vec::new();
+        let windows = niri::windows(soc);
+        checks.push(match &windows {
+            Ok(windows) => {
+                Check::pass("niri", format!("{} windows", windows.len()))
+            }
+            Err(err) => Check::fail("niri", err),
+        });
+        let focused = self.get_base_window(soc);
+        checks.push(match &focused {
+            Ok(Some(window)) => Check::pass(
+                "base window",
+                format!(
+                    "window {} of {}",
+                    window.id,
+                    window.app_id.as_deref().unwrap_or("unknown class")
+                ),
+            ),
+            Ok(None) => Check::fail("base window", Error::NoFocusedWindow),
+            Err(err) => Check::fail("base window", err),
+        });
+        checks.push(self.check_kitty_template());
+        for window in windows.as_deref().unwrap_or_default() {
+            if !source::is(&self.config.sources, window, "kitty") {
+                continue;
+            }
+            let name = format!("kitty window {}", window.id);
+            checks.push(match self.check_kitty_window(window) {
+                Ok(detail) => Check::pass(name, detail),
+                Err(err) => Check::fail(name, err),
+            });
+        }
+        if let Ok(Some(pid)) = focused.map(|w| w.and_then(|w| w.pid)) {
+            checks.push(match vim::Vim::find_sockets(pid) {
+                Ok(sockets) if sockets.is_empty() => {
+                    Check::pass("nvim sockets", "none")
+                }
+                Ok(sockets) => Check::pass(
+                    "nvim sockets",
+                    sockets
+                        .iter()
+                        .map(|path| path.display().to_string())
+                        .collect::<Vec<_>>()
+                        .join(", "),
+                ),
+                Err(err) => Check::fail("nvim sockets", err),
+            });
+        }
+
+        match self.format {
+            Format::Json => {
+                println!("{}", serde_json::to_string_pretty(&checks)?)
+            }
+            Format::Text => {
+                for check in checks.iter() {
+                    let status = if check.ok { " ok " } else { "fail" };
+                    println!("[{status}] {}: {}", check.name, check.detail);
+                }
+            }
+        }
+        let failed = checks.iter().filter(|check| !check.ok).count();
+        if failed > 0 {
+            Err(format!("{failed} of {} checks failed", checks.len()))?
+        }
+        Ok(())
+    }
+
+    fn check_kitty_template(&self) -> Check {
+        let name = "kitty socket template";
+        let envre = regex::Regex::new(r"\$\{([^\{\}\s]*)\}").unwrap();
+        let unset: Vec<_> = envre
+            .captures_iter(self.kitty_socket())
+            .map(|caps| caps[1].to_string())
+            .filter(|var| std::env::var_os(var).is_none_or(|v| v.is_empty()))
+            .collect();
+        if !unset.is_empty() {
+            Check::fail(name, format!("unset variables: {}", unset.join(", ")))
+        } else if !self.kitty_socket().contains("{pid}") {
+            Check::fail(name, "template does not contain {pid}")
+        } else {
+            let path = self.kitty_socket_path(0);
+            Check::pass(name, format!("expands to {}", path.display()))
+        }
+    }
+
+    fn check_kitty_window(&self, window: &niri_ipc::Window) -> Result<String> {
+        let pid = window.pid.ok_or("Window does not have pid")?;
+        let path = self.kitty_socket_path(pid);
+        let mut kitty = self.get_kitty_socket(pid)?;
+        let r = kitty.request(kitty::Command::Ls(kitty::Ls::default()))?;
+        let windows: Vec<kitty::OsWindow> = serde_json::from_value(r)?;
+        Ok(format!("{}: {} os windows", path.display(), windows.len()))
+    }
+
+    /// Print decisions made while getting launching data
+    fn doctor(&self, data: LaunchingData, soc: &mut Socket) -> Result<()> {
+        let mut steps = Vec::new();
+        let mut hints = Vec::new();
+        if self.fresh {
+            steps.push(Check::fail("fresh", "nothing inherited"));
+        }
+        match self.get_base_window(soc) {
+            Ok(Some(window)) => {
+                self.diagnose_window(&window, &mut steps, &mut hints)
+            }
+            Ok(None) => {
+                steps.push(Check::fail("base window", Error::NoFocusedWindow));
+                hints.push("Focus a window or pass --window <id>".into());
+            }
+            Err(err) => steps.push(Check::fail("base window", err)),
+        }
+        steps.push(match &data.cwd {
+            Some(cwd) => Check::pass("cwd", cwd),
+            None => {
+                hints.push(
+                    "Tools will start in the home directory because no cwd \
+                     was found"
+                        .into(),
+                );
+                Check::fail("cwd", "not found")
+            }
+        });
+        steps.push(Check::pass(
+            "environment",
+            format!("{} variables inherited", data.env.len()),
+        ));
+        if let Some(repo) = &data.git {
+            steps.push(Check::pass("git", repo.root.display()));
+        }
+
+        match self.format {
+            Format::Json => {
+                let report = serde_json::json!({
+                    "steps": steps,
+                    "hints": hints,
+                });
+                println!("{}", serde_json::to_string_pretty(&report)?)
+            }
+            Format::Text => {
+                for step in steps.iter() {
+                    let status = if step.ok { " ok " } else { "fail" };
+                    println!("[{status}] {}: {}", step.name, step.detail);
+                }
+                for hint in hints.iter() {
+                    println!("hint: {hint}");
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn diagnose_window(
+        &self,
+        window: &niri_ipc::Window,
+        steps: &mut Vec<Check>,
+        hints: &mut Vec<String>,
+    ) {
+        steps.push(Check::pass("base window", window.id));
+        let class = match window.app_id.as_deref() {
+            Some(class) => class,
+            None => {
+                steps.push(Check::fail("class", "window does not have class"));
+                return;
+            }
+        };
+        steps.push(Check::pass("class", class));
+        let pid = match window.pid {
+            Some(pid) => pid,
+            None => {
+                steps.push(Check::fail("pid", "window does not have pid"));
+                return;
+            }
+        };
+        steps.push(Check::pass("pid", pid));
+        let source = match source::select(&self.config.sources, window) {
+            Ok(source) => source,
+            Err(err) => {
+                steps.push(Check::fail("source", err));
+                return;
+            }
+        };
+        steps.push(Check::pass("source", source.name()));
+
+        if source.name() == "kitty" {
+            let path = self.kitty_socket_path(pid);
+            steps.push(Check::pass("kitty socket", path.display()));
+            let mut kitty = match self.get_kitty_socket(pid) {
+                Ok(kitty) => kitty,
+                Err(err) => {
+                    steps.push(Check::fail("kitty connect", err));
+                    hints.push(
+                        "Enable kitty remote control with lines printed by \
+                         `generate-kitty-conf` or adjust --kitty-socket"
+                            .into(),
+                    );
+                    return;
+                }
+            };
+            let windows = kitty
+                .request(kitty::Command::Ls(kitty::Ls::default()))
+                .map_err(Error::from)
+                .and_then(|r| {
+                    Ok(serde_json::from_value::<Vec<kitty::OsWindow>>(r)?)
+                });
+            let windows = match windows {
+                Ok(windows) => windows,
+                Err(err) => {
+                    steps.push(Check::fail("kitty ls", err));
+                    return;
+                }
+            };
+            steps.push(Check::pass(
+                "kitty ls",
+                format!("{} os windows", windows.len()),
+            ));
+            let os_window = Self::find_kitty_os_window(windows, window);
+            match os_window.as_ref().and_then(|w| w.active_window()) {
+                Some(window) => steps.push(Check::pass(
+                    "kitty window",
+                    format!(
+                        "cwd {}, effective cwd {}",
+                        window.cwd.display(),
+                        window.effective_cwd().display()
+                    ),
+                )),
+                None => steps.push(Check::fail("kitty window", "none active")),
+            }
+        } else if source.name() == "neovide" {
+            match vim::Vim::find_sockets(pid) {
+                Ok(sockets) if sockets.is_empty() => {
+                    steps.push(Check::fail("nvim sockets", "none found"));
+                    hints.push(
+                        "Neovim is found by $NVIM of processes within it, \
+                         $NVIM_LISTEN_ADDRESS or its default server name"
+                            .into(),
+                    );
+                }
+                Ok(sockets) => {
+                    for path in sockets.iter() {
+                        steps.push(Check::pass("nvim socket", path.display()));
+                    }
+                }
+                Err(err) => steps.push(Check::fail("nvim sockets", err)),
+            }
+        } else {
+            let tree = match pstree::build_process_tree(Some(pid)) {
+                Ok(tree) => tree,
+                Err(err) => {
+                    steps.push(Check::fail("process tree", err));
+                    return;
+                }
+            };
+            match tree.deepest_shell() {
+                Some(shell) => steps.push(Check::pass(
+                    "shell",
+                    format!(
+                        "{} ({})",
+                        shell.record.pid,
+                        pstree::comm(shell.record.pid).unwrap_or_default()
+                    ),
+                )),
+                None => {
+                    steps.push(Check::fail("shell", "none found"));
+                    hints.push(
+                        "No shell runs within the window, cwd of its own \
+                         process is used"
+                            .into(),
+                    );
+                }
+            }
+        }
+        if !source.may_host_vim() {
+            return;
+        }
+        match vimserver::VimServer::discover(pid) {
+            Ok(Some(_)) => steps.push(Check::pass("legacy vim", "found")),
+            Ok(None) => (),
+            Err(err) => steps.push(Check::fail("legacy vim", err)),
+        }
+    }
+
+    /// Run command lines one by one reusing niri connection
+    fn batch(&self, soc: &mut Socket, commands: &[String]) -> Result<()> {
+        let commands = if commands.is_empty() {
+            io::stdin().lines().collect::<io::Result<Vec<_>>>()?
+        } else {
+            commands.to_vec()
+        };
+        for line in commands.iter().map(|line| line.trim()) {
+            let command = match CommandLine::parse_line(line)? {
+                Some(command) => command,
+                None => continue,
+            };
+            log::debug!("Running batch command {command:?}");
+            self.execute(&command, soc)
+                .context(|| Context::new(format!("batch command `{line}`")))?;
+        }
+        Ok(())
+    }
+
+    /// Read and run command lines from stdin until end of input
+    fn repl(&self, soc: &mut Socket) -> Result<()> {
+        use std::io::{BufRead, Write};
+        let mut stdin = io::stdin().lock();
+        loop {
+            print!("niri> ");
+            io::stdout().flush()?;
+            let mut line = String::new();
+            if stdin.read_line(&mut line)? == 0 {
+                println!();
+                return Ok(());
+            }
+            let line = line.trim();
+            match line {
+                "exit" | "quit" => return Ok(()),
+                "help" => {
+                    let mut command = CommandLine::command();
+                    command.print_help()?;
+                    continue;
+                }
+                _ => (),
+            }
+            let result = match CommandLine::parse_line(line) {
+                Ok(Some(command)) => self.execute(&command, soc),
+                Ok(None) => Ok(()),
+                Err(err) => Err(err),
+            };
+            if let Err(err) = result {
+                eprintln!("Error: {err}");
+            }
+        }
+    }
+
+    /// Lines of kitty.conf matching the kitty socket template
+    fn kitty_conf(&self) -> String {
+        let listen_on = self.kitty_socket().replace("{pid}", "{kitty_pid}");
+        format!(
+            "# Generated by niri-integration generate-kitty-conf\n\
+             allow_remote_control socket-only\n\
+             listen_on unix:{listen_on}\n\
+             shell_integration enabled\n"
+        )
+    }
+
+    /// Print or install Lua plugin of neovim integration
+    fn vim_plugin(install: Option<&Path>) -> Result<()> {
+        let bin = std::env::current_exe()?;
+        let plugin = include_str!("plugin.lua")
+            .replace("@BIN@", &format!("{:?}", bin.to_string_lossy()));
+        if let Some(path) = install {
+            if let Some(dir) = path.parent() {
+                std::fs::create_dir_all(dir)?;
+            }
+            std::fs::write(path, plugin)?;
+            log::info!("Installed vim plugin to {}", path.display());
+        } else {
+            print!("{plugin}");
+        }
+        Ok(())
+    }
+
+    fn print_workspaces(&self, soc: &mut Socket) -> Result<()> {
+        let mut workspaces = niri::workspaces(soc)?;
+        workspaces.sort_by(|a, b| (&a.output, a.idx).cmp(&(&b.output, b.idx)));
+        match self.format {
+            Format::Json => {
+                println!("{}", serde_json::to_string_pretty(&workspaces)?)
+            }
+            Format::Text => {
+                println!("output      idx  name          active  focused");
+                for ws in workspaces.iter() {
+                    println!(
+                        "{:<10}  {:>3}  {:<12}  {:<6}  {}",
+                        ws.output.as_deref().unwrap_or("-"),
+                        ws.idx,
+                        ws.name.as_deref().unwrap_or("-"),
+                        ws.is_active,
+                        ws.is_focused
+                    );
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn clean_workspace(&self, soc: &mut Socket, idle: u64) -> Result<()> {
+        let idle = Duration::from_secs(idle * 60);
+        let ws = niri::workspaces(soc)?
+            .into_iter()
+            .find(|ws| ws.is_focused)
+            .ok_or("No focused workspace")?;
+        for window in niri::windows(soc)? {
+            if window.workspace_id != Some(ws.id)
+                || !source::is(&self.config.sources, &window, "kitty")
+            {
+                continue;
+            }
+            let pid = match window.pid {
+                Some(pid) => pid,
+                None => continue,
+            };
+            if self.is_kitty_scratch(pid, idle).unwrap_or(false) {
+                niri::action(
+                    soc,
+                    niri_ipc::Action::CloseWindow {
+                        id: Some(window.id),
+                    },
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    fn which(
+        &self,
+        soc: &mut Socket,
+        pid: Option<i32>,
+        cwd: Option<&Path>,
+    ) -> Result<()> {
+        let ancestors = pid.map(pstree::ancestors).transpose()?;
+        let cwd = cwd.map(|cwd| cwd.canonicalize()).transpose()?;
+        let ids: Vec<u64> = niri::windows(soc)?
+            .into_iter()
+            .filter(|window| {
+                let Some(root) = window.pid else {
+                    return false;
+                };
+                if let Some(ancestors) = &ancestors {
+                    ancestors.contains(&root)
+                } else if let Some(cwd) = &cwd {
+                    pstree::build_process_tree(Some(root))
+                        .map(|tree| {
+                            tree.root
+                                .find(&|node: &pstree::ProcessTreeNode| {
+                                    pstree::cwd(node.record.pid)
+                                        .is_ok_and(|dir| Path::new(&dir) == cwd)
+                                })
+                                .is_some()
+                        })
+                        .unwrap_or(false)
+                } else {
+                    false
+                }
+            })
+            .map(|window| window.id)
+            .collect();
+        if ids.is_empty() {
+            Err("No matching niri windows")?
+        }
+        match self.format {
+            Format::Json => println!("{}", serde_json::to_string(&ids)?),
+            Format::Text => ids.iter().for_each(|id| println!("{id}")),
+        }
+        Ok(())
+    }
+
+    /// Focus window working in directory or launch terminal there
+    fn focus_cwd(
+        &self,
+        soc: &mut Socket,
+        path: &Path,
+        launch: bool,
+    ) -> Result<()> {
+        let cwd = path.canonicalize()?;
+        let target = LaunchingData::default()
+            .maybe_cwd(cwd.to_str())
+            .detect_git();
+        for window in niri::windows(soc)? {
+            let id = window.id;
+            match self.focus_cwd_within(&window, &target) {
+                Ok(true) => {
+                    log::debug!("Window {id} works in {}", cwd.display());
+                    return niri::action(
+                        soc,
+                        niri_ipc::Action::FocusWindow { id },
+                    );
+                }
+                Ok(false) => (),
+                Err(err) => {
+                    log::debug!("Can not get cwd of window {id}: {err}")
+                }
+            }
+        }
+        if launch {
+            return self.run_kitty(target, soc);
+        }
+        Err(Error::NoMatchingWindow(cwd.display().to_string()))
+    }
+
+    /// Whenever the window works in cwd (or repository) of data
+    ///
+    /// All tabs of kitty are inspected, the matching one is focused.
+    fn focus_cwd_within(
+        &self,
+        window: &niri_ipc::Window,
+        data: &LaunchingData,
+    ) -> Result<bool> {
+        let source = source::select(&self.config.sources, window)?;
+        if source.name() != "kitty" {
+            let cwd = source.extract(self, window)?.cwd;
+            return Ok(cwd
+                .is_some_and(|cwd| self.is_working_in(Path::new(&cwd), data)));
+        }
+        let pid = window.pid.ok_or("Window does not have pid")?;
+        let mut kitty = self.get_kitty_socket(pid)?;
+        let r = kitty.request(kitty::Command::Ls(kitty::Ls::default()))?;
+        let windows: Vec<kitty::OsWindow> = serde_json::from_value(r)?;
+        let tab = Self::find_kitty_os_window(windows, window)
+            .into_iter()
+            .flat_map(|os_window| os_window.tabs)
+            .find(|tab| {
+                tab.windows
+                    .iter()
+                    .any(|win| self.is_working_in(win.effective_cwd(), data))
+            });
+        let Some(tab) = tab else {
+            return Ok(false);
+        };
+        if !dryrun::skip(format_args!("focus kitty tab {}", tab.id)) {
+            let focus = kitty::FocusTab {
+                match_tab: Some(format!("id:{}", tab.id)),
+            };
+            kitty.request(kitty::Command::FocusTab(focus))?;
+        }
+        Ok(true)
+    }
+
     fn is_kitty_matches(
         &self,
         win: &niri_ipc::Window,
         data: &LaunchingData,
     ) -> Result<bool> {
-        if win.app_id != Some("kitty".into()) {
+        if !source::is(&self.config.sources, win, "kitty") {
             return Ok(false);
         }
         if win.is_focused {
             return Ok(false);
         }
 
-        if data.cwd.is_none() {
-            return Ok(false);
+        if data.cwd.is_none() {
+            return Ok(false);
+        }
+
+        let pid = win.pid.ok_or(io::Error::new(
+            io::ErrorKind::NotFound,
+            "Focused niri window does not have pid",
+        ))?;
+        let mut kitty = self.get_kitty_socket(pid)?;
+        let matches = self.is_kitty_working_in(&mut kitty, win, data)?;
+        if matches {
+            log::debug!("Kitty {} matches launching data", win.id);
+        }
+        Ok(matches)
+    }
+
+    /// Whenever any window of kitty OS window shown as `win` works in cwd (or
+    /// repository) of data
+    fn is_kitty_working_in(
+        &self,
+        kitty: &mut dyn kitty::KittyTransport,
+        win: &niri_ipc::Window,
+        data: &LaunchingData,
+    ) -> Result<bool> {
+        let Some(cwd) = data.cwd.as_ref() else {
+            return Ok(false);
+        };
+        let r = kitty::Command::Ls(kitty::Ls::default());
+        let r = kitty.request(r)?;
+        let windows: Vec<kitty::OsWindow> = serde_json::from_value(r)?;
+
+        let Some(os_window) = Self::find_kitty_os_window(windows, win) else {
+            return Ok(false);
+        };
+        for tab in os_window.tabs {
+            for window in tab.windows {
+                if self.is_working_in(window.effective_cwd(), data) {
+                    log::debug!("Kitty window is in {cwd}");
+                    return Ok(true);
+                }
+            }
+        }
+        Ok(false)
+    }
+
+    /// Whenever `dir` is cwd of data or, with `launch.match-repo`, belongs to
+    /// the same repository
+    fn is_working_in(&self, dir: &Path, data: &LaunchingData) -> bool {
+        if data
+            .cwd
+            .as_deref()
+            .is_some_and(|cwd| dir.to_str() == Some(cwd))
+        {
+            return true;
+        }
+        data.git
+            .as_ref()
+            .filter(|_| self.config.launch.match_repo)
+            .is_some_and(|repo| {
+                git::Repo::detect(dir)
+                    .is_some_and(|other| other.root == repo.root)
+            })
+    }
+
+    fn print_env(
+        &self,
+        launching_data: LaunchingData,
+        show_secrets: bool,
+        diff: bool,
+    ) -> Result<()> {
+        let show = |prefix: &str, name: &str, val: &str| {
+            if !show_secrets && self.config.env.is_secret(name) {
+                println!("{prefix}{name}=\"{}\"", config::MASK);
+            } else {
+                println!("{prefix}{name}=\"{val}\"");
+            }
+        };
+        if !diff {
+            if let Some(repo) = &launching_data.git {
+                println!("# git root: {}", repo.root.display());
+                if let Some(branch) = &repo.branch {
+                    println!("# git branch: {branch}");
+                }
+            }
+            for (name, val) in launching_data.env {
+                show("", &name, &val);
+            }
+            return Ok(());
+        }
+
+        // Environment may contain non-unicode values, they are shown lossy
+        let current: HashMap<String, String> = std::env::vars_os()
+            .map(|(name, val)| {
+                let name = name.to_string_lossy().into_owned();
+                (name, val.to_string_lossy().into_owned())
+            })
+            .collect();
+        let mut names: Vec<&String> =
+            current.keys().chain(launching_data.env.keys()).collect();
+        names.sort();
+        names.dedup();
+        for name in names {
+            match (current.get(name), launching_data.env.get(name)) {
+                (Some(old), Some(new)) if old != new => {
+                    show("-", name, old);
+                    show("+", name, new);
+                }
+                (Some(old), None) => show("-", name, old),
+                (None, Some(new)) => show("+", name, new),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn run_vim(&self, mut data: LaunchingData, soc: &mut Socket) -> Result<()> {
+        if let Some(ref mut vim) = data.get_vim() {
+            vim.run(true, soc)
+        } else {
+            self.run_neovide(data, soc, &[], None)
+        }
+    }
+
+    fn open_vim(
+        &self,
+        mut data: LaunchingData,
+        soc: &mut Socket,
+        files: &[PathBuf],
+        position: Option<(i64, i64)>,
+    ) -> Result<()> {
+        // Paths are relative to the caller, not to the vim
+        let files = files
+            .iter()
+            .map(std::path::absolute)
+            .collect::<io::Result<Vec<_>>>()?;
+        if let Some(ref mut vim) = data.get_vim() {
+            vim.open(&files, soc)?;
+            match position {
+                Some((line, column)) => vim.set_cursor(line, column),
+                None => Ok(()),
+            }
+        } else {
+            self.run_neovide(data, soc, &files, position)
+        }
+    }
+
+    fn run_neovide(
+        &self,
+        data: LaunchingData,
+        soc: &mut Socket,
+        files: &[PathBuf],
+        position: Option<(i64, i64)>,
+    ) -> Result<()> {
+        let editor = match self.get_env_wrapper(&data) {
+            Some(wrapper) => {
+                [wrapper, self.config.launch.editor.clone()].concat()
+            }
+            None => self.config.launch.editor.clone(),
+        };
+        let mut proc =
+            Self::templated_command(&editor, data.cwd.as_deref(), &data.env)?;
+        // Editor always inherits environment, regardless to template
+        proc.envs(&data.env);
+
+        data.cwd.map(|workdir| {
+            proc.current_dir(workdir);
+        });
+        // Command goes before files, any editor treats arguments after `--`
+        // as files
+        if let Some((line, column)) = position {
+            proc.arg(format!("+call cursor({line}, {column})"));
+        }
+        proc.args(files);
+        self.launch(proc, soc)
+    }
+
+    fn sync_vim(
+        mut data: LaunchingData,
+        soc: &mut Socket,
+        height: bool,
+        all_tabs: bool,
+    ) -> Result<()> {
+        if let Some(ref mut vim) = data.get_vim() {
+            if all_tabs {
+                vim.use_widest_tabpage()?;
+            }
+            vim.test()?;
+            if height {
+                vim.sync_height(soc)?;
+            }
+            vim.sync_width(soc)?
+        };
+        Ok(())
+    }
+
+    fn shift_vim(mut data: LaunchingData, soc: &mut Socket) -> Result<()> {
+        if let Some(ref mut vim) = data.get_vim() {
+            vim.test()?;
+            vim.shift(soc)?
+        };
+        Ok(())
+    }
+
+    fn focus_vim(&self, soc: &mut Socket, file: &Path) -> Result<()> {
+        let file = std::path::absolute(file)?;
+        for window in niri::windows(soc)? {
+            if !source::is(&self.config.sources, &window, "neovide") {
+                continue;
+            }
+            // Instances which can not be reached are skipped
+            let Ok(mut vim) =
+                vim::Vim::new(window, &self.vim_choice(Vec::new()))
+            else {
+                continue;
+            };
+            if vim.focus_file(&file, soc)? {
+                return Ok(());
+            }
+        }
+        Err(format!("No vim has {} opened", file.display()).into())
+    }
+
+    fn fit_vim(mut data: LaunchingData) -> Result<()> {
+        let vim = data.get_vim().ok_or("Focused window is not vim")?;
+        vim.fit()
+    }
+
+    fn cycle_vim_width(
+        &self,
+        mut data: LaunchingData,
+        soc: &mut Socket,
+    ) -> Result<()> {
+        let vim = data.get_vim().ok_or("Focused window is not vim")?;
+        vim.cycle_width(&self.config.vim.presets, soc)
+    }
+
+    fn calibrate_vim(mut data: LaunchingData, soc: &mut Socket) -> Result<()> {
+        let vim = data.get_vim().ok_or("Focused window is not vim")?;
+        let calibration = vim.calibrate(soc)?;
+        state::State::update(|state| {
+            state.calibration = Some(calibration);
+            Ok(())
+        })?;
+        vim.sync_width(soc)
+    }
+
+    fn print_vim_columns(&self, mut data: LaunchingData) -> Result<()> {
+        let vim = data.get_vim().ok_or("Focused window is not vim")?;
+        let columns = vim.get_columns_info();
+        match self.format {
+            Format::Json => {
+                println!("{}", serde_json::to_string_pretty(&columns)?)
+            }
+            Format::Text => {
+                println!("column  start  end  textwidth  windows");
+                for (i, c) in columns.iter().enumerate() {
+                    println!(
+                        "{:>6}  {:>5}  {:>3}  {:>9}  {:>7}",
+                        i, c.start, c.end, c.textwidth, c.windows
+                    );
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn print_vim_instances(&self, socket: &mut Socket) -> Result<()> {
+        let window = self
+            .get_base_window(socket)?
+            .ok_or(Error::NoFocusedWindow)?;
+        let pid = window.pid.ok_or("Base window does not have pid")?;
+        let instances = vim::Vim::instances(pid)?;
+        match self.format {
+            Format::Json => {
+                println!("{}", serde_json::to_string_pretty(&instances)?)
+            }
+            Format::Text => {
+                for instance in instances.iter() {
+                    println!(
+                        "{:>7}  {}",
+                        instance.pid,
+                        instance.socket.display()
+                    );
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn switch(
+        mut data: LaunchingData,
+        soc: &mut Socket,
+        direction: &Direction,
+        wrap: bool,
+    ) -> Result<()> {
+        if let Some(ref mut vim) = data.get_vim() {
+            vim.switch(soc, direction, wrap)?;
+        } else if let Some(server) = data.get_legacy_vim() {
+            server.switch(soc, direction, wrap)?;
+        } else {
+            Self::switch_niri(soc, direction, wrap)?;
+        }
+        Ok(())
+    }
+
+    /// Focus niri window in direction.
+    ///
+    /// Returns false when wrapping was requested and the focused window is the
+    /// only one in direction, so the application may wrap on its own.
+    pub fn switch_niri(
+        soc: &mut Socket,
+        direction: &Direction,
+        wrap: bool,
+    ) -> Result<bool> {
+        if wrap {
+            match niri::edge(soc, direction)? {
+                niri::Edge::Wrap(action) => {
+                    niri::action(soc, action)?;
+                    return Ok(true);
+                }
+                niri::Edge::Single => return Ok(false),
+                niri::Edge::Inner => (),
+            }
+        }
+        niri::action(
+            soc,
+            NiriActionDirection::new_focus().mk_action(direction),
+        )?;
+        Ok(true)
+    }
+
+    fn move_window(
+        mut data: LaunchingData,
+        soc: &mut Socket,
+        direction: &Direction,
+    ) -> Result<()> {
+        if let Some(ref mut vim) = data.get_vim() {
+            vim.move_window(soc, direction)?;
+        } else if let Some(server) = data.get_legacy_vim() {
+            server.move_window(soc, direction)?;
+        } else {
+            Self::move_niri(soc, direction)?;
+        }
+        Ok(())
+    }
+
+    fn swap(
+        mut data: LaunchingData,
+        soc: &mut Socket,
+        direction: &Direction,
+    ) -> Result<()> {
+        if let Some(ref mut vim) = data.get_vim() {
+            vim.swap(soc, direction)
+        } else if let Some(server) = data.get_legacy_vim() {
+            server.swap(soc, direction)
+        } else {
+            Self::swap_niri(soc, direction)
         }
-        let cwd = data.cwd.as_ref().unwrap();
+    }
 
-        let pid = win.pid.ok_or(io::Error::new(
-            io::ErrorKind::NotFound,
-            "Focused niri window does not have pid",
-        ))?;
-        let mut kitty = self.get_kitty_socket(pid)?;
-        let r = kitty::Command::Ls(kitty::Ls::default());
-        let r = kitty.request(r)?;
-        let windows: Vec<kitty::OsWindow> = serde_json::from_value(r)?;
+    pub fn swap_niri(soc: &mut Socket, direction: &Direction) -> Result<()> {
+        niri::action(soc, NiriActionDirection::new_swap().mk_action(direction))
+    }
 
-        for window in windows {
-            for tab in window.tabs {
-                for window in tab.windows {
-                    if let Some(cwd2) = window.cwd.to_str() {
-                        if cwd == cwd2 {
-                            return Ok(true);
-                        }
-                    }
-                }
-            }
+    fn send_keys(mut data: LaunchingData, keys: &str) -> Result<()> {
+        if let Some(ref mut vim) = data.get_vim() {
+            vim.send_keys(keys)
+        } else if let Some(server) = data.get_legacy_vim() {
+            server.send_keys(keys)
+        } else if let Some(kitty) = data.get_kitty() {
+            let text = kitty::SendText {
+                data: format!("text:{keys}"),
+                ..Default::default()
+            };
+            kitty.request(kitty::Command::SendText(text))?;
+            Ok(())
+        } else {
+            Err(Error::Unsupported("sending keys"))
         }
-        Ok(false)
     }
 
-    fn print_env(launching_data: LaunchingData) -> Result<()> {
-        for (name, val) in launching_data.env {
-            println!("{name}=\"{val}\"");
+    /// Set titles of tabs, and optionally windows, of kitty of base window
+    fn retitle(&self, soc: &mut Socket) -> Result<()> {
+        let window =
+            self.get_base_window(soc)?.ok_or(Error::NoFocusedWindow)?;
+        if !source::is(&self.config.sources, &window, "kitty") {
+            return Err(Error::Unsupported("retitling"));
+        }
+        let pid = window.pid.ok_or("Base window does not have pid")?;
+        let mut kitty = self.get_kitty_socket(pid)?;
+        let r = kitty.request(kitty::Command::Ls(kitty::Ls::default()))?;
+        let windows: Vec<kitty::OsWindow> = serde_json::from_value(r)?;
+        let os_window = Self::find_kitty_os_window(windows, &window);
+        let title = &self.config.title;
+        for tab in os_window.into_iter().flat_map(|os_window| os_window.tabs) {
+            let Some(active) = tab.windows.iter().find(|win| win.is_active)
+            else {
+                continue;
+            };
+            let cwd = active.effective_cwd();
+            // Cwd of remote session does not exist locally
+            let repo = match active.ssh_destination() {
+                Some(_) => None,
+                None => git::Repo::detect(cwd),
+            };
+            let branch = repo.as_ref().and_then(|repo| repo.branch.as_deref());
+            let text = title.expand(cwd, branch);
+            log::debug!("Retitle kitty tab {} to {text:?}", tab.id);
+            if dryrun::skip(format_args!("retitle kitty tab {}", tab.id)) {
+                continue;
+            }
+            let set_tab = kitty::SetTitle {
+                title: text.clone(),
+                match_window: Some(format!("id:{}", tab.id)),
+                ..Default::default()
+            };
+            kitty.request(kitty::Command::SetTabTitle(set_tab))?;
+            if title.window {
+                let set_window = kitty::SetTitle {
+                    title: text,
+                    match_window: Some(format!("id:{}", active.id)),
+                    ..Default::default()
+                };
+                kitty.request(kitty::Command::SetWindowTitle(set_window))?;
+            }
         }
         Ok(())
     }
 
-    fn run_vim(mut data: LaunchingData, soc: &mut Socket) -> Result<()> {
+    fn tab(mut data: LaunchingData, soc: &mut Socket, tab: &Tab) -> Result<()> {
         if let Some(ref mut vim) = data.get_vim() {
-            vim.run(true, soc)
+            vim.tab(tab)
+        } else if let Some(server) = data.get_legacy_vim() {
+            server.tab(tab)
+        } else if let Some(kitty) = data.get_kitty() {
+            let action = kitty::Action {
+                action: tab.kitty_action().into(),
+                ..Default::default()
+            };
+            kitty.request(kitty::Command::Action(action))?;
+            Ok(())
         } else {
-            let mut proc = std::process::Command::new("neovide");
-
-            data.env
-                .into_iter()
-                .fold(&mut proc, |proc, (name, val)| proc.env(name, val));
-
-            data.cwd.map(|workdir| {
-                proc.current_dir(workdir);
-            });
-            Err(proc.exec())?
+            niri::action(soc, tab.niri_action())
         }
     }
 
-    fn sync_vim(mut data: LaunchingData, soc: &mut Socket) -> Result<()> {
+    fn float(&self, mut data: LaunchingData, soc: &mut Socket) -> Result<()> {
+        let window =
+            self.get_base_window(soc)?.ok_or(Error::NoFocusedWindow)?;
+        let id = Some(window.id);
+        let mut floated = None;
+        if window.is_floating {
+            let layout = &window.layout;
+            if let Some((x, y)) = layout.tile_pos_in_workspace_view {
+                let (width, height) = layout.window_size;
+                floated = Some(state::Geometry {
+                    x,
+                    y,
+                    width,
+                    height,
+                });
+            }
+            niri::action(soc, niri_ipc::Action::ToggleWindowFloating { id })?;
+        } else {
+            niri::action(soc, niri_ipc::Action::ToggleWindowFloating { id })?;
+            let state = state::State::load()?;
+            if let Some(geometry) = state.floating.get(&window.id) {
+                use niri_ipc::{PositionChange, SizeChange};
+                let width = SizeChange::SetFixed(geometry.width);
+                let height = SizeChange::SetFixed(geometry.height);
+                let (x, y) = (geometry.x, geometry.y);
+                niri::action(
+                    soc,
+                    niri_ipc::Action::SetWindowWidth { id, change: width },
+                )?;
+                niri::action(
+                    soc,
+                    niri_ipc::Action::SetWindowHeight { id, change: height },
+                )?;
+                niri::action(
+                    soc,
+                    niri_ipc::Action::MoveFloatingWindow {
+                        id,
+                        x: PositionChange::SetFixed(x),
+                        y: PositionChange::SetFixed(y),
+                    },
+                )?;
+            }
+        }
+        // Forget windows which are closed
+        let windows = niri::windows(soc)?;
+        state::State::update(|state| {
+            let floating = &mut state.floating;
+            floating.extend(floated.map(|geometry| (window.id, geometry)));
+            floating.retain(|id, _| windows.iter().any(|win| win.id == *id));
+            Ok(())
+        })?;
         if let Some(ref mut vim) = data.get_vim() {
-            vim.test()?;
-            vim.sync_width(soc)?
-        };
+            vim.sync_width(soc)?;
+        }
         Ok(())
     }
 
-    fn shift_vim(mut data: LaunchingData, soc: &mut Socket) -> Result<()> {
+    fn fullscreen(mut data: LaunchingData, soc: &mut Socket) -> Result<()> {
         if let Some(ref mut vim) = data.get_vim() {
-            vim.test()?;
-            vim.shift(soc)?
-        };
-        Ok(())
+            vim.toggle_fullscreen(soc)
+        } else {
+            niri::action(soc, niri_ipc::Action::FullscreenWindow { id: None })
+        }
     }
 
-    fn switch(
+    fn resize(
         mut data: LaunchingData,
         soc: &mut Socket,
         direction: &Direction,
+        amount: &config::Width,
     ) -> Result<()> {
         if let Some(ref mut vim) = data.get_vim() {
-            vim.switch(soc, direction)?;
+            if vim.resize(direction, amount)? {
+                return Ok(());
+            }
+        }
+        let (width, grow) = direction.resize_axis()?;
+        let change = match *amount {
+            config::Width::Pixels(px) => {
+                let px = if grow { px } else { -px };
+                niri_ipc::SizeChange::AdjustFixed(px as i32)
+            }
+            config::Width::Fraction(f) => {
+                let percents = if grow { f } else { -f } * 100.0;
+                niri_ipc::SizeChange::AdjustProportion(percents)
+            }
+        };
+        niri::action(
+            soc,
+            if width {
+                niri_ipc::Action::SetWindowWidth { id: None, change }
+            } else {
+                niri_ipc::Action::SetWindowHeight { id: None, change }
+            },
+        )
+    }
+
+    fn zoom(mut data: LaunchingData, soc: &mut Socket) -> Result<()> {
+        if let Some(ref mut vim) = data.get_vim() {
+            vim.zoom()
+        } else if let Some(server) = data.get_legacy_vim() {
+            server.zoom()
+        } else if let Some(kitty) = data.get_kitty() {
+            Self::zoom_kitty(kitty)
         } else {
-            Self::switch_niri(soc, direction)?;
+            niri::action(soc, niri_ipc::Action::MaximizeColumn {})
         }
-        Ok(())
     }
 
-    pub fn switch_niri(soc: &mut Socket, direction: &Direction) -> Result<()> {
-        soc.send(NiriActionDirection::new_focus().mk_request(direction))??;
+    /// Toggle stack layout of focused kitty tab
+    fn zoom_kitty(kitty: &mut dyn kitty::KittyTransport) -> Result<()> {
+        let windows =
+            kitty.request(kitty::Command::Ls(kitty::Ls::default()))?;
+        let windows: Vec<kitty::OsWindow> = serde_json::from_value(windows)?;
+        let stacked = windows
+            .iter()
+            .filter(|window| window.is_focused)
+            .flat_map(|window| window.tabs.iter())
+            .any(|tab| tab.is_focused && tab.layout == "stack");
+        let cmd = if stacked {
+            kitty::Command::LastUsedLayout(Default::default())
+        } else {
+            kitty::Command::GotoLayout(kitty::GotoLayout {
+                layout: "stack".into(),
+                ..Default::default()
+            })
+        };
+        kitty.request(cmd)?;
         Ok(())
     }
 
-    fn move_window(
-        mut data: LaunchingData,
-        soc: &mut Socket,
-        direction: &Direction,
-    ) -> Result<()> {
+    fn equalize(mut data: LaunchingData, soc: &mut Socket) -> Result<()> {
         if let Some(ref mut vim) = data.get_vim() {
-            vim.move_window(soc, direction)?;
+            vim.equalize()
+        } else if let Some(server) = data.get_legacy_vim() {
+            server.equalize()
+        } else if let Some(kitty) = data.get_kitty() {
+            let resize = kitty::ResizeWindow {
+                axis: Some("reset".into()),
+                ..Default::default()
+            };
+            kitty.request(kitty::Command::ResizeWindow(resize))?;
+            Ok(())
         } else {
-            Self::move_niri(soc, direction)?;
+            niri::action(soc, niri_ipc::Action::ResetWindowHeight { id: None })
         }
-        Ok(())
     }
 
-    fn close(mut data: LaunchingData, soc: &mut Socket) -> Result<()> {
+    fn close(
+        &self,
+        mut data: LaunchingData,
+        soc: &mut Socket,
+        force: bool,
+        write: bool,
+    ) -> Result<()> {
         if let Some(ref mut vim) = data.get_vim() {
-            vim.close_window(false, soc)?;
+            vim.close_window(force, write, soc)?;
+        } else if let Some(server) = data.get_legacy_vim() {
+            server.close_window(force, write, soc)?;
+        } else if let (Some(kitty), true) = (data.get_kitty(), force) {
+            // Process of kitty may serve other windows, so only the active
+            // kitty window of base one is closed
+            let base =
+                self.get_base_window(soc)?.ok_or(Error::NoFocusedWindow)?;
+            let r = kitty.request(kitty::Command::Ls(kitty::Ls::default()))?;
+            let windows = serde_json::from_value(r)?;
+            let id = Self::find_kitty_os_window(windows, &base)
+                .and_then(|os_window| os_window.active_window().map(|w| w.id))
+                .ok_or("No active kitty window")?;
+            let close = kitty::CloseWindow {
+                match_window: Some(format!("id:{id}")),
+            };
+            kitty.request(kitty::Command::CloseWindow(close))?;
         } else {
-            soc.send(niri_ipc::Request::Action(
-                niri_ipc::Action::CloseWindow { id: None },
-            ))??;
+            let id =
+                self.get_base_window(soc)?.ok_or(Error::NoFocusedWindow)?.id;
+            niri::action(soc, niri_ipc::Action::CloseWindow { id: Some(id) })?;
         }
         Ok(())
     }
 
     pub fn move_niri(soc: &mut Socket, direction: &Direction) -> Result<()> {
-        soc.send(NiriActionDirection::new_move().mk_request(direction))??;
-        Ok(())
+        niri::action(soc, NiriActionDirection::new_move().mk_action(direction))
     }
 
-    fn find_kitty_focused_window(
+    /// Find kitty OS window shown as niri window
+    ///
+    /// Single kitty process may serve several OS windows. The focused one is
+    /// shown in focused niri window, others are told by title.
+    fn find_kitty_os_window(
         windows: Vec<kitty::OsWindow>,
-    ) -> Option<kitty::Window> {
-        for window in windows {
-            if window.is_focused {
-                for tab in window.tabs {
-                    if tab.is_focused {
-                        for window in tab.windows {
-                            if window.is_focused {
-                                return Some(window);
-                            }
-                        }
-                    }
-                }
+        niri_window: &niri_ipc::Window,
+    ) -> Option<kitty::OsWindow> {
+        if windows.len() == 1 {
+            return windows.into_iter().next();
+        }
+        windows.into_iter().find(|os_window| {
+            if niri_window.is_focused {
+                os_window.is_focused
+            } else {
+                os_window.title() == niri_window.title.as_deref()
+            }
+        })
+    }
+
+    fn get_base_window(
+        &self,
+        socket: &mut Socket,
+    ) -> Result<Option<niri_ipc::Window>> {
+        let selector = match &self.window {
+            None | Some(WindowSelector::Focused) => {
+                return niri::focused_window(socket);
+            }
+            Some(WindowSelector::Pointer) => {
+                Err("Niri does not expose window under pointer")?
             }
+            Some(selector) => selector,
+        };
+        let mut windows: Vec<_> = niri::windows(socket)?
+            .into_iter()
+            .filter(|window| selector.matches(window))
+            .collect();
+        if let WindowSelector::Id(id) = selector {
+            return windows.pop().map(Some).ok_or(Error::WindowNotFound(*id));
         }
-        None
+        let history = state::State::load()
+            .map(|state| state.focus_history)
+            .unwrap_or_default();
+        // Focused first, then in order of focus history
+        windows.sort_by_key(|window| {
+            let recent = history.iter().position(|id| *id == window.id);
+            (!window.is_focused, recent.unwrap_or(usize::MAX))
+        });
+        windows
+            .into_iter()
+            .next()
+            .map(Some)
+            .ok_or_else(|| Error::NoMatchingWindow(selector.to_string()))
     }
+}
 
-    fn get_base_window(&self, socket: &mut Socket) -> Option<niri_ipc::Window> {
-        if let Some(id) = self.window {
-            if let Response::Windows(windows) =
-                socket.send(Request::Windows).unwrap().unwrap()
-            {
-                let mut res = None;
-                for window in windows.into_iter() {
-                    if window.id == id {
-                        res = Some(window);
-                        break;
-                    }
-                }
-                res
-            } else {
-                panic!("Unexpected response to Windows")
+/// Selector of base window given with `--window`
+#[derive(Debug, Clone)]
+pub enum WindowSelector {
+    /// Window with niri id
+    Id(u64),
+    /// Focused window
+    Focused,
+    /// Window under pointer
+    Pointer,
+    /// Windows with app id
+    AppId(String),
+    /// Windows with title matching regular expression
+    Title(regex::Regex),
+    /// Window picked interactively
+    Pick,
+}
+
+impl WindowSelector {
+    fn matches(&self, window: &niri_ipc::Window) -> bool {
+        match self {
+            WindowSelector::Id(id) => window.id == *id,
+            WindowSelector::Focused => window.is_focused,
+            WindowSelector::Pointer | WindowSelector::Pick => false,
+            WindowSelector::AppId(app_id) => {
+                window.app_id.as_deref() == Some(app_id)
             }
-        } else if let Response::FocusedWindow(window) =
-            socket.send(Request::FocusedWindow).unwrap().unwrap()
-        {
-            window
+            WindowSelector::Title(re) => {
+                re.is_match(window.title.as_deref().unwrap_or_default())
+            }
+        }
+    }
+}
+
+impl std::str::FromStr for WindowSelector {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        if let Ok(id) = s.parse() {
+            return Ok(WindowSelector::Id(id));
+        }
+        match s {
+            "focused" => return Ok(WindowSelector::Focused),
+            "pointer" => return Ok(WindowSelector::Pointer),
+            "pick" => return Ok(WindowSelector::Pick),
+            _ => (),
+        }
+        if let Some(app_id) = s.strip_prefix("app-id:") {
+            Ok(WindowSelector::AppId(app_id.into()))
+        } else if let Some(title) = s.strip_prefix("title:") {
+            let re = title
+                .strip_prefix('/')
+                .and_then(|title| title.strip_suffix('/'))
+                .unwrap_or(title);
+            regex::Regex::new(re)
+                .map(WindowSelector::Title)
+                .map_err(|err| err.to_string())
         } else {
-            panic!("Unexpected response to FocusedWindow")
+            Err(format!(
+                "Invalid window '{s}', expected id, 'focused', 'pointer', \
+                 'pick', 'app-id:ID' or 'title:/REGEX/'"
+            ))
+        }
+    }
+}
+
+impl Display for WindowSelector {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            WindowSelector::Id(id) => write!(f, "{id}"),
+            WindowSelector::Focused => write!(f, "focused"),
+            WindowSelector::Pointer => write!(f, "pointer"),
+            WindowSelector::AppId(app_id) => write!(f, "app-id:{app_id}"),
+            WindowSelector::Title(re) => write!(f, "title:/{re}/"),
+            WindowSelector::Pick => write!(f, "pick"),
+        }
+    }
+}
+
+/// Single command line of `batch` and `repl` commands
+#[derive(Parser)]
+#[command(no_binary_name = true)]
+struct CommandLine {
+    #[command(subcommand)]
+    command: Command,
+}
+
+impl CommandLine {
+    /// Parse command from line split by whitespace
+    ///
+    /// Returns [None] for empty and comment lines.
+    fn parse_line(line: &str) -> Result<Option<Command>> {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            return Ok(None);
+        }
+        let parsed = Self::try_parse_from(line.split_whitespace())
+            .map_err(|err| format!("Invalid command `{line}`: {err}"))?;
+        Ok(Some(parsed.command))
+    }
+}
+
+/// Result of single check of [Command::Test]
+#[derive(serde::Serialize)]
+struct Check {
+    name: String,
+    ok: bool,
+    detail: String,
+}
+
+impl Check {
+    fn pass(name: impl Into<String>, detail: impl Display) -> Self {
+        Self {
+            name: name.into(),
+            ok: true,
+            detail: detail.to_string(),
+        }
+    }
+
+    fn fail(name: impl Into<String>, detail: impl Display) -> Self {
+        Self {
+            name: name.into(),
+            ok: false,
+            detail: detail.to_string(),
         }
     }
 }
@@ -555,6 +3315,15 @@ impl LaunchingData {
         self
     }
 
+    /// Detect git repository of cwd
+    pub fn detect_git(mut self) -> Self {
+        self.git = self
+            .cwd
+            .as_deref()
+            .and_then(|cwd| git::Repo::detect(Path::new(cwd)));
+        self
+    }
+
     pub fn maybe_cwd<S>(mut self, cwd: Option<S>) -> Self
     where
         S: Into<String>,
@@ -603,6 +3372,57 @@ impl LaunchingData {
         self.clear_env().add_envs(it)
     }
 
+    /// Keep only variables which names satisfy the predicate
+    pub fn filter_envs<P>(mut self, predicate: P) -> Self
+    where
+        P: Fn(&str) -> bool,
+    {
+        self.env.retain(|name, _| predicate(name));
+        self
+    }
+
+    /// Make virtualenv from environment active for launched program
+    ///
+    /// Stale virtualenv which directory does not exist anymore is removed
+    /// from environment.
+    pub fn activate_venv(mut self) -> Self {
+        let venv = match self.env.get("VIRTUAL_ENV") {
+            Some(venv) => PathBuf::from(venv),
+            None => return self,
+        };
+        let bin = venv.join("bin");
+        let path = self.env.get("PATH").cloned().unwrap_or_default();
+        let mut paths: Vec<PathBuf> =
+            std::env::split_paths(&path).filter(|p| *p != bin).collect();
+        if venv.is_dir() {
+            paths.insert(0, bin);
+            self.env.remove("PYTHONHOME");
+        } else {
+            self.env.remove("VIRTUAL_ENV");
+            self.env.remove("VIRTUAL_ENV_PROMPT");
+        }
+        if let Some(path) = std::env::join_paths(paths)
+            .ok()
+            .and_then(|path| path.into_string().ok())
+        {
+            self.env.insert("PATH".into(), path);
+        }
+        self
+    }
+
+    pub fn maybe_ssh(mut self, ssh: Option<String>) -> Self {
+        self.ssh = ssh;
+        self
+    }
+
+    pub fn maybe_container(
+        mut self,
+        container: Option<container::Container>,
+    ) -> Self {
+        self.container = container;
+        self
+    }
+
     pub fn set_vim(mut self, vim: vim::Vim) -> Self {
         self.application = Application::Vim(vim);
         self
@@ -616,6 +3436,29 @@ impl LaunchingData {
         }
     }
 
+    pub fn set_foreground_nvim(mut self, pids: Vec<i32>) -> Self {
+        self.foreground_nvim = pids;
+        self
+    }
+
+    pub fn set_foreground(mut self, pids: Vec<i32>) -> Self {
+        self.foreground = Some(pids);
+        self
+    }
+
+    pub fn set_legacy_vim(mut self, server: vimserver::VimServer) -> Self {
+        self.application = Application::LegacyVim(server);
+        self
+    }
+
+    pub fn get_legacy_vim(&mut self) -> Option<&mut vimserver::VimServer> {
+        if let Application::LegacyVim(ref mut server) = self.application {
+            Some(server)
+        } else {
+            None
+        }
+    }
+
     pub fn set_kitty(mut self, kitty: kitty::KittySocket) -> Self {
         self.application = Application::Kitty(kitty);
         self
@@ -637,6 +3480,10 @@ impl NiriActionDirection {
             down: niri_ipc::Action::FocusWindowOrWorkspaceDown {},
             left: niri_ipc::Action::FocusColumnOrMonitorLeft {},
             right: niri_ipc::Action::FocusColumnOrMonitorRight {},
+            first: niri_ipc::Action::FocusColumnFirst {},
+            last: niri_ipc::Action::FocusColumnLast {},
+            next: niri_ipc::Action::FocusWindowDownOrColumnRight {},
+            prev: niri_ipc::Action::FocusWindowUpOrColumnLeft {},
         }
     }
 
@@ -646,6 +3493,53 @@ impl NiriActionDirection {
             down: niri_ipc::Action::MoveWindowDownOrToWorkspaceDown {},
             left: niri_ipc::Action::MoveColumnLeftOrToMonitorLeft {},
             right: niri_ipc::Action::MoveColumnRightOrToMonitorRight {},
+            first: niri_ipc::Action::MoveColumnToFirst {},
+            last: niri_ipc::Action::MoveColumnToLast {},
+            next: niri_ipc::Action::MoveColumnRight {},
+            prev: niri_ipc::Action::MoveColumnLeft {},
+        }
+    }
+
+    /// niri has no first and last monitor actions, so they fall back to the
+    /// left and right neighbours
+    pub fn new_focus_monitor() -> Self {
+        Self {
+            up: niri_ipc::Action::FocusMonitorUp {},
+            down: niri_ipc::Action::FocusMonitorDown {},
+            left: niri_ipc::Action::FocusMonitorLeft {},
+            right: niri_ipc::Action::FocusMonitorRight {},
+            first: niri_ipc::Action::FocusMonitorLeft {},
+            last: niri_ipc::Action::FocusMonitorRight {},
+            next: niri_ipc::Action::FocusMonitorNext {},
+            prev: niri_ipc::Action::FocusMonitorPrevious {},
+        }
+    }
+
+    /// Same as [Self::new_focus_monitor] but moves the focused column
+    pub fn new_move_monitor() -> Self {
+        Self {
+            up: niri_ipc::Action::MoveColumnToMonitorUp {},
+            down: niri_ipc::Action::MoveColumnToMonitorDown {},
+            left: niri_ipc::Action::MoveColumnToMonitorLeft {},
+            right: niri_ipc::Action::MoveColumnToMonitorRight {},
+            first: niri_ipc::Action::MoveColumnToMonitorLeft {},
+            last: niri_ipc::Action::MoveColumnToMonitorRight {},
+            next: niri_ipc::Action::MoveColumnToMonitorNext {},
+            prev: niri_ipc::Action::MoveColumnToMonitorPrevious {},
+        }
+    }
+
+    /// Windows within column are swapped by moving them
+    pub fn new_swap() -> Self {
+        Self {
+            up: niri_ipc::Action::MoveWindowUp {},
+            down: niri_ipc::Action::MoveWindowDown {},
+            left: niri_ipc::Action::SwapWindowLeft {},
+            right: niri_ipc::Action::SwapWindowRight {},
+            first: niri_ipc::Action::MoveColumnToFirst {},
+            last: niri_ipc::Action::MoveColumnToLast {},
+            next: niri_ipc::Action::SwapWindowRight {},
+            prev: niri_ipc::Action::SwapWindowLeft {},
         }
     }
 
@@ -655,11 +3549,67 @@ impl NiriActionDirection {
             Direction::Down => self.down,
             Direction::Left => self.left,
             Direction::Right => self.right,
+            Direction::First => self.first,
+            Direction::Last => self.last,
+            Direction::Next => self.next,
+            Direction::Prev => self.prev,
+        }
+    }
+}
+
+impl Workspace {
+    fn niri_action(&self) -> Result<niri_ipc::Action> {
+        Ok(match self {
+            Workspace::Focus { reference } => {
+                niri_ipc::Action::FocusWorkspace {
+                    reference: reference.clone(),
+                }
+            }
+            Workspace::SetName { name, workspace } => {
+                niri_ipc::Action::SetWorkspaceName {
+                    name: name.clone(),
+                    workspace: workspace.clone(),
+                }
+            }
+            Workspace::UnsetName { workspace } => {
+                niri_ipc::Action::UnsetWorkspaceName {
+                    reference: workspace.clone(),
+                }
+            }
+            Workspace::Previous => niri_ipc::Action::FocusWorkspacePrevious {},
+            Workspace::Clean { .. } | Workspace::List => {
+                Err("Not a niri workspace action")?
+            }
+        })
+    }
+}
+
+impl Tab {
+    fn vim_command(&self) -> &'static str {
+        match self {
+            Tab::New => "tabnew",
+            Tab::Next => "tabnext",
+            Tab::Prev => "tabprevious",
+            Tab::Close => "tabclose",
         }
     }
 
-    pub fn mk_request(self, direction: &Direction) -> niri_ipc::Request {
-        niri_ipc::Request::Action(self.mk_action(direction))
+    fn kitty_action(&self) -> &'static str {
+        match self {
+            Tab::New => "new_tab_with_cwd",
+            Tab::Next => "next_tab",
+            Tab::Prev => "previous_tab",
+            Tab::Close => "close_tab",
+        }
+    }
+
+    fn niri_action(&self) -> niri_ipc::Action {
+        match self {
+            Tab::New => niri_ipc::Action::ToggleColumnTabbedDisplay {},
+            Tab::Next => niri_ipc::Action::FocusWorkspaceDown {},
+            Tab::Prev => niri_ipc::Action::FocusWorkspaceUp {},
+            Tab::Close => niri_ipc::Action::CloseWindow { id: None },
+        }
     }
 }
 
@@ -677,6 +3627,196 @@ impl Direction {
             Direction::Down => "Down",
             Direction::Left => "Left",
             Direction::Right => "Right",
+            Direction::First => "First",
+            Direction::Last => "Last",
+            Direction::Next => "Next",
+            Direction::Prev => "Prev",
+        }
+    }
+
+    /// Key of vim `<C-w>` command which focuses window in direction
+    fn vim_key(&self) -> &'static str {
+        match self {
+            Direction::Up => "k",
+            Direction::Down => "j",
+            Direction::Left => "h",
+            Direction::Right => "l",
+            Direction::First => "t",
+            Direction::Last => "b",
+            Direction::Next => "w",
+            Direction::Prev => "W",
+        }
+    }
+
+    /// Key of vim `<C-w>` command which, given a big count, focuses the
+    /// window at the opposite edge
+    fn vim_wrap_key(&self) -> &'static str {
+        match self {
+            Direction::Up => "j",
+            Direction::Down => "k",
+            Direction::Left => "l",
+            Direction::Right => "h",
+            Direction::Next => "t",
+            Direction::Prev => "b",
+            Direction::First | Direction::Last => self.vim_key(),
+        }
+    }
+
+    /// Whenever resizing in direction changes width (or height) and whenever
+    /// it grows the window
+    fn resize_axis(&self) -> Result<(bool, bool)> {
+        Ok(match self {
+            Direction::Right => (true, true),
+            Direction::Left => (true, false),
+            Direction::Down => (false, true),
+            Direction::Up => (false, false),
+            _ => Err("Only up, down, left and right resize is supported")?,
+        })
+    }
+
+    /// Vim expression evaluating to number of window in direction
+    fn vim_winnr(&self) -> String {
+        match self {
+            Direction::First => "1".into(),
+            Direction::Last => "winnr('$')".into(),
+            Direction::Next => "winnr() + 1".into(),
+            Direction::Prev => "winnr() - 1".into(),
+            _ => format!("winnr('{}')", self.vim_key()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kitty::MockKitty;
+    use niri::{MockNiri, test_window};
+    use serde_json::json;
+
+    fn launcher(window: &str) -> Launcher {
+        let args = ["niri-integration", "--window", window, "equalize"];
+        Launcher::try_parse_from(args).unwrap()
+    }
+
+    fn niri() -> MockNiri {
+        let window = |id: u64, app_id: &str, title: &str| {
+            test_window(json!({"id": id, "app_id": app_id, "title": title}))
+        };
+        let mut windows = vec![
+            window(1, "kitty", "zsh"),
+            window(2, "neovide", "nvim"),
+            window(3, "kitty", "htop"),
+        ];
+        windows[2].is_focused = true;
+        MockNiri {
+            windows,
+            ..Default::default()
+        }
+    }
+
+    fn base_window(selector: &str) -> Result<Option<u64>> {
+        let window = launcher(selector).get_base_window(&mut niri())?;
+        Ok(window.map(|window| window.id))
+    }
+
+    #[test]
+    fn base_window_selectors() {
+        assert_eq!(base_window("focused").unwrap(), Some(3));
+        assert_eq!(base_window("2").unwrap(), Some(2));
+        assert!(matches!(base_window("4"), Err(Error::WindowNotFound(4))));
+        // Focused window is preferred among matching ones
+        assert_eq!(base_window("app-id:kitty").unwrap(), Some(3));
+        assert_eq!(base_window("title:/^nv/").unwrap(), Some(2));
+        assert!(matches!(
+            base_window("app-id:firefox"),
+            Err(Error::NoMatchingWindow(_))
+        ));
+        assert!(base_window("pointer").is_err());
+    }
+
+    fn kitty_window(id: u64, title: &str, cwd: &str) -> kitty::Window {
+        serde_json::from_value(json!({
+            "id": id,
+            "is_active": true,
+            "is_focused": false,
+            "title": title,
+            "cmdline": ["zsh"],
+            "cwd": cwd,
+            "env": {},
+        }))
+        .unwrap()
+    }
+
+    fn os_window(focused: bool, window: kitty::Window) -> kitty::OsWindow {
+        kitty::OsWindow {
+            is_active: focused,
+            is_focused: focused,
+            tabs: vec![kitty::Tab {
+                id: window.id,
+                is_active: true,
+                is_focused: focused,
+                layout: "tall".into(),
+                windows: vec![window],
+            }],
+        }
+    }
+
+    fn kitty() -> MockKitty {
+        MockKitty {
+            windows: vec![
+                os_window(false, kitty_window(1, "zsh", "/home/user/project")),
+                os_window(true, kitty_window(2, "htop", "/tmp")),
+            ],
+            ..Default::default()
         }
     }
+
+    #[test]
+    fn kitty_os_window_of_niri_window() {
+        let windows = kitty().windows;
+        let niri = niri();
+        let found = |niri_window: &niri_ipc::Window| {
+            Launcher::find_kitty_os_window(windows.clone(), niri_window)
+                .and_then(|os_window| os_window.active_window().cloned())
+                .map(|window| window.id)
+        };
+        assert_eq!(found(&niri.windows[0]), Some(1));
+        assert_eq!(found(&niri.windows[2]), Some(2));
+        assert_eq!(found(&niri.windows[1]), None);
+    }
+
+    #[test]
+    fn kitty_working_in() {
+        let launcher = launcher("focused");
+        let niri = niri();
+        let data = |cwd: Option<&str>| LaunchingData::default().maybe_cwd(cwd);
+        let working_in = |window: &niri_ipc::Window, cwd: Option<&str>| {
+            launcher
+                .is_kitty_working_in(&mut kitty(), window, &data(cwd))
+                .unwrap()
+        };
+        assert!(working_in(&niri.windows[0], Some("/home/user/project")));
+        // Other OS window of the same kitty is not considered
+        assert!(!working_in(&niri.windows[0], Some("/tmp")));
+        assert!(working_in(&niri.windows[2], Some("/tmp")));
+        assert!(!working_in(&niri.windows[0], None));
+    }
+
+    #[test]
+    fn zoom_kitty_toggles_stack_layout() {
+        let mut kitty = kitty();
+        Launcher::zoom_kitty(&mut kitty).unwrap();
+        assert!(matches!(
+            kitty.commands.as_slice(),
+            [kitty::Command::GotoLayout(_)]
+        ));
+
+        kitty.windows[1].tabs[0].layout = "stack".into();
+        kitty.commands.clear();
+        Launcher::zoom_kitty(&mut kitty).unwrap();
+        assert!(matches!(
+            kitty.commands.as_slice(),
+            [kitty::Command::LastUsedLayout(_)]
+        ));
+    }
 }
@@ -0,0 +1,83 @@
+//! Minimal [log] backend writing to stderr or to file
+//!
+//! Verbosity is counted from warnings: no `-v` shows warnings and errors,
+//! `-v` adds info, `-vv` adds debug and `-vvv` everything.
+
+use crate::error::Result;
+use log::{Level, Log, Metadata, Record};
+use std::{
+    fs,
+    io::{self, Write},
+    path::Path,
+    sync::Mutex,
+    time::SystemTime,
+};
+
+struct Logger {
+    target: Mutex<Box<dyn Write + Send>>,
+    /// Whenever to prefix records with time, files are read later
+    timestamps: bool,
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let Ok(mut target) = self.target.lock() else {
+            return;
+        };
+        if self.timestamps {
+            let now = SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap_or_default();
+            let _ =
+                write!(target, "{}.{:03} ", now.as_secs(), now.subsec_millis());
+        }
+        let _ = writeln!(
+            target,
+            "[{} {}] {}",
+            record.level(),
+            record.module_path().unwrap_or_default(),
+            record.args()
+        );
+    }
+
+    fn flush(&self) {
+        if let Ok(mut target) = self.target.lock() {
+            let _ = target.flush();
+        }
+    }
+}
+
+/// Install logger of given verbosity, appending to `file` if passed
+pub fn init(verbosity: u8, file: Option<&Path>) -> Result<()> {
+    let level = match verbosity {
+        0 => Level::Warn,
+        1 => Level::Info,
+        2 => Level::Debug,
+        _ => Level::Trace,
+    };
+    let target: Box<dyn Write + Send> = match file {
+        Some(file) => Box::new(
+            fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(file)?,
+        ),
+        None => Box::new(io::stderr()),
+    };
+    let logger = Logger {
+        target: Mutex::new(target),
+        timestamps: file.is_some(),
+    };
+    // Logger installed earlier (e.g. by daemon running remote command) is kept
+    if log::set_boxed_logger(Box::new(logger)).is_ok() {
+        log::set_max_level(level.to_level_filter());
+    }
+    Ok(())
+}
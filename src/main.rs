@@ -1,7 +1,14 @@
-use niri_integration::{Launcher, Parser, error::Result};
+use niri_integration::{Format, Launcher, Parser};
 
-fn main() -> Result<()> {
+fn main() {
     let args = Launcher::parse();
 
-    args.run()
+    let format = args.format();
+    if let Err(err) = args.run() {
+        match format {
+            Format::Text => eprintln!("Error: {err}"),
+            Format::Json => eprintln!("{}", err.to_json()),
+        }
+        std::process::exit(err.exit_code());
+    }
 }
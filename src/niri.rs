@@ -0,0 +1,403 @@
+//! Small helpers around niri IPC requests which unwrap typed responses.
+
+use crate::{
+    Direction, dryrun,
+    error::{Error, Result},
+    trace,
+};
+use niri_ipc::{Action, Reply, Request, Response, Window, Workspace};
+use std::io;
+
+/// Transport of niri requests
+///
+/// Implemented by the real niri socket, by [MockNiri] and by recorded
+/// [trace::Replay], so the logic built on top of requests is testable.
+pub trait NiriTransport {
+    /// Send request and wait for reply
+    fn send(&mut self, request: Request) -> io::Result<Reply>;
+
+    /// Forget replies kept by [Snapshot], e.g. while waiting for new window
+    fn forget(&mut self) {}
+}
+
+/// Niri connection used across the crate
+pub type Socket<'a> = dyn NiriTransport + 'a;
+
+impl NiriTransport for niri_ipc::socket::Socket {
+    fn send(&mut self, request: Request) -> io::Result<Reply> {
+        niri_ipc::socket::Socket::send(self, request)
+    }
+}
+
+impl NiriTransport for trace::Replay {
+    fn send(&mut self, request: Request) -> io::Result<Reply> {
+        let reply = self
+            .respond("niri", &request)
+            .map_err(|err| io::Error::other(err.to_string()))?;
+        Ok(serde_json::from_value(reply)?)
+    }
+}
+
+/// In-memory niri serving fixed state and recording actions
+///
+/// Focus actions change focused window, windows are placed in columns by
+/// their `pos_in_scrolling_layout`.
+#[cfg(any(test, feature = "lib"))]
+#[derive(Debug, Clone, Default)]
+pub struct MockNiri {
+    /// Windows returned by queries
+    pub windows: Vec<Window>,
+    /// Workspaces returned by queries
+    pub workspaces: Vec<Workspace>,
+    /// Outputs returned by queries, by name
+    pub outputs: std::collections::HashMap<String, niri_ipc::Output>,
+    /// Actions received so far
+    pub actions: Vec<Action>,
+}
+
+#[cfg(any(test, feature = "lib"))]
+impl NiriTransport for MockNiri {
+    fn send(&mut self, request: Request) -> io::Result<Reply> {
+        Ok(Ok(match request {
+            Request::Windows => Response::Windows(self.windows.clone()),
+            Request::Workspaces => {
+                Response::Workspaces(self.workspaces.clone())
+            }
+            Request::Outputs => Response::Outputs(self.outputs.clone()),
+            Request::FocusedWindow => Response::FocusedWindow(
+                self.windows.iter().find(|win| win.is_focused).cloned(),
+            ),
+            Request::Action(action) => {
+                self.focus(&action);
+                self.actions.push(action);
+                Response::Handled
+            }
+            request => {
+                return Ok(Err(format!("Unsupported request {request:?}")));
+            }
+        }))
+    }
+}
+
+#[cfg(any(test, feature = "lib"))]
+impl MockNiri {
+    /// Move focus as niri does on focus action
+    fn focus(&mut self, action: &Action) {
+        let target = match action {
+            Action::FocusWindow { id } => Some(*id),
+            Action::FocusColumnRight {} => self.neighbour_column(1),
+            Action::FocusColumnLeft {} => self.neighbour_column(-1),
+            _ => None,
+        };
+        let Some(target) = target else {
+            return;
+        };
+        if self.windows.iter().any(|win| win.id == target) {
+            for window in self.windows.iter_mut() {
+                window.is_focused = window.id == target;
+            }
+        }
+    }
+
+    /// Top window of column `step` columns right of the focused one
+    fn neighbour_column(&self, step: isize) -> Option<u64> {
+        let focused = self.windows.iter().find(|win| win.is_focused)?;
+        let (column, _) = focused.layout.pos_in_scrolling_layout?;
+        let column = column.checked_add_signed(step)?;
+        self.windows
+            .iter()
+            .filter(|win| win.workspace_id == focused.workspace_id)
+            .find(|win| win.layout.pos_in_scrolling_layout == Some((column, 1)))
+            .map(|win| win.id)
+    }
+}
+
+/// Transport answering repeated queries of niri state from the first reply
+///
+/// One command sees consistent niri state and issues each query at most
+/// once. Any other request, e.g. action, may change the state, so replies
+/// are forgotten then.
+pub struct Snapshot<'a, 's> {
+    soc: &'a mut Socket<'s>,
+    replies: Vec<(Request, Reply)>,
+}
+
+impl<'a, 's> Snapshot<'a, 's> {
+    /// Take snapshots of niri state over connection `soc`
+    pub fn new(soc: &'a mut Socket<'s>) -> Self {
+        Self {
+            soc,
+            replies: Vec::new(),
+        }
+    }
+}
+
+impl NiriTransport for Snapshot<'_, '_> {
+    fn send(&mut self, request: Request) -> io::Result<Reply> {
+        let query = matches!(
+            request,
+            Request::Windows
+                | Request::Workspaces
+                | Request::Outputs
+                | Request::FocusedWindow
+                | Request::FocusedOutput
+        );
+        if !query {
+            self.replies.clear();
+            return self.soc.send(request);
+        }
+        let cached = self.replies.iter().find(|(cached, _)| {
+            std::mem::discriminant(cached) == std::mem::discriminant(&request)
+        });
+        if let Some((_, reply)) = cached {
+            return Ok(reply.clone());
+        }
+        let reply = self.soc.send(request.clone())?;
+        self.replies.push((request, reply.clone()));
+        Ok(reply)
+    }
+
+    fn forget(&mut self) {
+        self.replies.clear();
+        self.soc.forget();
+    }
+}
+
+/// Send request to niri, the exchange is recorded to IPC trace
+pub fn request(soc: &mut Socket, request: Request) -> Result<Response> {
+    let reply = soc.send(request.clone())?;
+    trace::record("niri", &request, &reply);
+    Ok(reply?)
+}
+
+/// Perform niri action
+pub fn action(soc: &mut Socket, action: Action) -> Result<()> {
+    log::debug!("niri action {action:?}");
+    if !dryrun::skip(format_args!("niri action {action:?}")) {
+        request(soc, Request::Action(action))?;
+    }
+    Ok(())
+}
+
+/// Get list of all niri windows
+pub fn windows(soc: &mut Socket) -> Result<Vec<Window>> {
+    match request(soc, Request::Windows)? {
+        Response::Windows(windows) => Ok(windows),
+        _ => Err(Error::from("Unexpected response type for Windows")),
+    }
+}
+
+/// Get list of all niri workspaces
+pub fn workspaces(soc: &mut Socket) -> Result<Vec<Workspace>> {
+    match request(soc, Request::Workspaces)? {
+        Response::Workspaces(workspaces) => Ok(workspaces),
+        _ => Err(Error::from("Unexpected response type for Workspaces")),
+    }
+}
+
+/// Get currently focused niri window
+pub fn focused_window(soc: &mut Socket) -> Result<Option<Window>> {
+    match request(soc, Request::FocusedWindow)? {
+        Response::FocusedWindow(window) => Ok(window),
+        _ => Err(Error::from("Unexpected response type for FocusedWindow")),
+    }
+}
+
+/// Let user pick window interactively with niri
+///
+/// Returns [None] if picking was cancelled.
+pub fn pick_window(soc: &mut Socket) -> Result<Option<Window>> {
+    match request(soc, Request::PickWindow)? {
+        Response::PickedWindow(window) => Ok(window),
+        _ => Err(Error::from("Unexpected response type for PickWindow")),
+    }
+}
+
+/// Position of focused window relative to the workspace edge in direction
+pub enum Edge {
+    /// There is a neighbour window in direction
+    Inner,
+    /// Focused window is at the edge, the action focuses the opposite edge
+    Wrap(Action),
+    /// Focused window is the only one in direction, there is nothing to wrap
+    Single,
+}
+
+/// Locate focused window relative to the edge of its workspace in direction
+pub fn edge(soc: &mut Socket, direction: &Direction) -> Result<Edge> {
+    let Some(window) = focused_window(soc)? else {
+        return Ok(Edge::Inner);
+    };
+    let Some((column, tile)) = window.layout.pos_in_scrolling_layout else {
+        return Ok(Edge::Inner);
+    };
+    let positions: Vec<_> = windows(soc)?
+        .into_iter()
+        .filter(|w| w.workspace_id == window.workspace_id)
+        .filter_map(|w| w.layout.pos_in_scrolling_layout)
+        .collect();
+    let columns = positions.iter().map(|p| p.0).max().unwrap_or(column);
+    let tiles = positions
+        .iter()
+        .filter(|p| p.0 == column)
+        .map(|p| p.1)
+        .max()
+        .unwrap_or(tile);
+    let (at_edge, single, wrap) = match direction {
+        Direction::Up => (tile == 1, tiles == 1, Action::FocusWindowBottom {}),
+        Direction::Down => {
+            (tile == tiles, tiles == 1, Action::FocusWindowTop {})
+        }
+        Direction::Left => {
+            (column == 1, columns == 1, Action::FocusColumnLast {})
+        }
+        Direction::Right => {
+            (column == columns, columns == 1, Action::FocusColumnFirst {})
+        }
+        Direction::Prev => (
+            column == 1 && tile == 1,
+            columns == 1 && tiles == 1,
+            Action::FocusColumnLast {},
+        ),
+        Direction::Next => (
+            column == columns && tile == tiles,
+            columns == 1 && tiles == 1,
+            Action::FocusColumnFirst {},
+        ),
+        Direction::First | Direction::Last => return Ok(Edge::Inner),
+    };
+    Ok(if !at_edge {
+        Edge::Inner
+    } else if single {
+        Edge::Single
+    } else {
+        Edge::Wrap(wrap)
+    })
+}
+
+/// Window made of JSON fields, the rest is filled with defaults
+#[cfg(test)]
+pub(crate) fn test_window(fields: serde_json::Value) -> Window {
+    let mut window = serde_json::json!({
+        "id": 1,
+        "title": null,
+        "app_id": null,
+        "pid": null,
+        "workspace_id": 1,
+        "is_focused": false,
+        "is_floating": false,
+        "is_urgent": false,
+        "focus_timestamp": null,
+        "layout": {
+            "pos_in_scrolling_layout": null,
+            "tile_size": [800.0, 600.0],
+            "window_size": [800, 600],
+            "tile_pos_in_workspace_view": null,
+            "window_offset_in_tile": [0.0, 0.0],
+        },
+    });
+    merge(&mut window, fields);
+    serde_json::from_value(window).unwrap()
+}
+
+/// Override fields of `into` by `from` recursively
+#[cfg(test)]
+fn merge(into: &mut serde_json::Value, from: serde_json::Value) {
+    use serde_json::Value;
+    match (into, from) {
+        (Value::Object(into), Value::Object(from)) => {
+            for (key, value) in from {
+                merge(into.entry(key).or_insert(Value::Null), value);
+            }
+        }
+        (into, from) => *into = from,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    /// Transport counting requests which reach niri
+    struct Counting {
+        niri: MockNiri,
+        requests: usize,
+    }
+
+    impl NiriTransport for Counting {
+        fn send(&mut self, request: Request) -> io::Result<Reply> {
+            self.requests += 1;
+            self.niri.send(request)
+        }
+    }
+
+    fn niri() -> MockNiri {
+        MockNiri {
+            windows: vec![
+                test_window(json!({"id": 1, "is_focused": true})),
+                test_window(json!({"id": 2})),
+            ],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn snapshot_caches_queries() {
+        let mut counting = Counting {
+            niri: niri(),
+            requests: 0,
+        };
+        let mut snapshot = Snapshot::new(&mut counting);
+        assert_eq!(windows(&mut snapshot).unwrap().len(), 2);
+        assert_eq!(windows(&mut snapshot).unwrap().len(), 2);
+        let focused = focused_window(&mut snapshot).unwrap();
+        assert_eq!(focused.map(|win| win.id), Some(1));
+        focused_window(&mut snapshot).unwrap();
+        drop(snapshot);
+        assert_eq!(counting.requests, 2);
+    }
+
+    #[test]
+    fn snapshot_forgets_after_action() {
+        let mut counting = Counting {
+            niri: niri(),
+            requests: 0,
+        };
+        let mut snapshot = Snapshot::new(&mut counting);
+        focused_window(&mut snapshot).unwrap();
+        action(&mut snapshot, Action::FocusWindow { id: 2 }).unwrap();
+        let focused = focused_window(&mut snapshot).unwrap();
+        assert_eq!(focused.map(|win| win.id), Some(2));
+        windows(&mut snapshot).unwrap();
+        snapshot.forget();
+        windows(&mut snapshot).unwrap();
+        drop(snapshot);
+        assert_eq!(counting.requests, 5);
+        assert_eq!(counting.niri.actions.len(), 1);
+    }
+
+    #[test]
+    fn mock_focuses_columns() {
+        let column = |id, column| {
+            test_window(json!({
+                "id": id,
+                "is_focused": id == 1,
+                "layout": {"pos_in_scrolling_layout": [column, 1]},
+            }))
+        };
+        let mut niri = MockNiri {
+            windows: vec![column(1, 1), column(2, 2)],
+            ..Default::default()
+        };
+        let focused = |niri: &mut MockNiri| {
+            focused_window(niri).unwrap().map(|win| win.id)
+        };
+        action(&mut niri, Action::FocusColumnLeft {}).unwrap();
+        assert_eq!(focused(&mut niri), Some(1));
+        action(&mut niri, Action::FocusColumnRight {}).unwrap();
+        assert_eq!(focused(&mut niri), Some(2));
+        action(&mut niri, Action::FocusColumnRight {}).unwrap();
+        assert_eq!(focused(&mut niri), Some(2));
+    }
+}
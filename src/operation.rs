@@ -0,0 +1,82 @@
+//! Multi-step operations with timeouts and rollback
+//!
+//! Commands which spawn windows, wait for them and then move or resize them
+//! must not leave the layout in half-applied state. The [Operation] tracks the
+//! deadline of such command and the list of rollback steps which are executed
+//! in reverse order when any step fails.
+
+use crate::error::{Error, Result};
+use crate::niri::Socket;
+use std::{
+    thread,
+    time::{Duration, Instant},
+};
+
+/// Delay between polls of [Operation::wait_for]
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+type Rollback<'a> = Box<dyn FnOnce(&mut Socket) -> Result<()> + 'a>;
+
+pub struct Operation<'a> {
+    name: String,
+    deadline: Instant,
+    rollbacks: Vec<Rollback<'a>>,
+}
+
+impl<'a> Operation<'a> {
+    pub fn new<S: Into<String>>(name: S, timeout: Duration) -> Self {
+        Self {
+            name: name.into(),
+            deadline: Instant::now() + timeout,
+            rollbacks: Vec::new(),
+        }
+    }
+
+    /// Fail if the operation is out of time
+    pub fn check(&self) -> Result<()> {
+        if Instant::now() >= self.deadline {
+            Err(Error::from(format!("{}: timed out", self.name)))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Register step which reverts already applied changes
+    pub fn on_rollback<F>(&mut self, rollback: F)
+    where
+        F: FnOnce(&mut Socket) -> Result<()> + 'a,
+    {
+        self.rollbacks.push(Box::new(rollback));
+    }
+
+    /// Poll until `poll` returns value, fails or the operation is out of time
+    pub fn wait_for<T, F>(&self, mut poll: F) -> Result<T>
+    where
+        F: FnMut() -> Result<Option<T>>,
+    {
+        loop {
+            if let Some(value) = poll()? {
+                return Ok(value);
+            }
+            self.check()?;
+            thread::sleep(POLL_INTERVAL);
+        }
+    }
+
+    /// Run the body of operation
+    ///
+    /// On failure all registered rollback steps are executed. Errors of
+    /// rollback steps are ignored, the original error is returned.
+    pub fn run<T, F>(mut self, soc: &mut Socket, body: F) -> Result<T>
+    where
+        F: FnOnce(&mut Self, &mut Socket) -> Result<T>,
+    {
+        let res = body(&mut self, soc);
+        if res.is_err() {
+            while let Some(rollback) = self.rollbacks.pop() {
+                let _ = rollback(soc);
+            }
+        }
+        res
+    }
+}
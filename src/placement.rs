@@ -0,0 +1,253 @@
+//! Placement of windows launched by this tool
+//!
+//! Rules of `placement` section of configuration are matched against source
+//! of the base window and the focused workspace before launch, and against
+//! app id of the launched window once it appears. The program of launch with
+//! matching rules is spawned instead of replacing this process, so its window
+//! can be waited and moved with niri actions.
+
+use crate::{
+    config::{Placement, PlacementRule, Width},
+    error::Result,
+    niri::{self, Socket},
+    operation::Operation,
+};
+use niri_ipc::{Action, SizeChange, Window, Workspace, WorkspaceReferenceArg};
+use regex::Regex;
+use std::{collections::HashSet, time::Duration};
+
+/// Time given to launched program to show its window
+const PLACEMENT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// State of niri before launch
+pub struct Origin {
+    /// Window the launch is made from
+    base: Option<Window>,
+    /// Name of source of the base window
+    source: Option<&'static str>,
+    /// Focused workspace
+    workspace: Option<Workspace>,
+    /// Ids of windows existing before launch
+    known: HashSet<u64>,
+}
+
+impl Origin {
+    pub fn new(
+        soc: &mut Socket,
+        base: Option<Window>,
+        source: Option<&'static str>,
+    ) -> Result<Self> {
+        let workspace = niri::workspaces(soc)?
+            .into_iter()
+            .find(|workspace| workspace.is_focused);
+        let known = niri::windows(soc)?.iter().map(|win| win.id).collect();
+        Ok(Self {
+            base,
+            source,
+            workspace,
+            known,
+        })
+    }
+
+    /// Rules which may apply to window launched from the origin
+    pub fn candidates<'a>(
+        &self,
+        rules: &'a [PlacementRule],
+    ) -> Vec<&'a PlacementRule> {
+        rules
+            .iter()
+            .filter(|rule| {
+                rule.source
+                    .as_deref()
+                    .is_none_or(|source| Some(source) == self.source)
+            })
+            .filter(|rule| {
+                rule.workspace.as_deref().is_none_or(|name| {
+                    self.workspace.as_ref().is_some_and(|workspace| {
+                        workspace.name.as_deref() == Some(name)
+                            || workspace.idx.to_string() == name
+                    })
+                })
+            })
+            .collect()
+    }
+
+    /// Wait for new window matching any of rules and place it
+    ///
+    /// The first rule matching app id of the window is applied. The window
+    /// failed to be placed is closed, so it is not left half-placed.
+    pub fn place(
+        &self,
+        soc: &mut Socket,
+        rules: &[&PlacementRule],
+    ) -> Result<()> {
+        let patterns = rules
+            .iter()
+            .map(|rule| Regex::new(&rule.app_id))
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+        let op = Operation::new("place launched window", PLACEMENT_TIMEOUT);
+        op.run(soc, |op, soc| {
+            let (window, rule) = op.wait_for(|| {
+                soc.forget();
+                let found = niri::windows(soc)?
+                    .into_iter()
+                    .filter(|win| !self.known.contains(&win.id))
+                    .find_map(|win| {
+                        let app_id = win.app_id.as_deref().unwrap_or_default();
+                        let index = patterns
+                            .iter()
+                            .position(|pattern| pattern.is_match(app_id))?;
+                        Some((win, rules[index]))
+                    });
+                Ok(found)
+            })?;
+            log::debug!("Placing window {} by {rule:?}", window.id);
+            let id = Some(window.id);
+            op.on_rollback(move |soc| {
+                niri::action(soc, Action::CloseWindow { id })
+            });
+            self.apply(soc, &window, rule)
+        })
+    }
+
+    fn apply(
+        &self,
+        soc: &mut Socket,
+        window: &Window,
+        rule: &PlacementRule,
+    ) -> Result<()> {
+        let id = window.id;
+        if let Some(position) = rule.position {
+            self.position(soc, window, position)?;
+        }
+        if let Some(width) = rule.width {
+            let change = match width {
+                Width::Pixels(px) => SizeChange::SetFixed(px as i32),
+                Width::Fraction(f) => SizeChange::SetProportion(f * 100.0),
+            };
+            let id = Some(id);
+            niri::action(soc, Action::SetWindowWidth { id, change })?;
+        }
+        niri::action(soc, Action::FocusWindow { id })
+    }
+
+    /// Move window to the position relative to the base window
+    fn position(
+        &self,
+        soc: &mut Socket,
+        window: &Window,
+        position: Placement,
+    ) -> Result<()> {
+        let id = window.id;
+        if position == Placement::Floating {
+            return niri::action(
+                soc,
+                Action::MoveWindowToFloating { id: Some(id) },
+            );
+        }
+        let Some(base) = &self.base else {
+            return Ok(());
+        };
+        let workspace = base
+            .workspace_id
+            .filter(|workspace| Some(*workspace) != window.workspace_id);
+        if let Some(workspace) = workspace {
+            niri::action(
+                soc,
+                Action::MoveWindowToWorkspace {
+                    window_id: Some(id),
+                    reference: WorkspaceReferenceArg::Id(workspace),
+                    focus: true,
+                },
+            )?;
+        }
+        // Columns of base and window may shift once window was opened or
+        // moved between workspaces
+        soc.forget();
+        let windows = niri::windows(soc)?;
+        let column = |id| {
+            windows
+                .iter()
+                .find(|win| win.id == id)
+                .and_then(|win| win.layout.pos_in_scrolling_layout)
+                .map(|(column, _)| column)
+        };
+        let (Some(base), Some(current)) = (column(base.id), column(id)) else {
+            return Ok(());
+        };
+        // Moved column is taken out before insertion, so columns right of
+        // it shift left
+        let index = match position {
+            Placement::Left if current < base => base - 1,
+            Placement::Left => base,
+            _ if current < base => base,
+            _ => base + 1,
+        };
+        niri::action(soc, Action::FocusWindow { id })?;
+        niri::action(soc, Action::MoveColumnToIndex { index })?;
+        if position == Placement::Consume {
+            niri::action(
+                soc,
+                Action::ConsumeOrExpelWindowLeft { id: Some(id) },
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::niri::{MockNiri, NiriTransport, test_window};
+    use niri_ipc::{Reply, Request};
+    use serde_json::json;
+    use std::io;
+
+    /// Transport failing resize actions
+    struct Failing {
+        niri: MockNiri,
+    }
+
+    impl NiriTransport for Failing {
+        fn send(&mut self, request: Request) -> io::Result<Reply> {
+            match request {
+                Request::Action(Action::SetWindowWidth { .. }) => {
+                    Ok(Err("Can not resize".into()))
+                }
+                request => self.niri.send(request),
+            }
+        }
+    }
+
+    #[test]
+    fn closes_window_failed_to_place() {
+        let mut soc = Failing {
+            niri: MockNiri {
+                windows: vec![
+                    test_window(json!({"id": 1, "is_focused": true})),
+                    test_window(json!({"id": 2, "app_id": "foot"})),
+                ],
+                ..Default::default()
+            },
+        };
+        let origin = Origin {
+            base: None,
+            source: None,
+            workspace: None,
+            known: HashSet::from([1]),
+        };
+        let rule = PlacementRule {
+            app_id: "^foot$".into(),
+            source: None,
+            workspace: None,
+            position: None,
+            width: Some(Width::Pixels(800)),
+        };
+        assert!(origin.place(&mut soc, &[&rule]).is_err());
+        let actions = &soc.niri.actions;
+        assert!(matches!(
+            actions.as_slice(),
+            [Action::CloseWindow { id: Some(2) }]
+        ));
+    }
+}
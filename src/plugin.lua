@@ -0,0 +1,47 @@
+-- Neovim integration with niri-integration
+--
+-- Generated by `niri-integration vim plugin`. Keeps neovide window width in
+-- sync with vim splits.
+
+if not vim.g.neovide then
+  return
+end
+
+local bin = @BIN@
+local window_id
+
+-- Neovide is the parent process of nvim, its niri window is looked up by pid
+local function find_window()
+  if window_id then
+    return window_id
+  end
+  local ppid = vim.uv.os_getppid()
+  local out = vim.fn.system({ "niri", "msg", "--json", "windows" })
+  local ok, windows = pcall(vim.json.decode, out)
+  if not ok then
+    return nil
+  end
+  for _, win in ipairs(windows) do
+    if win.pid == ppid then
+      window_id = win.id
+      return window_id
+    end
+  end
+end
+
+local function run(...)
+  local id = find_window()
+  if not id then
+    return
+  end
+  vim.system({ bin, "--window", tostring(id), "vim", ... }, { detach = true })
+end
+
+local group = vim.api.nvim_create_augroup("NiriIntegration", { clear = true })
+
+vim.api.nvim_create_autocmd({ "WinResized", "WinNew", "WinClosed" }, {
+  group = group,
+  callback = function()
+    run("sync")
+  end,
+})
@@ -1,6 +1,11 @@
+//! Process tree and properties of processes read from procfs
+//!
+//! Free functions read the system `/proc`, [ProcFs] reads procfs mounted
+//! elsewhere, e.g. fake one prepared by tests.
+
 // Copy-pasted from
 // [here](https://github.com/posborne/rust-pstree/blob/2ef62f0e2d05b95b68c321de2bcb3d3cf16f20b3/pstree.rs)
-use std::path::Path;
+use std::path::PathBuf;
 use std::fs;
 use std::io::prelude::*;
 use std::fs::File;
@@ -8,92 +13,50 @@ use std::collections::hash_map::Entry::{Occupied, Vacant};
 use std::collections::HashMap;
 use crate::error::Result;
 
+/// Process and its parent
 #[derive(Clone,Debug)]
 pub struct ProcessRecord {
+    /// Id of process
     pub pid: i32,
+    /// Id of parent process
     pub ppid: i32,
 }
 
+/// Process with its descendants
 #[derive(Clone,Debug)]
 pub struct ProcessTreeNode {
+    /// The process
     pub record: ProcessRecord,  // the node owns the associated record
+    /// Direct children of process
     pub children: Vec<ProcessTreeNode>, // nodes own their children
 }
 
+/// Tree of processes
 #[derive(Clone,Debug)]
 pub struct ProcessTree {
+    /// Root process, pid 0 for tree of all processes
     pub root: ProcessTreeNode, // tree owns ref to root node
+    /// Procfs the tree was read from
+    pub procfs: ProcFs,
 }
 
 impl ProcessTreeNode {
-    // constructor
+    /// Node of process without children
     pub fn new(record : &ProcessRecord) -> ProcessTreeNode {
         ProcessTreeNode { record: (*record).clone(), children: Vec::new() }
     }
 }
 
-// Given a status file path, return a hashmap with the following form:
-// pid -> ProcessRecord
-fn get_process_record(status_path: &Path) -> Option<ProcessRecord> {
-    let mut pid : Option<i32> = None;
-    let mut ppid : Option<i32> = None;
-
-    let file = File::open(status_path);
-    if file.is_err() {
-        return None;
-    }
-    let mut reader = std::io::BufReader::new(file.unwrap());
-    loop {
-        let mut linebuf = String::new();
-        match reader.read_line(&mut linebuf) {
-            Ok(_) => {
-                if linebuf.is_empty() {
-                    break;
-                }
-                let parts : Vec<&str> = linebuf[..].splitn(2, ':').collect();
-                if parts.len() == 2 {
-                    let key = parts[0].trim();
-                    let value = parts[1].trim();
-                    match key {
-                        "Pid" => pid = value.parse().ok(),
-                        "PPid" => ppid = value.parse().ok(),
-                        _ => (),
-                    }
-                }
-            },
-            Err(_) => break,
-        }
-    }
-    return if pid.is_some() && ppid.is_some() {
-        Some(ProcessRecord { pid: pid.unwrap(), ppid: ppid.unwrap() })
-    } else {
-        None
-    }
+/// Procfs mounted at some root, `/proc` by default
+#[derive(Clone, Debug)]
+pub struct ProcFs {
+    root: PathBuf,
 }
 
-
-// build a simple struct (ProcessRecord) for each process
-fn get_process_records() -> Result<Vec<ProcessRecord>> {
-    let proc_directory = Path::new("/proc");
-
-    // find potential process directories under /proc
-    let proc_directory_contents = fs::read_dir(&proc_directory)?;
-    fn process_entry(entry: std::io::Result<fs::DirEntry>) -> Result<Option<ProcessRecord>>
-    {
-        let entry_path = entry?.path();
-        if fs::metadata(entry_path.as_path())?.is_dir() {
-            let status_path = entry_path.join("status");
-            if let Ok(metadata) = fs::metadata(status_path.as_path()) {
-                if metadata.is_file() {
-                    return Ok(get_process_record(status_path.as_path()));
-                }
-            }
-        }
-        Ok(None)
+impl Default for ProcFs {
+    fn default() -> Self {
+        Self::new("/proc")
     }
-    Ok(proc_directory_contents.filter_map(|entry| {
-        process_entry(entry).unwrap_or(None)
-    }).collect())
 }
 
 fn populate_node_helper(node: &mut ProcessTreeNode, pid_map: &HashMap<i32, &ProcessRecord>, ppid_map: &HashMap<i32, Vec<i32>>) {
@@ -132,20 +95,453 @@ fn populate_node(node : &mut ProcessTreeNode, records: &Vec<ProcessRecord>) {
     populate_node_helper(node, &pid_map, &ppid_map);
 }
 
-pub fn build_process_tree(pid: Option<i32>) -> Result<ProcessTree> {
-    let records = get_process_records();
-    let mut tree = ProcessTree {
-        root : ProcessTreeNode::new(
-            &ProcessRecord {
+/// Names of processes which are treated as interactive shells
+const SHELLS: &[&str] = &[
+    "bash", "zsh", "fish", "sh", "dash", "ksh", "tcsh", "nu", "elvish", "xonsh",
+];
+
+impl ProcFs {
+    /// Procfs mounted at `root`
+    pub fn new<P: Into<PathBuf>>(root: P) -> Self {
+        Self { root: root.into() }
+    }
+
+    /// Path of file of process within procfs
+    fn path(&self, pid: i32, name: &str) -> PathBuf {
+        self.root.join(pid.to_string()).join(name)
+    }
+
+    // Given a pid, return the record of process
+    fn get_process_record(&self, pid: i32) -> Option<ProcessRecord> {
+        let stat = fs::read_to_string(self.path(pid, "stat")).ok()?;
+        // Name of process is in parentheses and may contain spaces and
+        // parentheses itself, so fields are counted from the last one
+        let (pid, rest) = stat.split_once(" (")?;
+        let (_, rest) = rest.rsplit_once(") ")?;
+        let ppid = rest.split_whitespace().nth(1)?;
+        Some(ProcessRecord {
+            pid: pid.parse().ok()?,
+            ppid: ppid.parse().ok()?,
+        })
+    }
+
+    // build a simple struct (ProcessRecord) for each process
+    fn get_process_records(&self) -> Result<Vec<ProcessRecord>> {
+        // only numeric entries of procfs are processes
+        Ok(fs::read_dir(&self.root)?
+            .filter_map(|entry| {
+                let name = entry.ok()?.file_name();
+                self.get_process_record(name.to_str()?.parse().ok()?)
+            })
+            .collect())
+    }
+
+    /// Whenever kernel lists children of tasks in
+    /// `/proc/<pid>/task/<tid>/children`
+    ///
+    /// The files are missing without `CONFIG_PROC_CHILDREN`.
+    fn children_supported(&self) -> bool {
+        self.root.join("thread-self/children").exists()
+    }
+
+    /// Get children of process started by any of its threads
+    ///
+    /// Processes which exit meanwhile are skipped.
+    fn read_children(&self, pid: i32) -> Vec<i32> {
+        let tasks = match fs::read_dir(self.path(pid, "task")) {
+            Ok(tasks) => tasks,
+            Err(_) => return Vec::new(),
+        };
+        tasks
+            .filter_map(|task| {
+                fs::read_to_string(task.ok()?.path().join("children")).ok()
+            })
+            .flat_map(|children| {
+                children
+                    .split_whitespace()
+                    .filter_map(|pid| pid.parse().ok())
+                    .collect::<Vec<i32>>()
+            })
+            .collect()
+    }
+
+    /// Populate subtree of node walking children files from the node down
+    fn populate_node_from_children(&self, node: &mut ProcessTreeNode) {
+        let pid = node.record.pid;
+        let children = self.read_children(pid).into_iter().map(|child_pid| {
+            let record = ProcessRecord { pid: child_pid, ppid: pid };
+            let mut child = ProcessTreeNode::new(&record);
+            self.populate_node_from_children(&mut child);
+            child
+        });
+        node.children.extend(children);
+    }
+
+    /// Build tree of process `pid` and its descendants, of all processes
+    /// without `pid`
+    ///
+    /// Descendants are found by walking children files from `pid` down,
+    /// which is much cheaper than reading every process of the system. The
+    /// latter is the fallback when kernel does not provide children files.
+    pub fn build_process_tree(&self, pid: Option<i32>) -> Result<ProcessTree> {
+        let mut tree = ProcessTree {
+            root: ProcessTreeNode::new(&ProcessRecord {
                 pid: pid.unwrap_or(0),
-                ppid: -1
+                ppid: -1,
+            }),
+            procfs: self.clone(),
+        };
+
+        if pid.is_some() && self.children_supported() {
+            self.populate_node_from_children(&mut tree.root);
+            return Ok(tree);
+        }
+
+        // recursively populate all nodes in the tree starting from root (pid 0)
+        let records = self.get_process_records()?;
+        log::trace!("Building process tree of {} processes", records.len());
+        populate_node(&mut tree.root, &records);
+        Ok(tree)
+    }
+
+    /// Get the process and all its ancestors, starting from the process itself
+    pub fn ancestors(&self, pid: i32) -> Result<Vec<i32>> {
+        let parent =
+            |pid| self.get_process_record(pid).map(|record| record.ppid);
+        let mut res = vec![pid];
+        let mut pid = pid;
+        while let Some(ppid) = parent(pid).filter(|ppid| *ppid > 0) {
+            res.push(ppid);
+            pid = ppid;
+        }
+        Ok(res)
+    }
+
+    /// Get the name of executable of process
+    pub fn comm(&self, pid: i32) -> Result<String> {
+        Ok(fs::read_to_string(self.path(pid, "comm"))?.trim_end().into())
+    }
+
+    /// Get the command line arguments of process
+    pub fn cmdline(&self, pid: i32) -> Result<Vec<String>> {
+        Ok(fs::read(self.path(pid, "cmdline"))?
+            .split(|byte| *byte == 0)
+            .filter(|arg| !arg.is_empty())
+            .map(|arg| String::from_utf8_lossy(arg).into_owned())
+            .collect())
+    }
+
+    /// Whenever the process is a shell
+    pub fn is_shell(&self, pid: i32) -> bool {
+        self.comm(pid)
+            .map(|name| SHELLS.contains(&name.as_str()))
+            .unwrap_or(false)
+    }
+
+    /// Get the current working directory of process
+    pub fn cwd(&self, pid: i32) -> Result<String> {
+        let cwd = fs::read_link(self.path(pid, "cwd"))?;
+        Ok(cwd
+            .to_str()
+            .ok_or(format!("Non-unicode cwd of process {pid}"))?
+            .into())
+    }
+
+    /// Get the environment variables of process
+    ///
+    /// Invalid entries are ignored.
+    pub fn environ(&self, pid: i32) -> Result<HashMap<String, String>> {
+        let environ = File::open(self.path(pid, "environ"))?;
+        Ok(std::io::BufReader::new(environ)
+            .split(0x0)
+            .filter_map(|line| {
+                let line = String::from_utf8(line.ok()?).ok()?;
+                let (k, v) = line.split_once("=")?;
+                Some((k.to_string(), v.to_string()))
             })
-    };
+            .collect())
+    }
+
+    /// Get the time since the last activity on terminal of process
+    ///
+    /// The activity is detected by modification time of the terminal device
+    /// attached to stdin of process.
+    pub fn tty_idle(&self, pid: i32) -> Result<std::time::Duration> {
+        let tty = fs::metadata(self.path(pid, "fd/0"))?;
+        Ok(tty.modified()?.elapsed().unwrap_or_default())
+    }
+}
 
-    // recursively populate all nodes in the tree starting from root (pid 0)
+impl ProcessTreeNode {
+    /// Find the deepest descendant (including self) which is a shell of
+    /// `procfs`
+    ///
+    /// The deepest shell is most likely the one user interacts with.
+    pub fn deepest_shell(&self, procfs: &ProcFs) -> Option<&ProcessTreeNode> {
+        fn walk<'a>(
+            node: &'a ProcessTreeNode,
+            procfs: &ProcFs,
+            depth: usize,
+        ) -> Option<(usize, &'a ProcessTreeNode)> {
+            let own = if procfs.is_shell(node.record.pid) {
+                Some((depth, node))
+            } else {
+                None
+            };
+            node.children
+                .iter()
+                .filter_map(|child| walk(child, procfs, depth + 1))
+                .chain(own)
+                .max_by_key(|(depth, _)| *depth)
+        }
+        walk(self, procfs, 0).map(|(_, node)| node)
+    }
+
+    /// Find the first node (including self) matching the predicate
+    pub fn find<P>(&self, predicate: &P) -> Option<&ProcessTreeNode>
+    where
+        P: Fn(&ProcessTreeNode) -> bool,
     {
-        let root = &mut tree.root;
-        populate_node(root, &records?);
+        if predicate(self) {
+            Some(self)
+        } else {
+            self.children.iter().find_map(|child| child.find(predicate))
+        }
+    }
+
+    /// Iterate descendants (excluding self) breadth-first
+    pub fn descendants(&self) -> impl Iterator<Item = &ProcessTreeNode> {
+        let mut queue: std::collections::VecDeque<_> =
+            self.children.iter().collect();
+        std::iter::from_fn(move || {
+            let node = queue.pop_front()?;
+            queue.extend(node.children.iter());
+            Some(node)
+        })
+    }
+}
+
+impl ProcessTree {
+    /// Descendants of root matching the predicate, nearest first
+    pub fn descendants_matching<P>(&self, predicate: P) -> Vec<&ProcessTreeNode>
+    where
+        P: Fn(&ProcessTreeNode) -> bool,
+    {
+        self.root.descendants().filter(|node| predicate(node)).collect()
+    }
+
+    /// The deepest process (including root) which is a shell
+    pub fn deepest_shell(&self) -> Option<&ProcessTreeNode> {
+        self.root.deepest_shell(&self.procfs)
+    }
+
+    /// The nearest descendant of root which is a shell
+    ///
+    /// Unlike [Self::deepest_shell] this finds the shell the application was
+    /// started with rather than nested ones.
+    pub fn first_shell_descendant(&self) -> Option<&ProcessTreeNode> {
+        self.root
+            .descendants()
+            .find(|node| self.procfs.is_shell(node.record.pid))
+    }
+}
+
+/// Build process tree from system procfs, see [ProcFs::build_process_tree]
+pub fn build_process_tree(pid: Option<i32>) -> Result<ProcessTree> {
+    ProcFs::default().build_process_tree(pid)
+}
+
+/// Get the process and all its ancestors, starting from the process itself
+pub fn ancestors(pid: i32) -> Result<Vec<i32>> {
+    ProcFs::default().ancestors(pid)
+}
+
+/// Get the name of executable of process
+pub fn comm(pid: i32) -> Result<String> {
+    ProcFs::default().comm(pid)
+}
+
+/// Get the command line arguments of process
+pub fn cmdline(pid: i32) -> Result<Vec<String>> {
+    ProcFs::default().cmdline(pid)
+}
+
+/// Whenever the process is a shell
+pub fn is_shell(pid: i32) -> bool {
+    ProcFs::default().is_shell(pid)
+}
+
+/// Get the current working directory of process
+pub fn cwd(pid: i32) -> Result<String> {
+    ProcFs::default().cwd(pid)
+}
+
+/// Get the environment variables of process
+///
+/// Invalid entries are ignored.
+pub fn environ(pid: i32) -> Result<HashMap<String, String>> {
+    ProcFs::default().environ(pid)
+}
+
+/// Get the time since the last activity on terminal of process
+pub fn tty_idle(pid: i32) -> Result<std::time::Duration> {
+    ProcFs::default().tty_idle(pid)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::symlink;
+
+    /// Procfs in temporary directory, removed on drop
+    struct FakeProc {
+        root: PathBuf,
+        /// Whenever kernel lists children of tasks
+        children: bool,
+    }
+
+    impl FakeProc {
+        fn new(name: &str, children: bool) -> Self {
+            let root = std::env::temp_dir().join(format!(
+                "niri-integration-proc-{}-{name}",
+                std::process::id()
+            ));
+            let _ = fs::remove_dir_all(&root);
+            fs::create_dir_all(root.join("thread-self")).unwrap();
+            if children {
+                fs::write(root.join("thread-self/children"), "").unwrap();
+            }
+            Self { root, children }
+        }
+
+        /// Add process, its parent must be added before
+        fn process(&self, pid: i32, ppid: i32, comm: &str) -> &Self {
+            let dir = self.root.join(pid.to_string());
+            fs::create_dir_all(dir.join(format!("task/{pid}"))).unwrap();
+            let stat = format!("{pid} ({comm}) S {ppid} {pid} {pid} 0 -1");
+            fs::write(dir.join("stat"), stat).unwrap();
+            fs::write(dir.join("comm"), format!("{comm}\n")).unwrap();
+            let parent = self.root.join(format!("{ppid}/task/{ppid}"));
+            if self.children && parent.exists() {
+                let mut children = fs::OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(parent.join("children"))
+                    .unwrap();
+                write!(children, "{pid} ").unwrap();
+            }
+            self
+        }
+
+        fn procfs(&self) -> ProcFs {
+            ProcFs::new(&self.root)
+        }
+
+        /// Terminal with shell running nvim and nested shell running less
+        fn terminal(name: &str, children: bool) -> Self {
+            let proc = Self::new(name, children);
+            proc.process(1, 0, "systemd")
+                .process(10, 1, "kitty")
+                .process(11, 10, "zsh")
+                .process(12, 11, "nvim")
+                .process(13, 11, "bash")
+                .process(14, 13, "less")
+                .process(20, 1, "other (app)");
+            proc
+        }
+    }
+
+    impl Drop for FakeProc {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.root);
+        }
+    }
+
+    /// Parent-child pairs of tree, sorted
+    fn edges(node: &ProcessTreeNode) -> Vec<(i32, i32)> {
+        let mut edges: Vec<_> = node
+            .descendants()
+            .map(|node| (node.record.ppid, node.record.pid))
+            .collect();
+        edges.sort();
+        edges
+    }
+
+    const TERMINAL_EDGES: &[(i32, i32)] =
+        &[(10, 11), (11, 12), (11, 13), (13, 14)];
+
+    #[test]
+    fn build_process_tree_from_children() {
+        let proc = FakeProc::terminal("children", true);
+        let tree = proc.procfs().build_process_tree(Some(10)).unwrap();
+        assert_eq!(tree.root.record.pid, 10);
+        assert_eq!(edges(&tree.root), TERMINAL_EDGES);
+    }
+
+    #[test]
+    fn build_process_tree_from_stat() {
+        let proc = FakeProc::terminal("stat", false);
+        let tree = proc.procfs().build_process_tree(Some(10)).unwrap();
+        assert_eq!(tree.root.record.pid, 10);
+        assert_eq!(edges(&tree.root), TERMINAL_EDGES);
+
+        let tree = proc.procfs().build_process_tree(None).unwrap();
+        assert_eq!(tree.root.record.pid, 0);
+        assert_eq!(edges(&tree.root).len(), 7);
+    }
+
+    #[test]
+    fn descendants_matching() {
+        let proc = FakeProc::terminal("matching", true);
+        let tree = proc.procfs().build_process_tree(Some(10)).unwrap();
+        let comm = |node: &ProcessTreeNode| {
+            tree.procfs.comm(node.record.pid).unwrap_or_default()
+        };
+        let found = tree.descendants_matching(|node| comm(node) != "zsh");
+        let pids: Vec<_> = found.iter().map(|node| node.record.pid).collect();
+        assert_eq!(pids, [12, 13, 14]);
+        let vims = tree.descendants_matching(|node| comm(node) == "vim");
+        assert!(vims.is_empty());
+    }
+
+    #[test]
+    fn shells() {
+        let proc = FakeProc::terminal("shells", true);
+        let tree = proc.procfs().build_process_tree(Some(10)).unwrap();
+        let first = tree.first_shell_descendant().map(|node| node.record.pid);
+        assert_eq!(first, Some(11));
+        let deepest = tree.deepest_shell().map(|node| node.record.pid);
+        assert_eq!(deepest, Some(13));
+    }
+
+    #[test]
+    fn ancestors_and_comm() {
+        let proc = FakeProc::terminal("ancestors", true);
+        let procfs = proc.procfs();
+        assert_eq!(procfs.ancestors(14).unwrap(), [14, 13, 11, 10, 1]);
+        assert_eq!(procfs.comm(20).unwrap(), "other (app)");
+        assert_eq!(procfs.ancestors(20).unwrap(), [20, 1]);
+    }
+
+    #[test]
+    fn environ() {
+        let proc = FakeProc::terminal("environ", true);
+        let environ = b"HOME=/home/user\0INVALID\0EQ=a=b\0\xff=1\0";
+        fs::write(proc.root.join("11/environ"), environ).unwrap();
+        let environ = proc.procfs().environ(11).unwrap();
+        let expected = HashMap::from([
+            ("HOME".to_string(), "/home/user".to_string()),
+            ("EQ".to_string(), "a=b".to_string()),
+        ]);
+        assert_eq!(environ, expected);
+        assert!(proc.procfs().environ(12).is_err());
+    }
+
+    #[test]
+    fn cwd() {
+        let proc = FakeProc::terminal("cwd", true);
+        symlink("/home/user/project", proc.root.join("11/cwd")).unwrap();
+        assert_eq!(proc.procfs().cwd(11).unwrap(), "/home/user/project");
+        assert!(proc.procfs().cwd(12).is_err());
     }
-    Ok(tree)
 }
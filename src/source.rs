@@ -0,0 +1,201 @@
+//! Sources of launching data
+//!
+//! Each [EnvSource] knows how to extract launching data from windows of some
+//! application. The source of window is selected by regular expressions over
+//! its app id and title: rules of `sources` configuration are checked first,
+//! then the built-in ones. Windows matched by no rule are inspected by the
+//! generic `process` source.
+//!
+//! Configured rule may also name external extractor command whose output is
+//! merged into data of the source.
+
+use super::{Launcher, LaunchingData, config::SourceRule, exec};
+use crate::error::Result;
+use regex::Regex;
+use serde::Deserialize;
+use std::{collections::HashMap, process};
+
+/// Backend extracting launching data from window of some application
+pub trait EnvSource: Sync {
+    /// Name of source used in configuration
+    fn name(&self) -> &'static str;
+
+    /// Extract launching data from the window
+    fn extract(
+        &self,
+        launcher: &Launcher,
+        window: &niri_ipc::Window,
+    ) -> Result<LaunchingData>;
+
+    /// Whenever classic vim may run within the window
+    fn may_host_vim(&self) -> bool {
+        true
+    }
+}
+
+/// Kitty terminal queried over remote control socket
+struct Kitty;
+
+impl EnvSource for Kitty {
+    fn name(&self) -> &'static str {
+        "kitty"
+    }
+
+    fn extract(
+        &self,
+        launcher: &Launcher,
+        window: &niri_ipc::Window,
+    ) -> Result<LaunchingData> {
+        launcher.get_launching_data_from_kitty(window)
+    }
+}
+
+/// Neovide with nvim queried over its server socket
+struct Neovide;
+
+impl EnvSource for Neovide {
+    fn name(&self) -> &'static str {
+        "neovide"
+    }
+
+    fn extract(
+        &self,
+        launcher: &Launcher,
+        window: &niri_ipc::Window,
+    ) -> Result<LaunchingData> {
+        launcher.get_launching_data_from_vim(window.clone())
+    }
+
+    fn may_host_vim(&self) -> bool {
+        false
+    }
+}
+
+/// Any application inspected through its process tree
+struct Process;
+
+impl EnvSource for Process {
+    fn name(&self) -> &'static str {
+        "process"
+    }
+
+    fn extract(
+        &self,
+        launcher: &Launcher,
+        window: &niri_ipc::Window,
+    ) -> Result<LaunchingData> {
+        launcher.get_launching_data_from_proc(window)
+    }
+}
+
+/// All known sources
+const SOURCES: &[&dyn EnvSource] = &[&Kitty, &Neovide, &Process];
+
+/// App ids of built-in sources, checked after configured rules
+const BUILTIN_RULES: &[(&str, &str)] =
+    &[("^kitty$", "kitty"), ("^neovide$", "neovide")];
+
+/// Find source by name
+pub fn find(name: &str) -> Result<&'static dyn EnvSource> {
+    SOURCES
+        .iter()
+        .copied()
+        .find(|source| source.name() == name)
+        .ok_or_else(|| format!("Unknown source '{name}'").into())
+}
+
+/// Output of external extractor
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct Extracted {
+    cwd: Option<String>,
+    env: HashMap<String, String>,
+}
+
+/// Whenever the rule matches app id and title of window
+fn matches(rule: &SourceRule, window: &niri_ipc::Window) -> Result<bool> {
+    let app_id = window.app_id.as_deref().unwrap_or_default();
+    if !Regex::new(&rule.app_id)?.is_match(app_id) {
+        return Ok(false);
+    }
+    Ok(match &rule.title {
+        Some(title) => {
+            let window_title = window.title.as_deref().unwrap_or_default();
+            Regex::new(title)?.is_match(window_title)
+        }
+        None => true,
+    })
+}
+
+/// First configured rule matching the window
+fn find_rule<'a>(
+    rules: &'a [SourceRule],
+    window: &niri_ipc::Window,
+) -> Result<Option<&'a SourceRule>> {
+    for rule in rules {
+        if matches(rule, window)? {
+            return Ok(Some(rule));
+        }
+    }
+    Ok(None)
+}
+
+/// Command of external extractor configured for the window
+pub fn extractor<'a>(
+    rules: &'a [SourceRule],
+    window: &niri_ipc::Window,
+) -> Result<Option<&'a [String]>> {
+    Ok(find_rule(rules, window)?
+        .filter(|rule| !rule.command.is_empty())
+        .map(|rule| rule.command.as_slice()))
+}
+
+/// Run external extractor and merge its output into `data`
+pub fn run_extractor(
+    command: &[String],
+    window: &niri_ipc::Window,
+    data: LaunchingData,
+) -> Result<LaunchingData> {
+    let expand = |arg: &String| {
+        arg.replace("{pid}", &window.pid.unwrap_or(0).to_string())
+            .replace("{id}", &window.id.to_string())
+            .replace("{app_id}", window.app_id.as_deref().unwrap_or(""))
+            .replace("{title}", window.title.as_deref().unwrap_or(""))
+    };
+    let args: Vec<String> = command.iter().map(expand).collect();
+    log::debug!("Running extractor {args:?}");
+    let mut proc = process::Command::new(&args[0]);
+    proc.args(&args[1..]).stdout(process::Stdio::piped());
+    let output = exec::spawn(&mut proc)?.wait_with_output()?;
+    if !output.status.success() {
+        Err(format!("Extractor `{}` failed: {}", args[0], output.status))?
+    }
+    let extracted: Extracted = serde_json::from_slice(&output.stdout)?;
+    let data = data.add_envs(extracted.env.into_iter());
+    Ok(match extracted.cwd {
+        Some(cwd) => data.set_cwd(cwd),
+        None => data,
+    })
+}
+
+/// Select source of window
+pub fn select(
+    rules: &[SourceRule],
+    window: &niri_ipc::Window,
+) -> Result<&'static dyn EnvSource> {
+    if let Some(rule) = find_rule(rules, window)? {
+        return find(&rule.source);
+    }
+    let app_id = window.app_id.as_deref().unwrap_or_default();
+    for (pattern, name) in BUILTIN_RULES {
+        if Regex::new(pattern)?.is_match(app_id) {
+            return find(name);
+        }
+    }
+    find("process")
+}
+
+/// Whenever the window is served by source with `name`
+pub fn is(rules: &[SourceRule], window: &niri_ipc::Window, name: &str) -> bool {
+    select(rules, window).is_ok_and(|source| source.name() == name)
+}
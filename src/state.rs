@@ -0,0 +1,190 @@
+//! Persistent state shared between invocations
+//!
+//! The state is stored as JSON file at
+//! `${XDG_STATE_HOME}/niri-integration/state.json` (or
+//! `~/.local/state/niri-integration/state.json`).
+//!
+//! The state is versioned. State of older versions is migrated on load, state
+//! written by newer version is refused to not lose its data on save.
+//!
+//! Invocations and daemon change the state concurrently, so changes are made
+//! with [State::update] under exclusive lock of `state.lock` file. Frequently
+//! rewritten caches are stored apart in own files with own locks.
+
+use crate::{dryrun, error::Result};
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use std::{
+    collections::HashMap,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+/// Current version of state format
+const STATE_VERSION: u32 = 1;
+
+/// Top-level state structure
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct State {
+    /// Version of state format, 0 for unversioned state
+    pub version: u32,
+    /// Result of vim calibration wizard
+    pub calibration: Option<Calibration>,
+    /// Last floating geometry of niri windows by window id
+    pub floating: HashMap<u64, Geometry>,
+    /// Ids of niri windows, the most recently focused first
+    pub focus_history: Vec<u64>,
+    /// Snapshot of focus history being walked by cycle command
+    pub cycle: Cycle,
+    /// Ids of niri windows by mark name
+    pub marks: HashMap<String, u64>,
+}
+
+/// Sockets of nvim instances found within process tree by pid of its root
+///
+/// Stored in `nvim-sockets.json` next to the state.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct NvimSockets(pub HashMap<i32, Vec<PathBuf>>);
+
+/// Window list walked by consecutive cycle commands
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct Cycle {
+    /// Ids of niri windows in most recently used order
+    pub windows: Vec<u64>,
+    /// Position of the window focused by the last cycle command
+    pub position: usize,
+    /// Filter of application ids the windows were chosen with
+    pub app_id: Option<String>,
+}
+
+/// Position and size of floating window in logical pixels
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct Geometry {
+    pub x: f64,
+    pub y: f64,
+    pub width: i32,
+    pub height: i32,
+}
+
+/// Calibrated parameters of vim width calculation
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Calibration {
+    /// Width of one symbol in pixels per point of font size
+    pub symbol_ratio: f64,
+    /// Multiplier of textwidth of each column
+    pub column_width_koeff: f64,
+}
+
+impl State {
+    /// Load state, missing file results in default state
+    pub fn load() -> Result<Self> {
+        read::<Self>(&Self::path())?.migrate()
+    }
+
+    /// Bring state of older version to the current one
+    fn migrate(mut self) -> Result<Self> {
+        if self.version > STATE_VERSION {
+            Err(format!(
+                "State {} is written by newer version {}, supported is {}",
+                Self::path().display(),
+                self.version,
+                STATE_VERSION
+            ))?
+        }
+        // Version 0 has the same layout, but symbol ratio did not account
+        // neovide scale factor. It is still valid for unscaled neovide.
+        self.version = STATE_VERSION;
+        Ok(self)
+    }
+
+    /// Change state under lock and store it if `update` succeeds
+    pub fn update<T, F>(update: F) -> Result<T>
+    where
+        F: FnOnce(&mut Self) -> Result<T>,
+    {
+        let path = Self::path();
+        let _lock = lock(&path)?;
+        let mut state = Self::load()?;
+        let result = update(&mut state)?;
+        state.version = STATE_VERSION;
+        write(&path, &state)?;
+        Ok(result)
+    }
+
+    /// Path of state file
+    pub fn path() -> PathBuf {
+        std::env::var_os("XDG_STATE_HOME")
+            .map(PathBuf::from)
+            .or_else(|| {
+                std::env::var_os("HOME").map(|home| {
+                    PathBuf::from(home).join(".local").join("state")
+                })
+            })
+            .unwrap_or_else(|| PathBuf::from("."))
+            .join("niri-integration")
+            .join("state.json")
+    }
+}
+
+impl NvimSockets {
+    /// Load cache, missing file results in empty cache
+    pub fn load() -> Result<Self> {
+        read(&Self::path())
+    }
+
+    /// Change cache under lock and store it if `update` succeeds
+    pub fn update<T, F>(update: F) -> Result<T>
+    where
+        F: FnOnce(&mut Self) -> Result<T>,
+    {
+        let path = Self::path();
+        let _lock = lock(&path)?;
+        let mut cache = Self::load()?;
+        let result = update(&mut cache)?;
+        write(&path, &cache)?;
+        Ok(result)
+    }
+
+    fn path() -> PathBuf {
+        State::path().with_file_name("nvim-sockets.json")
+    }
+}
+
+/// Read JSON file, missing file results in default value
+fn read<T: DeserializeOwned + Default>(path: &Path) -> Result<T> {
+    match fs::File::open(path) {
+        Ok(file) => Ok(serde_json::from_reader(io::BufReader::new(file))?),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(T::default()),
+        Err(err) => Err(err)?,
+    }
+}
+
+/// Take exclusive lock guarding the file, released when result is dropped
+fn lock(path: &Path) -> Result<fs::File> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let lock = fs::OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(path.with_extension("lock"))?;
+    lock.lock()?;
+    Ok(lock)
+}
+
+/// Replace content of the file atomically
+fn write<T: Serialize>(path: &Path, value: &T) -> Result<()> {
+    if dryrun::skip(format_args!("write {}", path.display())) {
+        return Ok(());
+    }
+    // Readers never see partially written file. The name is unique, so
+    // concurrent writers do not truncate file of each other.
+    let tmp = path.with_extension(format!("json.{}.tmp", std::process::id()));
+    serde_json::to_writer_pretty(fs::File::create(&tmp)?, value)?;
+    fs::rename(tmp, path)?;
+    Ok(())
+}
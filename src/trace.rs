@@ -0,0 +1,161 @@
+//! Recording of IPC exchanges for bug reports
+//!
+//! Every request to niri and kitty sockets together with its response, as
+//! well as expressions, functions, commands and input sent to nvim, are
+//! appended to JSON-lines file.
+//! Values of secret variables are masked according to `env.secrets` of
+//! configuration. The recorded file may be read back with [Replay] to serve
+//! the same responses again.
+
+use crate::{config::EnvConfig, error::Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{
+    collections::VecDeque,
+    fs,
+    io::{self, BufRead, Write},
+    path::Path,
+    sync::Mutex,
+};
+
+struct Tracer {
+    file: fs::File,
+    env: EnvConfig,
+}
+
+static TRACER: Mutex<Option<Tracer>> = Mutex::new(None);
+
+/// One recorded exchange
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Record {
+    /// Peer of exchange: `niri`, `kitty` or `nvim`
+    pub channel: String,
+    /// Request as sent to peer with secrets masked
+    pub request: Value,
+    /// Response of peer, `null` for requests without one
+    pub response: Value,
+}
+
+/// Start appending exchanges to file
+pub fn enable(path: &Path, env: EnvConfig) -> Result<()> {
+    let file = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)?;
+    if let Ok(mut tracer) = TRACER.lock() {
+        *tracer = Some(Tracer { file, env });
+    }
+    Ok(())
+}
+
+/// Record exchange if tracing is enabled
+///
+/// Failures of tracing must not break the traced program, so they are ignored.
+pub fn record<Q: Serialize, R: Serialize>(
+    channel: &str,
+    request: &Q,
+    response: &R,
+) {
+    let Ok(mut tracer) = TRACER.lock() else {
+        return;
+    };
+    let Some(tracer) = tracer.as_mut() else {
+        return;
+    };
+    let record = Record {
+        channel: channel.into(),
+        request: tracer.redact(serde_json::to_value(request).ok()),
+        response: tracer.redact(serde_json::to_value(response).ok()),
+    };
+    if let Ok(line) = serde_json::to_string(&record) {
+        let _ = writeln!(tracer.file, "{line}");
+    }
+}
+
+impl Tracer {
+    fn redact(&self, value: Option<Value>) -> Value {
+        let mut value = value.unwrap_or_default();
+        self.env.redact(&mut value);
+        value
+    }
+}
+
+/// Recorded exchanges served back in order of recording
+#[derive(Debug, Clone, Default)]
+pub struct Replay {
+    records: VecDeque<Record>,
+}
+
+impl Replay {
+    /// Load exchanges from trace file
+    pub fn load(path: &Path) -> Result<Self> {
+        let file = io::BufReader::new(fs::File::open(path)?);
+        let mut records = VecDeque::new();
+        for line in file.lines() {
+            records.push_back(serde_json::from_str(&line?)?);
+        }
+        Ok(Self { records })
+    }
+
+    /// Response to the next request of channel
+    ///
+    /// The request has to match the recorded one, exchanges of other channels
+    /// are not affected.
+    pub fn respond<Q: Serialize>(
+        &mut self,
+        channel: &str,
+        request: &Q,
+    ) -> Result<Value> {
+        let request = serde_json::to_value(request)?;
+        let Some(record) = self
+            .records
+            .iter()
+            .position(|record| record.channel == channel)
+            .and_then(|position| self.records.remove(position))
+        else {
+            Err(format!("No more recorded {channel} exchanges"))?
+        };
+        if record.request != request {
+            Err(format!(
+                "Unexpected {channel} request {request}, recorded {}",
+                record.request
+            ))?
+        }
+        Ok(record.response)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kitty::{self, KittyTransport};
+    use serde_json::json;
+
+    #[test]
+    fn replay_recorded_exchanges() {
+        let path = std::env::temp_dir()
+            .join(format!("niri-integration-trace-{}", std::process::id()));
+        enable(&path, EnvConfig::default()).unwrap();
+        let ls = kitty::Command::Ls(kitty::Ls::default());
+        let windows =
+            json!([{"is_active": true, "is_focused": true, "tabs": []}]);
+        record("kitty", &ls, &windows);
+        let launch = kitty::Command::Launch(kitty::Launch {
+            env: Some(vec!["GH_TOKEN=secret".into(), "EDITOR=vim".into()]),
+            ..Default::default()
+        });
+        record("kitty", &launch, &Value::Null);
+        *TRACER.lock().unwrap() = None;
+
+        let mut replay = Replay::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(replay.request(ls.clone()).unwrap(), windows);
+        assert_eq!(
+            replay.records[0].request["launch"]["env"],
+            json!(["GH_TOKEN=********", "EDITOR=vim"])
+        );
+        // Recorded request has secret masked
+        assert!(replay.request(launch).is_err());
+        assert!(replay.request(ls).is_err());
+    }
+}
@@ -1,23 +1,350 @@
 use super::{
-    Direction, Launcher,
+    Direction, Launcher, Tab,
+    config::{Width, WidthPreset},
+    dryrun,
     error::{Error, Result},
-    pstree::{ProcessTreeNode, build_process_tree},
+    niri,
+    pstree::{self, ProcessTreeNode, build_process_tree},
+    state::{Calibration, NvimSockets},
+    trace,
 };
-use neovim_lib::{Neovim, NeovimApi, Session, neovim_api::Window};
+use async_trait::async_trait;
 use niri_ipc;
 use nix::unistd;
-use std::{collections::HashMap, num::ParseIntError};
+use nvim_rs::{
+    Neovim, Value, compat::tokio::Compat, create::tokio as create,
+    error::CallError,
+};
+use serde::Serialize;
+use std::{
+    cell::RefCell,
+    collections::{HashMap, HashSet},
+    fs,
+    future::Future,
+    io::{self, BufRead, Write},
+    num::ParseFloatError,
+    ops::Deref,
+    path::{Path, PathBuf},
+    rc::Rc,
+    thread,
+    time::Duration,
+};
+use tokio::{
+    io::WriteHalf,
+    net::UnixStream,
+    runtime::Runtime,
+    sync::mpsc::{self, UnboundedReceiver, UnboundedSender},
+};
+
+/// Default width of one symbol in pixels per point of font size
+const SYMBOL_RATIO: f64 = 0.80093;
+/// Default height of one line in pixels per point of font size
+const LINE_RATIO: f64 = 1.6;
+/// Default multiplier of textwidth of each column
+const COLUMN_WIDTH_KOEFF: f64 = 1.2;
+/// Width of text in symbols when `textwidth` option is lower or unset
+const TEXTWIDTH: i64 = 80;
+/// Expression of number of lines needed to show whole current buffer
+const CONTENT_LINES: &str = "line('$') \
+    + (&showtabline == 2 || &showtabline == 1 && tabpagenr('$') > 1) \
+    + (&laststatus >= 2 || &laststatus == 1 && winnr('$') > 1) \
+    + &cmdheight";
+/// Time given to neovide to apply new window size
+const RESIZE_DELAY: Duration = Duration::from_millis(300);
+/// Command which toggles zoom of current window
+pub const ZOOM_COMMAND: &str = "if exists('t:niri_zoom') | exe t:niri_zoom | \
+    unlet t:niri_zoom | else | let t:niri_zoom = winrestcmd() | resize | \
+    vertical resize | endif";
+/// Interval between steps of view offset animation
+const FRAME_INTERVAL: Duration = Duration::from_millis(16);
+/// Autocmd events which change layout of vim windows
+const LAYOUT_EVENTS: &[&str] =
+    &["WinResized", "WinNew", "WinClosed", "TabEnter"];
+/// Method of notification sent by nvim on layout change
+const LAYOUT_NOTIFICATION: &str = "niri_integration_layout";
+
+thread_local! {
+    /// Connections kept open between commands, by path of socket
+    static SESSIONS: RefCell<Option<HashMap<PathBuf, Rc<Nvim>>>> =
+        const { RefCell::new(None) };
+}
+
+/// Keep nvim connections of this thread open between commands
+///
+/// Used by batch and repl modes, which run many commands in one process.
+pub fn keep_sessions() {
+    SESSIONS.with_borrow_mut(|sessions| {
+        sessions.get_or_insert_default();
+    });
+}
+
+/// Write half of nvim connection
+type Writer = Compat<WriteHalf<UnixStream>>;
+/// Window of nvim reached over its socket
+pub type Window = nvim_rs::Window<Writer>;
+type Buffer = nvim_rs::Buffer<Writer>;
+
+/// Handler of notifications from nvim
+///
+/// Only layout notifications are expected, they are forwarded to `layout`.
+#[derive(Clone, Default)]
+struct Handler {
+    layout: Option<UnboundedSender<()>>,
+}
+
+#[async_trait]
+impl nvim_rs::Handler for Handler {
+    type Writer = Writer;
+
+    async fn handle_notify(
+        &self,
+        name: String,
+        _args: Vec<Value>,
+        _neovim: Neovim<Writer>,
+    ) {
+        if let (Some(layout), LAYOUT_NOTIFICATION) = (&self.layout, &*name) {
+            let _ = layout.send(());
+        }
+    }
+}
+
+/// Blocking connection to nvim
+///
+/// The nvim-rs client is asynchronous. Calls are run to completion on own
+/// single-threaded runtime which also drives the connection meanwhile.
+pub struct Nvim {
+    api: Neovim<Writer>,
+    runtime: Runtime,
+}
+
+impl Nvim {
+    fn connect(path: &Path, handler: Handler) -> Result<Self> {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_io()
+            .build()?;
+        let (api, _io) = runtime.block_on(create::new_path(path, handler))?;
+        Ok(Self { api, runtime })
+    }
+
+    /// Connect nvim or reuse the kept connection to the same socket
+    ///
+    /// Connections subscribed to notifications are never shared.
+    fn open(path: &Path, handler: Handler) -> Result<Rc<Self>> {
+        if handler.layout.is_some() {
+            return Ok(Rc::new(Self::connect(path, handler)?));
+        }
+        let kept = SESSIONS.with_borrow(|s| s.as_ref()?.get(path).cloned());
+        // Nvim may exit since the previous command
+        if let Some(nvim) = kept.filter(|n| n.block(n.eval("1")).is_ok()) {
+            return Ok(nvim);
+        }
+        let nvim = Rc::new(Self::connect(path, handler)?);
+        SESSIONS.with_borrow_mut(|sessions| {
+            if let Some(sessions) = sessions {
+                sessions.insert(path.to_path_buf(), nvim.clone());
+            }
+        });
+        Ok(nvim)
+    }
+
+    /// Wait for result of API call
+    pub fn block<T, F>(&self, call: F) -> Result<T>
+    where
+        F: Future<Output = std::result::Result<T, Box<CallError>>>,
+    {
+        Ok(self.runtime.block_on(call)?)
+    }
+
+    /// Call vim function and record the exchange
+    fn call(&self, name: &str, args: Vec<Value>) -> Result<Value> {
+        let request = serde_json::json!({
+            "call": name,
+            "args": args.iter().map(Value::to_string).collect::<Vec<_>>(),
+        });
+        let response = self.block(self.call_function(name, args))?;
+        trace::record("nvim", &request, &response.to_string());
+        Ok(response)
+    }
 
+    /// Window object of window handle or id
+    fn window(&self, value: Value) -> Window {
+        Window::new(value, self.api.clone())
+    }
+
+    /// Output of ex command
+    fn output(&self, cmd: &str) -> Result<String> {
+        let opts = vec![("output".into(), true.into())];
+        let res = self.block(self.exec2(cmd, opts))?;
+        let request = serde_json::json!({ "exec": cmd });
+        trace::record("nvim", &request, &Value::Map(res.clone()).to_string());
+        for (k, v) in res {
+            let k = k
+                .as_str()
+                .ok_or(Error::Str("Invalid key type of nvim value".into()))?;
+            if k == "output" {
+                let v = v.as_str().ok_or(Error::Str(
+                    "Invalid type of output field of nvim_exec2 call".into(),
+                ))?;
+                return Ok(v.into());
+            }
+        }
+        Err(Error::Str(
+            "Call to nvim_exec2 does not contains output".into(),
+        ))
+    }
+}
+
+impl Deref for Nvim {
+    type Target = Neovim<Writer>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.api
+    }
+}
+
+/// Nvim instance reachable within process tree of window
+#[derive(Debug, Clone, Serialize)]
+pub struct Instance {
+    /// Pid of nvim process
+    pub pid: i32,
+    /// Path of RPC socket of nvim
+    pub socket: PathBuf,
+}
+
+impl Instance {
+    /// Whenever `selector`, pid or path of socket, names the instance
+    pub fn matches(&self, selector: &str) -> bool {
+        match selector.parse::<i32>() {
+            Ok(pid) => pid == self.pid,
+            Err(_) => Path::new(selector) == self.socket,
+        }
+    }
+}
+
+/// Choice among several nvim instances of one window
+#[derive(Debug, Clone, Default)]
+pub struct Choice {
+    /// Pid or socket path of instance chosen by user
+    pub selector: Option<String>,
+    /// Pids of processes user interacts with, e.g. foreground processes of
+    /// focused kitty window
+    pub foreground: Vec<i32>,
+}
+
+impl Choice {
+    /// Position of chosen instance
+    ///
+    /// The instance named by selector is chosen, otherwise the one in
+    /// foreground. Without both the first instance is chosen.
+    fn pick<'a, I>(&self, mut instances: I) -> Option<usize>
+    where
+        I: Iterator<Item = &'a Instance>,
+    {
+        if let Some(selector) = &self.selector {
+            instances.position(|instance| instance.matches(selector))
+        } else if !self.foreground.is_empty() {
+            instances
+                .position(|instance| self.foreground.contains(&instance.pid))
+        } else {
+            instances.next().map(|_| 0)
+        }
+    }
+
+    /// Whenever `instances` contain the chosen one and all foreground ones
+    fn complete<'a, I>(&self, instances: I) -> bool
+    where
+        I: Iterator<Item = &'a Instance>,
+    {
+        let instances: Vec<_> = instances.collect();
+        self.pick(instances.iter().copied()).is_some()
+            && self.foreground.iter().all(|pid| {
+                instances.iter().any(|instance| instance.pid == *pid)
+            })
+    }
+}
+
+/// Subscription to layout changes of nvim
+///
+/// Autocmds of layout events are registered in nvim, they notify this
+/// connection over RPC channel. The connection is driven only while waiting
+/// for notification. The autocmds are removed when the watch is dropped or,
+/// if connection is lost, on the first failed notification.
+pub struct LayoutWatch {
+    nvim: Rc<Nvim>,
+    layout: UnboundedReceiver<()>,
+    /// Id of augroup of the autocmds
+    group: Value,
+}
+
+impl LayoutWatch {
+    /// Subscribe to nvim running within the niri window
+    pub fn new(niri_window: &niri_ipc::Window) -> Result<Self> {
+        let (sender, layout) = mpsc::unbounded_channel();
+        let handler = Handler {
+            layout: Some(sender),
+        };
+        let choice = Choice::default();
+        let nvim = Vim::try_connect(niri_window.pid, &handler, &choice)?;
+        // Sender is owned by connection only, so closing of connection ends
+        // the subscription
+        drop(handler);
+        let lua = "local events, method = ...
+            local chan = vim.api.nvim_get_api_info()[1]
+            local group = vim.api.nvim_create_augroup(method, { clear = true })
+            vim.api.nvim_create_autocmd(events, {
+              group = group,
+              callback = function()
+                if not pcall(vim.rpcnotify, chan, method) then
+                  vim.api.nvim_del_augroup_by_id(group)
+                end
+              end,
+            })
+            return group";
+        let events = LAYOUT_EVENTS.iter().map(|&event| event.into()).collect();
+        let args = vec![Value::Array(events), LAYOUT_NOTIFICATION.into()];
+        let group = nvim.block(nvim.exec_lua(lua, args))?;
+        log::debug!("Watching layout of nvim in window {}", niri_window.id);
+        Ok(Self {
+            nvim,
+            layout,
+            group,
+        })
+    }
+
+    /// Wait for layout change, false when nvim closes connection
+    ///
+    /// Bursts of changes are reported once.
+    pub fn wait(&mut self) -> bool {
+        if self.nvim.runtime.block_on(self.layout.recv()).is_none() {
+            return false;
+        }
+        while self.layout.try_recv().is_ok() {}
+        true
+    }
+}
+
+impl Drop for LayoutWatch {
+    fn drop(&mut self) {
+        let lua = "pcall(vim.api.nvim_del_augroup_by_id, ...)";
+        let args = vec![self.group.clone()];
+        // Nvim may be already gone
+        let _ = self.nvim.block(self.nvim.exec_lua(lua, args));
+    }
+}
+
+/// Vim windows stacked vertically in one column of the layout
 pub struct WinColumn {
+    /// Position of left edge in symbols
     pub start: i64,
+    /// Position of right edge in symbols
     pub end: i64,
     windows: Vec<Win>,
 }
 
 impl WinColumn {
-    fn from_window(win: Window, nvim: &mut Neovim) -> Result<Self> {
-        let pos = win.get_position(nvim)?;
-        let width = win.get_width(nvim)?;
+    fn from_window(win: Window, nvim: &Nvim) -> Result<Self> {
+        let pos = nvim.block(win.get_position())?;
+        let width = nvim.block(win.get_width())?;
         Ok(Self {
             start: pos.1,
             end: pos.1 + width,
@@ -33,71 +360,75 @@ impl WinColumn {
         &mut self.windows[0]
     }
 
-    fn textwidth(&mut self, nvim: &mut Neovim) -> i64 {
-        std::cmp::max(
-            80,
-            self.windows.iter_mut().fold(0, |fin, win| {
-                // Do not account windows which are attached to more then two columns
-                if win.get_columns() > 1 {
-                    fin
-                } else {
-                    let textwidth = win
-                        .win
-                        .get_buf(nvim)
-                        .map(|buf| {
-                            buf.get_option(nvim, "textwidth")
-                                .map(|val| val.as_i64().unwrap_or(80))
-                                .unwrap_or(80)
+    /// Desired width of column in symbols including gutters
+    ///
+    /// The width of window is taken from `widths` by filetype of its buffer,
+    /// otherwise from `textwidth` option which is at least [TEXTWIDTH].
+    fn textwidth(&mut self, nvim: &Nvim, widths: &HashMap<String, i64>) -> i64 {
+        let width = self.windows.iter_mut().fold(0, |fin, win| {
+            // Do not account windows which are attached to more then two columns
+            if win.get_columns() > 1 {
+                fin
+            } else {
+                let textwidth = nvim
+                    .block(win.win.get_buf())
+                    .map(|buf| {
+                        let filetype = buf_option(nvim, &buf, "filetype");
+                        let width = filetype
+                            .ok()
+                            .and_then(|ft| widths.get(ft.as_str()?).cloned());
+                        width.unwrap_or_else(|| {
+                            let textwidth = buf_option(nvim, &buf, "textwidth")
+                                .ok()
+                                .and_then(|val| val.as_i64())
+                                .unwrap_or(TEXTWIDTH);
+                            std::cmp::max(textwidth, TEXTWIDTH)
                         })
-                        .unwrap_or(80);
-                    std::cmp::max(textwidth, fin)
-                }
-            }),
-        )
+                    })
+                    .unwrap_or(TEXTWIDTH);
+                std::cmp::max(textwidth + win.textoff(nvim), fin)
+            }
+        });
+        // All windows of column span several columns
+        if width == 0 { TEXTWIDTH } else { width }
     }
 
+    /// Add window stacked into the column
     pub fn add_win(&mut self, win: Window) {
         self.windows.push(Win::new(win));
     }
 
-    pub fn increase_wins_columns(&mut self) {
-        for win in &mut self.windows {
-            win.add_to_column();
-        }
+    /// Merge column stacked vertically with this one
+    fn merge(&mut self, other: WinColumn) {
+        self.start = std::cmp::min(self.start, other.start);
+        self.end = std::cmp::max(self.end, other.end);
+        self.windows.extend(other.windows);
     }
+}
 
-    pub fn add_other(&mut self, other: &mut WinColumn) {
-        // Same columns - do nothing
-        if self.start == other.start && self.end == other.end {
-        }
-        // New - inside other
-        else if self.start <= other.start && self.end >= other.end {
-            // Shrink current column
-            self.start = other.start;
-            self.end = other.end;
-            // Count new column in windows
-            self.increase_wins_columns();
-        }
-        // Current - inside other
-        else if self.start >= other.start && self.end <= other.end {
-            // Count current column in new windows
-            other.increase_wins_columns();
-        }
-        // Other cases can not be handled correctly
-        else {
-        }
-
-        self.windows.append(&mut other.windows);
-    }
+/// Summary of computed column
+#[derive(Debug, Clone, Serialize)]
+pub struct ColumnInfo {
+    /// Position of left edge in symbols
+    pub start: i64,
+    /// Position of right edge in symbols
+    pub end: i64,
+    /// Desired width in symbols including gutters
+    pub textwidth: i64,
+    /// Number of windows stacked in column
+    pub windows: usize,
 }
 
+/// Vim window with its cached configuration
 pub struct Win {
+    /// Window of nvim
     pub win: Window,
     num_colums: i64,
-    config: Option<HashMap<String, neovim_lib::Value>>,
+    config: Option<HashMap<String, Value>>,
 }
 
 impl Win {
+    /// Wrap window which spans single column
     pub fn new(win: Window) -> Self {
         Self {
             win,
@@ -106,122 +437,123 @@ impl Win {
         }
     }
 
-    pub fn add_to_column(&mut self) {
-        self.num_colums += 1;
+    /// Set the number of columns window spans
+    pub fn set_columns(&mut self, columns: i64) {
+        self.num_colums = columns;
     }
 
+    /// Get the number of columns window spans
     pub fn get_columns(&self) -> i64 {
         self.num_colums
     }
 
-    pub fn is_floating(&mut self, nvim: &mut Neovim) -> bool {
+    /// Width of gutters: number, sign and fold columns
+    pub fn textoff(&self, nvim: &Nvim) -> i64 {
+        let info = vec![self.win.get_value().clone()];
+        nvim.call("getwininfo", info)
+            .ok()
+            .and_then(|info| {
+                info.as_array()?
+                    .first()?
+                    .as_map()?
+                    .iter()
+                    .find(|(k, _)| k.as_str() == Some("textoff"))?
+                    .1
+                    .as_i64()
+            })
+            .unwrap_or(0)
+    }
+
+    /// Whenever window floats over the layout
+    pub fn is_floating(&mut self, nvim: &Nvim) -> bool {
         self.get_config(nvim)
             .get("relative")
             .cloned()
-            .unwrap_or(neovim_lib::Value::Nil)
+            .unwrap_or(Value::Nil)
             .as_str()
             .unwrap_or("")
             != ""
     }
 
-    fn get_config(
-        &mut self,
-        nvim: &mut Neovim,
-    ) -> &HashMap<String, neovim_lib::Value> {
+    fn get_config(&mut self, nvim: &Nvim) -> &HashMap<String, Value> {
         if self.config.is_none() {
             self.config = Some(
-                nvim.session
-                    .call(
-                        "nvim_win_get_config",
-                        vec![self.win.get_value().clone()],
-                    )
-                    .map(|value| {
-                        value
-                            .as_map()
-                            .cloned()
-                            .unwrap_or_else(|| Default::default())
+                nvim.block(self.win.get_config())
+                    .map(|config| {
+                        config
                             .into_iter()
                             .map(|(k, v)| {
-                                (
-                                    String::from(
-                                        k.as_str().unwrap_or("__invalid"),
-                                    ),
-                                    v.clone(),
-                                )
+                                let k = k.as_str().unwrap_or("__invalid");
+                                (String::from(k), v)
                             })
                             .collect()
                     })
-                    .unwrap_or_else(|_| Default::default()),
+                    .unwrap_or_default(),
             );
         }
         self.config.as_ref().unwrap()
     }
 }
 
+/// Nvim instance shown in niri window
 pub struct Vim {
-    nvim: Neovim,
+    nvim: Rc<Nvim>,
     columns: Vec<WinColumn>,
     column_width_koeff: f64,
+    symbol_ratio: f64,
     width: i64,
     height: i64,
     niri_window: niri_ipc::Window,
-    font_size: i32,
+    font_size: f64,
+    /// Value of `g:neovide_scale_factor`
+    scale_factor: f64,
+    min_width: Option<Width>,
+    max_width: Option<Width>,
+    /// Desired widths of buffers by filetype
+    filetype_widths: HashMap<String, i64>,
+    /// Duration of view offset animation
+    animation: Duration,
+    /// Gaps between niri windows in pixels
+    gaps: f64,
+    /// Width of niri window border in pixels
+    border: f64,
 }
 
 impl Vim {
-    pub fn new(niri_window: niri_ipc::Window) -> Result<Self> {
-        let mut session = Self::try_session_from(
-            &unistd::geteuid(),
-            &build_process_tree(niri_window.pid)?.root,
-        )?;
-        session.start_event_loop();
-        let mut nvim = Neovim::new(session);
-        let (columns, width, height) = Self::calculate_columns(&mut nvim)?;
-        let font_size = Self::get_font_size(&mut nvim).unwrap_or(10);
+    /// Connect nvim running in `niri_window` and compute its layout
+    pub fn new(niri_window: niri_ipc::Window, choice: &Choice) -> Result<Self> {
+        let handler = Handler::default();
+        let nvim = Self::try_connect(niri_window.pid, &handler, choice)?;
+        log::debug!("Connected to nvim of window {}", niri_window.id);
+        let (columns, width, height) = Self::calculate_columns(&nvim)?;
+        let font_size = Self::get_font_size(&nvim).unwrap_or(10.0);
+        let scale_factor = Self::get_scale_factor(&nvim).unwrap_or(1.0);
         Ok(Self {
             nvim,
             columns,
-            column_width_koeff : 1.2,
+            column_width_koeff: COLUMN_WIDTH_KOEFF,
+            symbol_ratio: SYMBOL_RATIO,
             width,
             height,
             niri_window,
             font_size,
+            scale_factor,
+            min_width: None,
+            max_width: None,
+            filetype_widths: HashMap::new(),
+            animation: Duration::ZERO,
+            gaps: 0.0,
+            border: 0.0,
         })
     }
 
-    fn get_font(nvim: &mut Neovim) -> Result<String> {
-        let res = nvim
-            .session
-            .call(
-                "nvim_exec2",
-                vec![
-                    "set guifont".into(),
-                    (vec![("output".into(), true.into())]).into(),
-                ],
-            )
-            .map_err(|e| e.to_string())?;
-        let res = res
-            .as_map()
-            .ok_or(Error::Str("Unexpected result of nvim_exec2 call".into()))?;
-        for (k, v) in res {
-            let k = k
-                .as_str()
-                .ok_or(Error::Str("Invalid key type of nvim value".into()))?;
-            if k == "output" {
-                let v = v.as_str().ok_or(Error::Str(
-                    "Invalid type of output field of nvim_exec2 call".into(),
-                ))?;
-                return Ok(v.into());
-            }
-        }
-        Err(Error::Str(
-            "Call to nvim_exec2 does not contains output".into(),
-        ))
+    fn get_font(nvim: &Nvim) -> Result<String> {
+        nvim.output("set guifont")
     }
 
-    fn get_font_size(nvim: &mut Neovim) -> Result<i32> {
+    fn get_font_size(nvim: &Nvim) -> Result<f64> {
         let font = Self::get_font(nvim)?;
-        let re = regex::Regex::new(r":h(\d+)$")?;
+        let re = regex::Regex::new(r":h(\d+(?:\.\d+)?)")?;
         let match_err =
             format!("Can not find size component of font in '{font}'");
         let h = re
@@ -231,213 +563,528 @@ impl Vim {
             .ok_or(Error::Str(match_err.clone()))?;
         Ok(h.as_str()
             .parse()
-            .map_err(|e: ParseIntError| e.to_string())?)
+            .map_err(|e: ParseFloatError| e.to_string())?)
     }
 
-    fn try_session_from(
-        uid: &unistd::Uid,
-        node: &ProcessTreeNode,
-    ) -> Result<Session> {
-        Ok(Session::new_unix_socket(format!(
-            "/run/user/{}/nvim.{}.0",
-            uid, node.record.pid,
-        ))
-        .or_else(|err| {
-            node.children.iter().fold(Err(err), |res, elem| {
-                res.or_else(|_| Ok(Self::try_session_from(uid, elem)?))
-            })
-        })?)
-    }
-
-    // This is not very stable function. It attempt to count number of columns of windows in vim.
-    // In my work I always split vertically, so this should work for me. But it may not work, when
-    // someone splits vim horizontally at first.
-    fn calculate_columns(
-        nvim: &mut Neovim,
-    ) -> Result<(Vec<WinColumn>, i64, i64)> {
-        let wins = nvim.get_current_tabpage()?.list_wins(nvim)?;
-        // Vector of columns. TODO(Shvedov) here should be used LinkedList, but it does not have an
-        // insert by iter operation. LikedList now has cursor functionality, which is now available
-        // only in nightly.
-        let mut columns: Vec<WinColumn> = Vec::new();
-        let (mut width, mut height) = (0, 0);
-        columns.reserve(wins.len());
-
-        // For each window - create column record and find the place to store it in columns vector.
-        for win in wins {
-            let (win_width, win_height) = (
-                win.get_width(nvim).unwrap_or(0)
-                    + win.get_position(nvim).unwrap_or((0, 0)).1,
-                win.get_height(nvim).unwrap_or(0)
-                    + win.get_position(nvim).unwrap_or((0, 0)).0,
-            );
-            let mut new_column = WinColumn::from_window(win, nvim)?;
-            if new_column.primary_window_mut().is_floating(nvim) {
+    /// Get the scale factor neovide applies to the font
+    fn get_scale_factor(nvim: &Nvim) -> Result<f64> {
+        let scale = nvim.block(nvim.get_var("neovide_scale_factor"))?;
+        scale
+            .as_f64()
+            .or_else(|| scale.as_i64().map(|v| v as f64))
+            .filter(|v| *v > 0.0)
+            .ok_or_else(|| Error::from("Invalid g:neovide_scale_factor"))
+    }
+
+    /// Find existing nvim sockets of process `pid` and its descendants
+    pub fn find_sockets(pid: i32) -> Result<Vec<PathBuf>> {
+        Ok(candidate_sockets(&build_process_tree(Some(pid))?.root))
+    }
+
+    /// Enumerate nvim instances reachable within process tree of `pid`
+    pub fn instances(pid: i32) -> Result<Vec<Instance>> {
+        let instances = Self::connect_all(Some(pid), &Handler::default())?;
+        Ok(instances
+            .into_iter()
+            .map(|(instance, _)| instance)
+            .collect())
+    }
+
+    /// Connect all nvim instances listening within process tree of `pid`
+    ///
+    /// Sockets of found instances are cached for the tree of `pid`.
+    fn connect_all(
+        pid: Option<i32>,
+        handler: &Handler,
+    ) -> Result<Vec<(Instance, Rc<Nvim>)>> {
+        let tree = build_process_tree(pid)?;
+        let mut instances: Vec<(Instance, Rc<Nvim>)> = Vec::new();
+        for socket in candidate_sockets(&tree.root) {
+            // Sockets of crashed instances may be left
+            let Ok(nvim) = Nvim::open(&socket, handler.clone()) else {
                 continue;
+            };
+            let pid = nvim.block(nvim.eval("getpid()"))?.as_i64();
+            let pid = pid.ok_or("Can not get valid pid from vim")? as i32;
+            // The same instance may be found by several addresses
+            if instances.iter().all(|(instance, _)| instance.pid != pid) {
+                instances.push((Instance { pid, socket }, nvim));
             }
-            width = std::cmp::max(width, win_width);
-            height = std::cmp::max(height, win_height);
-            let mut place_to = Some(columns.len());
-            for (i, cur_column) in columns.iter_mut().enumerate() {
-                // Current last less then new first - go next
-                if cur_column.end <= new_column.start {
-                    continue;
-                }
-                // New last less then current first - place new before current
-                if new_column.end <= cur_column.start {
-                    // Place before
-                    place_to = Some(i);
-                    break;
-                }
-                // Columns intersects.
-
-                // First option - when one column is subcolumn of another.
-                // Starts are the same - shrink current and drop new column
-                if cur_column.start == new_column.start {
-                    cur_column.add_other(&mut new_column);
-                    place_to = None;
-                    break;
-                }
-                // Ends are the same - shrink current to start of new and place new after
-                if cur_column.end == new_column.end {
-                    cur_column.end =
-                        std::cmp::min(cur_column.end, new_column.start);
-                    // Wins of current belongs to new too
-                    cur_column.increase_wins_columns();
-                    // Place after
-                    place_to = Some(i + 1);
-                    break;
-                }
-                // New is subcolumn of current
-                if cur_column.start < new_column.start
-                    && new_column.end > cur_column.end
-                {
-                    cur_column.end = new_column.start;
-                    // Wins of current belongs to new too
-                    cur_column.increase_wins_columns();
-                    // Place after
-                    place_to = Some(i + 1);
-                    break;
-                }
-                // Current is subcolumn of new
-                if new_column.start < cur_column.start
-                    && cur_column.end > new_column.end
-                {
-                    new_column.end = cur_column.start;
-                    // Wins of current belongs to new too
-                    new_column.increase_wins_columns();
-                    // Place before
-                    place_to = Some(i);
-                    break;
-                }
+        }
+        if let Some(pid) = pid {
+            let sockets = instances.iter().map(|(i, _)| i.socket.clone());
+            if let Err(err) = Self::cache_sockets(pid, sockets.collect()) {
+                log::warn!("Can not cache nvim sockets of {pid}: {err}");
+            }
+        }
+        Ok(instances)
+    }
 
-                // Bad option - no obvious columns. Ignore new column
-                place_to = None;
-                break;
+    /// Store sockets of nvim instances within process tree of `pid`
+    ///
+    /// Entries of exited processes are dropped meanwhile.
+    fn cache_sockets(pid: i32, sockets: Vec<PathBuf>) -> Result<()> {
+        NvimSockets::update(|cache| {
+            cache.0.retain(|pid, _| pstree::comm(*pid).is_ok());
+            cache.0.insert(pid, sockets);
+            Ok(())
+        })
+    }
+
+    /// Connect nvim instances cached for process tree of `pid`
+    ///
+    /// The cache is valid only when every cached socket is still served by
+    /// nvim within the tree, otherwise the instances are discovered again.
+    fn connect_cached(
+        pid: i32,
+        handler: &Handler,
+    ) -> Option<Vec<(Instance, Rc<Nvim>)>> {
+        let sockets = NvimSockets::load().ok()?.0.remove(&pid)?;
+        let mut instances = Vec::new();
+        for socket in sockets {
+            let nvim = Nvim::open(&socket, handler.clone()).ok()?;
+            let nvim_pid = nvim.block(nvim.eval("getpid()")).ok()?.as_i64()?;
+            let nvim_pid = nvim_pid as i32;
+            // Pid of exited nvim may be reused by unrelated process
+            if !pstree::ancestors(nvim_pid).ok()?.contains(&pid) {
+                return None;
             }
+            let instance = Instance {
+                pid: nvim_pid,
+                socket,
+            };
+            instances.push((instance, nvim));
+        }
+        Some(instances)
+    }
 
-            if let Some(place_to) = place_to {
-                columns.insert(place_to, new_column);
+    /// Connect nvim within process tree of `pid` chosen by `choice`
+    ///
+    /// Cached instances are used while the choice is among them, so the
+    /// process tree is not walked on each call.
+    fn try_connect(
+        pid: Option<i32>,
+        handler: &Handler,
+        choice: &Choice,
+    ) -> Result<Rc<Nvim>> {
+        let cached = pid.and_then(|pid| Self::connect_cached(pid, handler));
+        let mut instances = match cached {
+            Some(cached) if choice.complete(cached.iter().map(|(i, _)| i)) => {
+                cached
             }
+            _ => Self::connect_all(pid, handler)?,
+        };
+        if instances.is_empty() {
+            Err("Can not find nvim socket")?
         }
+        let index = choice
+            .pick(instances.iter().map(|(instance, _)| instance))
+            .ok_or("No nvim instance matches the choice")?;
+        let (instance, nvim) = instances.swap_remove(index);
+        log::debug!(
+            "Connected to nvim {} at {}",
+            instance.pid,
+            instance.socket.display()
+        );
+        Ok(nvim)
+    }
+
+    /// Compute columns of vim windows of current tabpage
+    ///
+    /// The columns are taken from the split tree reported by `winlayout()`.
+    /// Returns the columns together with total width and height of all
+    /// non-floating windows.
+    fn calculate_columns(nvim: &Nvim) -> Result<(Vec<WinColumn>, i64, i64)> {
+        let (mut width, mut height) = (0, 0);
+        let tab = nvim.block(nvim.get_current_tabpage())?;
+        for win in nvim.block(tab.list_wins())? {
+            let (row, col) = nvim.block(win.get_position()).unwrap_or((0, 0));
+            if Win::new(win.clone()).is_floating(nvim) {
+                continue;
+            }
+            let win_width = nvim.block(win.get_width()).unwrap_or(0);
+            let win_height = nvim.block(win.get_height()).unwrap_or(0);
+            width = std::cmp::max(width, col + win_width);
+            height = std::cmp::max(height, row + win_height);
+        }
+        let layout = nvim.call("winlayout", Vec::new())?;
+        let columns = Self::layout_columns(&layout, nvim)?;
         Ok((columns, width, height))
     }
 
+    /// Convert node of `winlayout()` tree to the list of columns
+    ///
+    /// Children of `row` node are placed side by side, so their columns are
+    /// concatenated. Children of `col` node are stacked: the child with the
+    /// most columns defines the columns, the windows of other children are
+    /// merged into them when number of columns matches, otherwise they span
+    /// several columns.
+    fn layout_columns(layout: &Value, nvim: &Nvim) -> Result<Vec<WinColumn>> {
+        let err = || Error::from("Unexpected result of winlayout()");
+        let (kind, content) = match layout.as_array().map(Vec::as_slice) {
+            Some([kind, content]) => (kind.as_str().ok_or_else(err)?, content),
+            _ => Err(err())?,
+        };
+        if kind == "leaf" {
+            let win = nvim.window(content.clone());
+            return Ok(vec![WinColumn::from_window(win, nvim)?]);
+        }
+        let mut children = content
+            .as_array()
+            .ok_or_else(err)?
+            .iter()
+            .map(|child| Self::layout_columns(child, nvim))
+            .collect::<Result<Vec<_>>>()?;
+        match kind {
+            "row" => Ok(children.into_iter().flatten().collect()),
+            "col" => {
+                children.sort_by_key(|c| std::cmp::Reverse(c.len()));
+                let mut children = children.into_iter();
+                let mut columns = children.next().unwrap_or_default();
+                for child in children {
+                    if child.len() == columns.len() {
+                        for (column, other) in columns.iter_mut().zip(child) {
+                            column.merge(other);
+                        }
+                    } else {
+                        let span = columns.len() as i64;
+                        for mut win in child.into_iter().flat_map(|c| c.windows)
+                        {
+                            win.set_columns(span);
+                            columns[0].windows.push(win);
+                        }
+                    }
+                }
+                Ok(columns)
+            }
+            _ => Err(err()),
+        }
+    }
+
+    /// Get columns of current tabpage
     pub fn get_columns(&self) -> &Vec<WinColumn> {
         &self.columns
     }
 
+    /// Get columns of current tabpage to change
     pub fn get_columns_mut(&mut self) -> &mut Vec<WinColumn> {
         &mut self.columns
     }
 
+    /// Get summary of columns
+    pub fn get_columns_info(&mut self) -> Vec<ColumnInfo> {
+        let nvim = &self.nvim;
+        let widths = &self.filetype_widths;
+        self.columns
+            .iter_mut()
+            .map(|c| ColumnInfo {
+                start: c.start,
+                end: c.end,
+                textwidth: c.textwidth(nvim, widths),
+                windows: c.windows.len(),
+            })
+            .collect()
+    }
+
+    /// Get the number of columns
     pub fn get_num_columns(&self) -> Result<usize> {
         Ok(self.get_columns().len())
     }
 
+    /// Width of one symbol in pixels
+    ///
+    /// The symbol width is proportional to the font size scaled by neovide.
     pub fn get_pixels_for_symbol(&self) -> f64 {
-        // TODO(Shvedov): calculate correctly
-        self.font_size as f64 * 0.80093
+        self.font_size * self.scale_factor * self.symbol_ratio
+    }
+
+    /// Use measured symbol ratio and column width koefficient
+    pub fn set_calibration(&mut self, calibration: &Calibration) {
+        self.symbol_ratio = calibration.symbol_ratio;
+        self.column_width_koeff = calibration.column_width_koeff;
+    }
+
+    /// Limit the width of niri window in pixels
+    pub fn set_width_limits(&mut self, min: Option<Width>, max: Option<Width>) {
+        self.min_width = min;
+        self.max_width = max;
+    }
+
+    /// Clamp pixel width of window into configured limits
+    fn clamp_pixel_width(
+        &self,
+        width: i64,
+        soc: &mut niri::Socket,
+    ) -> Result<i64> {
+        if self.min_width.is_none() && self.max_width.is_none() {
+            return Ok(width);
+        }
+        let output =
+            get_logical_output_of_window(&self.niri_window, soc)?.width;
+        let output = output as i64;
+        let mut width = width;
+        if let Some(max) = self.max_width {
+            width = std::cmp::min(width, max.resolve(output));
+        }
+        if let Some(min) = self.min_width {
+            width = std::cmp::max(width, min.resolve(output));
+        }
+        Ok(width)
     }
 
+    /// Set desired widths of buffers by filetype
+    pub fn set_filetype_widths(&mut self, widths: HashMap<String, i64>) {
+        self.filetype_widths = widths;
+    }
+
+    /// Set niri gaps and border width in pixels
+    pub fn set_paddings(&mut self, gaps: f64, border: f64) {
+        self.gaps = gaps;
+        self.border = border;
+    }
+
+    /// Set duration of view offset animation
+    pub fn set_animation(&mut self, animation: Duration) {
+        self.animation = animation;
+    }
+
+    /// Set koefficient of column width to its text width
     pub fn set_column_width_koeff(&mut self, koef: f64) {
         self.column_width_koeff = koef;
     }
 
+    /// Get koefficient of column width to its text width
     pub fn get_column_width_koeff(&self) -> f64 {
         self.column_width_koeff
     }
 
+    /// Width of all columns in symbols which fits their text
     pub fn get_desired_symbol_width(&mut self) -> i64 {
         let k = self.get_column_width_koeff();
-        let cols = &mut self.columns;
-        let nvim = &mut self.nvim;
+        let widths = &self.filetype_widths;
+        Self::symbol_width_of(k, &mut self.columns, &self.nvim, widths)
+    }
+
+    fn symbol_width_of(
+        k: f64,
+        cols: &mut [WinColumn],
+        nvim: &Nvim,
+        widths: &HashMap<String, i64>,
+    ) -> i64 {
         cols.iter_mut()
-            .fold(0.0, |summ, c| summ + (k * (c.textwidth(nvim) as f64)))
+            .fold(0.0, |summ, c| {
+                summ + (k * (c.textwidth(nvim, widths) as f64))
+            })
             .round() as i64
     }
 
+    /// Use columns of the widest tabpage instead of the current one
+    ///
+    /// Keeps the width stable while switching between tabpages with different
+    /// number of splits.
+    pub fn use_widest_tabpage(&mut self) -> Result<()> {
+        let k = self.get_column_width_koeff();
+        let mut widest = self.get_desired_symbol_width();
+        let nvim = &self.nvim;
+        for tab in nvim.block(nvim.list_tabpages())? {
+            let nr = nvim.block(tab.get_number())?;
+            let layout = nvim.call("winlayout", vec![nr.into()])?;
+            let mut columns = Self::layout_columns(&layout, nvim)?;
+            let width = Self::symbol_width_of(
+                k,
+                &mut columns,
+                nvim,
+                &self.filetype_widths,
+            );
+            if width > widest {
+                widest = width;
+                self.columns = columns;
+            }
+        }
+        Ok(())
+    }
+
+    /// Width of all columns in pixels which fits their text
     pub fn get_desired_pixel_width(&mut self) -> i64 {
         (self.get_desired_symbol_width() as f64 * self.get_pixels_for_symbol())
             .round() as i64
     }
 
+    /// Current width of layout in symbols
     pub fn get_current_symbol_width(&mut self) -> i64 {
         self.columns
             .iter()
             .fold(0, |last, c| std::cmp::max(last, c.end))
     }
 
+    /// Current width of layout in pixels
     pub fn get_current_pixel_width(&mut self) -> i64 {
         (self.get_current_symbol_width() as f64 * self.get_pixels_for_symbol())
             .round() as i64
     }
 
-    pub fn sync_width(
-        &mut self,
-        soc: &mut niri_ipc::socket::Socket,
-    ) -> Result<()> {
-        soc.send(niri_ipc::Request::Action(
-            niri_ipc::Action::SetWindowWidth {
+    /// Resize niri window to fit columns and scroll the view to current one
+    pub fn sync_width(&mut self, soc: &mut niri::Socket) -> Result<()> {
+        let width = self.get_desired_pixel_width() + self.border_width();
+        let width = self.clamp_pixel_width(width, soc)?;
+        self.set_pixel_width(width, soc)?;
+        self.shift(soc)
+    }
+
+    /// Width of both borders of window in pixels
+    fn border_width(&self) -> i64 {
+        (2.0 * self.border).round() as i64
+    }
+
+    /// Height of one line in pixels
+    pub fn get_pixels_for_line(&self) -> f64 {
+        self.font_size * self.scale_factor * LINE_RATIO
+    }
+
+    /// Set height of niri window to fit all vim lines
+    ///
+    /// These are lines of current buffer together with tabline, statusline
+    /// and command line when they are shown.
+    pub fn sync_height(&mut self, soc: &mut niri::Socket) -> Result<()> {
+        let lines = self
+            .eval(CONTENT_LINES)?
+            .as_i64()
+            .ok_or_else(|| Error::from("Can not get valid height from vim"))?;
+        let height = (lines as f64 * self.get_pixels_for_line()).round();
+        niri::action(
+            soc,
+            niri_ipc::Action::SetWindowHeight {
                 id: Some(self.niri_window.id),
-                change: niri_ipc::SizeChange::SetFixed(
-                    self.get_desired_pixel_width() as i32,
-                ),
+                change: niri_ipc::SizeChange::SetFixed(height as i32),
             },
-        ))??;
+        )?;
+        Ok(())
+    }
+
+    /// Distribute width of vim among columns proportionally to textwidths
+    ///
+    /// This is the reverse of [Vim::sync_width]: the niri window width is kept
+    /// and vim splits are resized to fit it.
+    pub fn fit(&mut self) -> Result<()> {
+        let total = self.get_vim_columns()?;
+        let nvim = &self.nvim;
+        let widths = &self.filetype_widths;
+        let textwidths: Vec<i64> = self
+            .columns
+            .iter_mut()
+            .map(|c| c.textwidth(nvim, widths))
+            .collect();
+        let desired: i64 = textwidths.iter().sum();
+        if desired <= 0 {
+            return Ok(());
+        }
+        // Each column but the last one is followed by separator
+        let available = total - (textwidths.len() as i64 - 1);
+        for (column, textwidth) in self.columns.iter().zip(textwidths) {
+            let width = available * textwidth / desired;
+            if dryrun::skip(format_args!("nvim set column width {width}")) {
+                continue;
+            }
+            let win = &column.primary_window().win;
+            nvim.block(win.set_width(std::cmp::max(width, 1)))?;
+        }
+        Ok(())
+    }
+
+    /// Switch window width to the next of presets
+    ///
+    /// The current preset is the one closest to the current window width.
+    pub fn cycle_width(
+        &mut self,
+        presets: &[WidthPreset],
+        soc: &mut niri::Socket,
+    ) -> Result<()> {
+        if presets.is_empty() {
+            return Err(Error::from("No width presets configured"));
+        }
+        let output =
+            get_logical_output_of_window(&self.niri_window, soc)?.width;
+        let fit = self.get_desired_pixel_width() + self.border_width();
+        let fit = self.clamp_pixel_width(fit, soc)?;
+        let widths: Vec<i64> = presets
+            .iter()
+            .map(|preset| match preset {
+                WidthPreset::Fit => fit,
+                WidthPreset::Width(width) => width.resolve(output as i64),
+            })
+            .collect();
+        let current = (self.get_vim_columns()? as f64
+            * self.get_pixels_for_symbol())
+        .round() as i64;
+        let (index, _) = widths
+            .iter()
+            .enumerate()
+            .min_by_key(|(_, width)| (*width - current).abs())
+            .unwrap();
+        self.set_pixel_width(widths[(index + 1) % widths.len()], soc)?;
         self.shift(soc)
     }
 
-    pub fn shift(&mut self, soc: &mut niri_ipc::socket::Socket) -> Result<()> {
-        let mode = get_output_mode_of_window(&self.niri_window, soc)?;
-        let win = self.nvim.get_current_win()?;
-        let pos = win.get_position(&mut self.nvim)?;
-        let start =
-            std::cmp::max(pos.1 - 1, 0) as f64 * self.get_pixels_for_symbol();
-        let end = (pos.1 + win.get_width(&mut self.nvim)?) as f64
-            * self.get_pixels_for_symbol();
+    /// Scroll the niri view to show current vim window
+    pub fn shift(&mut self, soc: &mut niri::Socket) -> Result<()> {
+        let output = get_logical_output_of_window(&self.niri_window, soc)?;
+        let win = self.nvim.block(self.nvim.get_current_win())?;
+        let pos = self.nvim.block(win.get_position())?;
+        // Visible part of window must include the gap around it
+        let start = std::cmp::max(pos.1 - 1, 0) as f64
+            * self.get_pixels_for_symbol()
+            + self.border
+            - self.gaps;
+        let end = (pos.1 + self.nvim.block(win.get_width())?) as f64
+            * self.get_pixels_for_symbol()
+            + self.border
+            + self.gaps;
 
-        let offset = if (self.niri_window.view_offset + mode.width as f64) < end
-        {
-            Some(end - (mode.width as f64))
-        } else if self.niri_window.view_offset > start {
-            Some(start)
-        } else {
-            None
-        };
+        let offset =
+            if (self.niri_window.view_offset + output.width as f64) < end {
+                Some(end - (output.width as f64))
+            } else if self.niri_window.view_offset > start {
+                Some(start)
+            } else {
+                None
+            };
 
         if let Some(offset) = offset {
-            soc.send(niri_ipc::Request::Action(
+            self.animate_view_offset(offset, soc)?;
+        }
+
+        Ok(())
+    }
+
+    /// Move view to offset in several steps during animation duration
+    fn animate_view_offset(
+        &self,
+        offset: f64,
+        soc: &mut niri::Socket,
+    ) -> Result<()> {
+        let frames = std::cmp::max(
+            self.animation.as_millis() / FRAME_INTERVAL.as_millis(),
+            1,
+        ) as u32;
+        let from = self.niri_window.view_offset;
+        for frame in 1..=frames {
+            // Ease out: fast start and slow finish
+            let progress = 1.0 - (1.0 - frame as f64 / frames as f64).powi(2);
+            niri::action(
+                soc,
                 niri_ipc::Action::ViewOffset {
                     id: Some(self.niri_window.id),
-                    offset,
+                    offset: from + (offset - from) * progress,
                 },
-            ))??;
+            )?;
+            if frame < frames {
+                thread::sleep(FRAME_INTERVAL);
+            }
         }
-
         Ok(())
     }
 
+    /// Print computed widths to stdout
     pub fn test(&mut self) -> Result<()> {
         let nums = self.get_num_columns()?;
         let sym_w = self.get_desired_symbol_width();
@@ -450,6 +1097,83 @@ impl Vim {
         Ok(())
     }
 
+    fn set_pixel_width(
+        &mut self,
+        width: i64,
+        soc: &mut niri::Socket,
+    ) -> Result<()> {
+        niri::action(
+            soc,
+            niri_ipc::Action::SetWindowWidth {
+                id: Some(self.niri_window.id),
+                change: niri_ipc::SizeChange::SetFixed(width as i32),
+            },
+        )?;
+        Ok(())
+    }
+
+    fn get_vim_columns(&mut self) -> Result<i64> {
+        self.eval("&columns")?
+            .as_i64()
+            .ok_or_else(|| Error::from("Can not get valid &columns from vim"))
+    }
+
+    /// Interactively calibrate width calculation
+    ///
+    /// At first the pixels-per-symbol ratio is measured by setting known
+    /// window width and reading the number of vim columns. Then user adjusts
+    /// the width until all splits fit their content and the width coefficient
+    /// is computed from the result.
+    pub fn calibrate(&mut self, soc: &mut niri::Socket) -> Result<Calibration> {
+        let width = self.get_desired_pixel_width();
+        self.set_pixel_width(width, soc)?;
+        thread::sleep(RESIZE_DELAY);
+        let columns = self.get_vim_columns()?;
+        if columns <= 0 {
+            return Err(Error::from("Vim reports no columns"));
+        }
+        self.symbol_ratio = width as f64
+            / columns as f64
+            / (self.font_size * self.scale_factor);
+        println!(
+            "Measured {:.3} pixels per symbol ({} columns in {} pixels)",
+            self.get_pixels_for_symbol(),
+            columns,
+            width
+        );
+
+        let step = std::cmp::max(self.get_num_columns()?, 1) as i64;
+        let textwidth = self.get_desired_symbol_width() as f64
+            / self.get_column_width_koeff();
+        let mut symbols = self.get_desired_symbol_width();
+        let mut input = io::stdin().lock();
+        loop {
+            let width =
+                (symbols as f64 * self.get_pixels_for_symbol()).round() as i64;
+            self.set_pixel_width(width, soc)?;
+            print!(
+                "Width is {symbols} symbols. Enter '+' to widen, '-' to \
+                 narrow or empty line when all splits fit: "
+            );
+            io::stdout().flush()?;
+            let mut line = String::new();
+            if input.read_line(&mut line)? == 0 {
+                return Err(Error::from("Calibration aborted"));
+            }
+            match line.trim() {
+                "" => break,
+                "+" => symbols += step,
+                "-" => symbols = std::cmp::max(symbols - step, step),
+                other => println!("Unknown input '{other}'"),
+            }
+        }
+        self.column_width_koeff = symbols as f64 / textwidth;
+        Ok(Calibration {
+            symbol_ratio: self.symbol_ratio,
+            column_width_koeff: self.column_width_koeff,
+        })
+    }
+
     fn get_vim_cmd_direction<'a, 'b>(
         &'a mut self,
         direction: &'b Direction,
@@ -461,10 +1185,11 @@ impl Vim {
             pub left: bool,
             pub right: bool,
         }
-        let win = self.nvim.get_current_win()?;
-        let (row, col) = win.get_position(&mut self.nvim)?;
-        let width = col + win.get_width(&mut self.nvim)?;
-        let height = row + win.get_height(&mut self.nvim)?;
+        let nvim = &self.nvim;
+        let win = nvim.block(nvim.get_current_win())?;
+        let (row, col) = nvim.block(win.get_position())?;
+        let width = col + nvim.block(win.get_width())?;
+        let height = row + nvim.block(win.get_height())?;
         let borders = Borders {
             top: row == 0,
             bottom: height == self.height,
@@ -477,26 +1202,39 @@ impl Vim {
             Direction::Down => borders.bottom,
             Direction::Left => borders.left,
             Direction::Right => borders.right,
+            Direction::First | Direction::Prev => {
+                self.eval("winnr() == 1")?.as_i64() == Some(1)
+            }
+            Direction::Last | Direction::Next => {
+                self.eval("winnr() == winnr('$')")?.as_i64() == Some(1)
+            }
         };
+        if at_border {
+            log::debug!("Vim is at {direction} border, falling back to niri");
+        }
         Ok(if !at_border { Some(direction) } else { None })
     }
 
+    /// Focus vim window in `direction` or niri one at the border of layout
     pub fn switch(
         &mut self,
-        soc: &mut niri_ipc::socket::Socket,
+        soc: &mut niri::Socket,
         direction: &Direction,
+        wrap: bool,
     ) -> Result<()> {
         if let Some(action) = self.get_vim_cmd_direction(direction)? {
-            self.send_window_input([&*format!("<{}>", action)].iter())?;
-        } else {
-            Launcher::switch_niri(soc, direction)?;
+            self.send_window_input([action.vim_key()].iter())?;
+        } else if !Launcher::switch_niri(soc, direction, wrap)? {
+            let key = direction.vim_wrap_key();
+            self.command(&format!("999wincmd {key}"))?;
         };
         Ok(())
     }
 
+    /// Move vim window in `direction` or niri one at the border of layout
     pub fn move_window(
         &mut self,
-        soc: &mut niri_ipc::socket::Socket,
+        soc: &mut niri::Socket,
         direction: &Direction,
     ) -> Result<()> {
         let rotation =
@@ -506,6 +1244,10 @@ impl Vim {
                     Direction::Down => ["r"].iter(),
                     Direction::Left => ["<Left>", "x"].iter(),
                     Direction::Right => ["x", "<Right>"].iter(),
+                    Direction::First => ["H"].iter(),
+                    Direction::Last => ["L"].iter(),
+                    Direction::Next => ["x", "w"].iter(),
+                    Direction::Prev => ["W", "x"].iter(),
                 })
             } else {
                 None
@@ -518,46 +1260,302 @@ impl Vim {
         Ok(())
     }
 
+    /// Exchange buffers of current window and its neighbour in direction
+    ///
+    /// The cursor follows the current buffer. At the vim border niri windows
+    /// are swapped instead.
+    pub fn swap(
+        &mut self,
+        soc: &mut niri::Socket,
+        direction: &Direction,
+    ) -> Result<()> {
+        if self.get_vim_cmd_direction(direction)?.is_none() {
+            return Launcher::swap_niri(soc, direction);
+        }
+        let nr = self.eval(&direction.vim_winnr())?;
+        let nvim = &self.nvim;
+        let other = nvim.call("win_getid", vec![nr.clone()])?;
+        let other = nvim.window(other);
+        let current = nvim.block(nvim.get_current_win())?;
+        if dryrun::skip(format_args!("nvim swap buffers with window {nr}")) {
+            return Ok(());
+        }
+        let buf = nvim.block(current.get_buf())?;
+        let other_buf = nvim.block(other.get_buf())?;
+        let cursor = nvim.block(current.get_cursor())?;
+        let other_cursor = nvim.block(other.get_cursor())?;
+        nvim.block(current.set_buf(&other_buf))?;
+        nvim.block(other.set_buf(&buf))?;
+        nvim.block(current.set_cursor(other_cursor))?;
+        nvim.block(other.set_cursor(cursor))?;
+        nvim.block(nvim.set_current_win(&other))?;
+        Ok(())
+    }
+
+    /// Toggle fullscreen of neovide and fit splits to the new size
+    pub fn toggle_fullscreen(&mut self, soc: &mut niri::Socket) -> Result<()> {
+        niri::action(
+            soc,
+            niri_ipc::Action::FullscreenWindow {
+                id: Some(self.niri_window.id),
+            },
+        )?;
+        thread::sleep(RESIZE_DELAY);
+        self.fit()
+    }
+
+    /// Send keys to vim as if typed by user
+    pub fn send_keys(&mut self, keys: &str) -> Result<()> {
+        self.input(keys)?;
+        Ok(())
+    }
+
+    /// Run tabpage operation
+    pub fn tab(&mut self, tab: &Tab) -> Result<()> {
+        self.command(tab.vim_command())?;
+        Ok(())
+    }
+
+    /// Maximize current window or restore layout of zoomed tabpage
+    ///
+    /// The layout is stored in tabpage variable of vim.
+    pub fn zoom(&mut self) -> Result<()> {
+        self.command(ZOOM_COMMAND)?;
+        Ok(())
+    }
+
+    /// Resize current split in direction, right and down grow it
+    ///
+    /// Percents are relative to the whole vim. Returns false when the split
+    /// has no neighbours in the direction's axis, so there is nothing to resize
+    /// within vim.
+    pub fn resize(
+        &mut self,
+        direction: &Direction,
+        amount: &Width,
+    ) -> Result<bool> {
+        let (width, grow) = direction.resize_axis()?;
+        let (keys, total, pixels) = if width {
+            ("hl", "&columns", self.get_pixels_for_symbol())
+        } else {
+            ("kj", "&lines", self.get_pixels_for_line())
+        };
+        let mut alone = true;
+        for key in keys.chars() {
+            let expr = format!("winnr('{key}') == winnr()");
+            alone &= self.eval(&expr)?.as_i64() == Some(1);
+        }
+        if alone {
+            return Ok(false);
+        }
+        let total = self.eval(total)?.as_i64().ok_or_else(|| {
+            Error::from(format!("Can not get valid {total} from vim"))
+        })?;
+        let delta = match amount {
+            Width::Pixels(px) => (*px as f64 / pixels).round() as i64,
+            Width::Fraction(_) => amount.resolve(total),
+        }
+        .max(1);
+        let delta = if grow { delta } else { -delta };
+        if dryrun::skip(format_args!("nvim resize current window by {delta}")) {
+            return Ok(true);
+        }
+        let nvim = &self.nvim;
+        let win = nvim.block(nvim.get_current_win())?;
+        if width {
+            let current = nvim.block(win.get_width())?;
+            nvim.block(win.set_width((current + delta).max(1)))?;
+        } else {
+            let current = nvim.block(win.get_height())?;
+            nvim.block(win.set_height((current + delta).max(1)))?;
+        }
+        Ok(true)
+    }
+
+    /// Make all vim windows (almost) equally high and wide
+    pub fn equalize(&mut self) -> Result<()> {
+        self.send_window_input(["="].iter())
+    }
+
+    /// Close current vim window or quit vim on the last one
     pub fn close_window(
         &mut self,
         force: bool,
-        soc: &mut niri_ipc::socket::Socket,
+        write: bool,
+        soc: &mut niri::Socket,
     ) -> Result<()> {
-        self.nvim
-            .session
-            .call("nvim_win_close", vec![0.into(), force.into()])
-            .map_err(
-                // TODO(Shvedov): Should show the error message to vim
-                |e| e.to_string(),
-            )?;
+        if write {
+            self.command("update")?;
+        }
+        if self.is_last_window()? {
+            // Vim refuses to close its last window. Quit it instead, neovide
+            // and its niri window follow. Input is used to not block on
+            // confirmation of unsaved changes.
+            let quit = match (force, write) {
+                (true, _) => "qall!",
+                (_, true) => "confirm xall",
+                _ => "confirm qall",
+            };
+            self.input(&format!("<C-\\><C-N>:{quit}<CR>"))?;
+            return Ok(());
+        }
+        if dryrun::skip(format_args!("nvim close current window")) {
+            return Ok(());
+        }
+        // TODO(Shvedov): Should show the error message to vim
+        let win = self.nvim.block(self.nvim.get_current_win())?;
+        self.nvim.block(win.close(force))?;
+        self.sync_width(soc)
+    }
+
+    /// Number of listed buffers with unsaved changes
+    pub fn modified_buffers(&mut self) -> Result<i64> {
+        self.eval("len(filter(getbufinfo({'buflisted': 1}), 'v:val.changed'))")?
+            .as_i64()
+            .ok_or_else(|| Error::from("Can not get modified buffers from vim"))
+    }
+
+    /// Whenever the current window is the only non-floating one in all
+    /// tabpages
+    fn is_last_window(&mut self) -> Result<bool> {
+        let nvim = &self.nvim;
+        let current = Win::new(nvim.block(nvim.get_current_win())?);
+        if current.is_floating(nvim)
+            || nvim.block(nvim.list_tabpages())?.len() > 1
+        {
+            return Ok(false);
+        }
+        let mut count = 0;
+        for win in nvim.block(nvim.list_wins())? {
+            if !Win::new(win).is_floating(nvim) {
+                count += 1;
+            }
+        }
+        Ok(count <= 1)
+    }
+
+    /// Split current window to the left and open file explorer in it
+    pub fn run(&mut self, focus: bool, soc: &mut niri::Socket) -> Result<()> {
+        if dryrun::skip(format_args!("nvim split current window")) {
+            return Ok(());
+        }
+        let nvim = &self.nvim;
+        let buf = nvim.block(nvim.get_current_buf())?;
+        let config =
+            vec![("split".into(), "left".into()), ("win".into(), 0.into())];
+        // TODO(Shvedov): Should show the error message to vim
+        nvim.block(nvim.open_win(&buf, focus, config))?;
+        self.command("Ex")?;
         self.sync_width(soc)
     }
 
-    pub fn run(
+    /// Focus vim window showing the file if vim has it loaded
+    ///
+    /// If the buffer of file is hidden it is shown in the current window.
+    /// Returns whenever the file was found.
+    pub fn focus_file(
+        &mut self,
+        file: &Path,
+        soc: &mut niri::Socket,
+    ) -> Result<bool> {
+        let nvim = &self.nvim;
+        let mut found = None;
+        for buf in nvim.block(nvim.list_bufs())? {
+            let name = nvim.block(buf.get_name())?;
+            if !name.is_empty() && Path::new(&name) == file {
+                found = Some(buf);
+                break;
+            }
+        }
+        let Some(buf) = found else {
+            return Ok(false);
+        };
+        let number = nvim.block(buf.get_number())?;
+        let winid = nvim
+            .call("bufwinid", vec![number.into()])?
+            .as_i64()
+            .unwrap_or(-1);
+        if !dryrun::skip(format_args!("nvim show buffer {number}")) {
+            if winid > 0 {
+                let win = nvim.window(Value::from(winid));
+                nvim.block(nvim.set_current_win(&win))?;
+            } else {
+                nvim.block(nvim.set_current_buf(&buf))?;
+            }
+        }
+        niri::action(
+            soc,
+            niri_ipc::Action::FocusWindow {
+                id: self.niri_window.id,
+            },
+        )?;
+        Ok(true)
+    }
+
+    /// Open files in current window and focus vim
+    pub fn open(
         &mut self,
-        focus: bool,
-        soc: &mut niri_ipc::socket::Socket,
+        files: &[PathBuf],
+        soc: &mut niri::Socket,
     ) -> Result<()> {
-        self.nvim
-            .session
-            .call(
-                "nvim_open_win",
-                vec![
-                    0.into(),
-                    focus.into(),
-                    (vec![
-                        ("split".into(), "left".into()),
-                        ("win".into(), 0.into()),
-                    ])
-                    .into(),
-                ],
-            )
-            .map_err(
-                // TODO(Shvedov): Should show the error message to vim
-                |e| e.to_string(),
-            )?;
-        self.nvim.command("Ex")?;
-        self.sync_width(soc)
+        for file in files {
+            let file = file
+                .to_str()
+                .ok_or_else(|| Error::from("Non-unicode path of file"))?;
+            let nvim = &self.nvim;
+            let file = nvim.call("fnameescape", vec![file.into()])?;
+            let file = file.as_str().ok_or_else(|| {
+                Error::from("Unexpected result of fnameescape")
+            })?;
+            self.command(&format!("edit {file}"))?;
+        }
+        niri::action(
+            soc,
+            niri_ipc::Action::FocusWindow {
+                id: self.niri_window.id,
+            },
+        )?;
+        Ok(())
+    }
+
+    /// Move cursor of current window to the line and column
+    pub fn set_cursor(&mut self, line: i64, column: i64) -> Result<()> {
+        if dryrun::skip(format_args!("nvim cursor({line}, {column})")) {
+            return Ok(());
+        }
+        let cursor = vec![line.into(), column.into()];
+        self.nvim.call("cursor", cursor)?;
+        Ok(())
+    }
+
+    /// Evaluate vim expression
+    fn eval(&self, expr: &str) -> Result<Value> {
+        let value = self.nvim.block(self.nvim.eval(expr))?;
+        let request = serde_json::json!({ "eval": expr });
+        trace::record("nvim", &request, &value.to_string());
+        Ok(value)
+    }
+
+    /// Run vim command, it is only printed in dry-run mode
+    fn command(&mut self, cmd: &str) -> Result<()> {
+        if !dryrun::skip(format_args!("nvim command {cmd}")) {
+            self.nvim.block(self.nvim.command(cmd))?;
+            trace::record("nvim", &serde_json::json!({ "command": cmd }), &());
+        }
+        Ok(())
+    }
+
+    /// Feed keys to vim, they are only printed in dry-run mode
+    fn input(&mut self, keys: &str) -> Result<()> {
+        if !dryrun::skip(format_args!("nvim input {keys}")) {
+            let typed = self.nvim.block(self.nvim.input(keys))?;
+            trace::record(
+                "nvim",
+                &serde_json::json!({ "input": keys }),
+                &typed,
+            );
+        }
+        Ok(())
     }
 
     fn send_window_input<'a, I: IntoIterator<Item = &'a &'a str>>(
@@ -566,18 +1564,19 @@ impl Vim {
     ) -> Result<()> {
         for key in keys.into_iter() {
             let cmd = format!("<Esc><C-w>{}", key);
-            self.nvim.input(&cmd)?;
+            self.input(&cmd)?;
         }
         Ok(())
     }
 
+    /// Get the working directory of vim
     pub fn get_cwd(&mut self) -> Result<String> {
-        Ok(self.nvim.command_output("pwd")?.as_str().into())
+        self.nvim.output("pwd")
     }
 
+    /// Get the pid of nvim process
     pub fn get_pid(&mut self) -> Result<i32> {
-        self.nvim
-            .call_function("getpid", Default::default())?
+        self.eval("getpid()")?
             .as_i64()
             .ok_or_else(|| {
                 crate::error::Error::from("Can not get valid pid from vim")
@@ -586,14 +1585,98 @@ impl Vim {
     }
 }
 
-fn get_output_mode_of_window(
+/// Addresses nvim within process tree may listen on, most specific first
+///
+/// Processes spawned by nvim inherit its address in `NVIM`, nvim started with
+/// `NVIM_LISTEN_ADDRESS` listens on it. `NVIM` of the root process belongs to
+/// nvim outside of the tree and is ignored. Default server names of all
+/// processes are enumerated after them. Only existing paths are returned.
+fn candidate_sockets(root: &ProcessTreeNode) -> Vec<PathBuf> {
+    fn collect(node: &ProcessTreeNode, pids: &mut Vec<i32>) {
+        pids.push(node.record.pid);
+        for child in node.children.iter() {
+            collect(child, pids);
+        }
+    }
+    let mut pids = Vec::new();
+    collect(root, &mut pids);
+    let environs: Vec<_> = pids
+        .into_iter()
+        .map(|pid| (pid, pstree::environ(pid).unwrap_or_default()))
+        .collect();
+    let outer = environs[0].1.get("NVIM");
+    let mut sockets = Vec::new();
+    for (_, env) in environs.iter() {
+        sockets.extend(env.get("NVIM").filter(|addr| Some(*addr) != outer));
+        sockets.extend(env.get("NVIM_LISTEN_ADDRESS"));
+    }
+    let mut sockets: Vec<PathBuf> =
+        sockets.into_iter().map(PathBuf::from).collect();
+    for (pid, env) in environs.iter() {
+        sockets.extend(default_servernames(*pid, env));
+    }
+    let mut seen = HashSet::new();
+    sockets.retain(|path| path.exists() && seen.insert(path.clone()));
+    sockets
+}
+
+/// Default server names nvim of process `pid` may listen on
+///
+/// Nvim listens on `$XDG_RUNTIME_DIR/nvim.<pid>.<n>` or, without runtime
+/// directory, on `$TMPDIR/nvim.<user>/<random>/nvim.<pid>.<n>`.
+fn default_servernames(
+    pid: i32,
+    env: &HashMap<String, String>,
+) -> Vec<PathBuf> {
+    let uid = unistd::geteuid();
+    let mut dirs: Vec<PathBuf> = env
+        .get("XDG_RUNTIME_DIR")
+        .map(PathBuf::from)
+        .into_iter()
+        .collect();
+    let tmp = env.get("TMPDIR").map(String::as_str).unwrap_or("/tmp");
+    let user = env.get("USER").cloned().unwrap_or_else(|| uid.to_string());
+    if let Ok(entries) =
+        fs::read_dir(Path::new(tmp).join(format!("nvim.{user}")))
+    {
+        dirs.extend(entries.filter_map(|entry| Some(entry.ok()?.path())));
+    }
+    // Environment of processes of other users is not readable
+    dirs.push(PathBuf::from(format!("/run/user/{uid}")));
+    let prefix = format!("nvim.{pid}.");
+    let mut names: Vec<PathBuf> = dirs
+        .iter()
+        .filter_map(|dir| fs::read_dir(dir).ok())
+        .flatten()
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| {
+            entry
+                .file_name()
+                .to_str()
+                .is_some_and(|name| name.starts_with(&prefix))
+        })
+        .map(|entry| entry.path())
+        .collect();
+    names.sort();
+    names
+}
+
+/// Local option of buffer
+fn buf_option(nvim: &Nvim, buf: &Buffer, name: &str) -> Result<Value> {
+    let number = nvim.block(buf.get_number())?;
+    let opts = vec![("buf".into(), number.into())];
+    nvim.block(nvim.get_option_value(name, opts))
+}
+
+/// Get logical geometry of output the window is shown on
+fn get_logical_output_of_window(
     win: &niri_ipc::Window,
-    soc: &mut niri_ipc::socket::Socket,
-) -> Result<niri_ipc::Mode> {
+    soc: &mut niri::Socket,
+) -> Result<niri_ipc::LogicalOutput> {
     let id = win
         .workspace_id
         .ok_or(String::from("Unknown workspace of window"))?;
-    let reply = soc.send(niri_ipc::Request::Workspaces)??;
+    let reply = niri::request(soc, niri_ipc::Request::Workspaces)?;
     let workspaces = match reply {
         niri_ipc::Response::Workspaces(workspaces) => Ok(workspaces),
         _ => Err(String::from("Unexpected response type for Workspaces")),
@@ -607,7 +1690,7 @@ fn get_output_mode_of_window(
         .output
         .as_ref()
         .ok_or(String::from("Window atteched to hidden workspace"))?;
-    let reply = soc.send(niri_ipc::Request::Outputs)??;
+    let reply = niri::request(soc, niri_ipc::Request::Outputs)?;
     let mut outputs = match reply {
         niri_ipc::Response::Outputs(outputs) => Ok(outputs),
         _ => Err(String::from("Unexpected response type for Outputs")),
@@ -616,17 +1699,10 @@ fn get_output_mode_of_window(
         .get_mut(outputname)
         .ok_or(String::from("Can not find output of window"))?;
 
-    let modeindex = output
-        .current_mode
-        .ok_or(String::from("Window belongs to disabled output"))?;
-
-    if output.modes.len() <= modeindex {
-        Err(Error::from(format!(
-            "Output references to invalid mode: {} of {}",
-            modeindex,
-            output.modes.len()
-        )))
-    } else {
-        Ok(output.modes.swap_remove(modeindex))
-    }
+    // Logical size accounts output scale and transform, niri sizes and
+    // offsets are in logical pixels
+    output
+        .logical
+        .take()
+        .ok_or(Error::from("Window belongs to disabled output"))
 }
@@ -0,0 +1,230 @@
+//! Support of classic vim compiled with `+clientserver`
+//!
+//! Such vim can not be driven with RPC, so it is controlled with
+//! `vim --remote-send` and `vim --remote-expr` using the servername found in
+//! the process tree.
+
+use super::{
+    Direction, Launcher, Tab, dryrun, error::Result, niri, pstree, vim,
+};
+use std::{path::Path, process};
+
+/// Names of vim executables with client-server support
+const VIMS: &[&str] = &["vim", "gvim", "vimx"];
+
+/// Whenever the process is vim with client-server support
+fn is_vim(pid: i32) -> bool {
+    pstree::comm(pid)
+        .map(|name| VIMS.contains(&name.as_str()))
+        .unwrap_or(false)
+}
+
+pub struct VimServer {
+    program: String,
+    name: String,
+}
+
+impl VimServer {
+    /// Find vim server among process and its descendants
+    pub fn discover(pid: i32) -> Result<Option<Self>> {
+        let tree = pstree::build_process_tree(Some(pid))?;
+        match tree.root.find(&|node| is_vim(node.record.pid)) {
+            Some(node) => Self::from_pid(node.record.pid),
+            None => Ok(None),
+        }
+    }
+
+    /// Find vim server among given processes only
+    ///
+    /// Used when foreground processes of terminal window are known, so the
+    /// process tree of the whole terminal is not scanned.
+    pub fn discover_among(pids: &[i32]) -> Result<Option<Self>> {
+        match pids.iter().find(|pid| is_vim(**pid)) {
+            Some(pid) => Self::from_pid(*pid),
+            None => Ok(None),
+        }
+    }
+
+    /// Get vim server of vim process
+    fn from_pid(pid: i32) -> Result<Option<Self>> {
+        let cmdline = pstree::cmdline(pid)?;
+        let program = match cmdline.first() {
+            Some(program) => program.clone(),
+            None => return Ok(None),
+        };
+        let name = cmdline
+            .iter()
+            .skip_while(|arg| *arg != "--servername")
+            .nth(1)
+            .cloned()
+            .or_else(|| {
+                // Gui vim registers server with default name
+                let exe = Path::new(&program).file_name()?.to_str()?;
+                (exe == "gvim").then(|| "GVIM".to_string())
+            });
+        let name = match name {
+            Some(name) => name,
+            None => return Ok(None),
+        };
+        let server = Self { program, name };
+        Ok(if server.is_alive()? {
+            Some(server)
+        } else {
+            None
+        })
+    }
+
+    fn run(&self, args: &[&str]) -> Result<String> {
+        let output = process::Command::new(&self.program)
+            .arg("--servername")
+            .arg(&self.name)
+            .args(args)
+            .stdin(process::Stdio::null())
+            .output()?;
+        if !output.status.success() {
+            Err(format!(
+                "{} {}: {}",
+                self.program,
+                args.join(" "),
+                String::from_utf8_lossy(&output.stderr).trim()
+            ))?
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).trim().into())
+    }
+
+    fn is_alive(&self) -> Result<bool> {
+        Ok(self
+            .run(&["--serverlist"])?
+            .lines()
+            .any(|name| name.eq_ignore_ascii_case(&self.name)))
+    }
+
+    pub fn remote_send(&self, keys: &str) -> Result<()> {
+        if dryrun::skip(format_args!("vim --remote-send {keys}")) {
+            return Ok(());
+        }
+        self.run(&["--remote-send", &format!("<C-\\><C-N>{keys}")])?;
+        Ok(())
+    }
+
+    pub fn remote_expr(&self, expr: &str) -> Result<String> {
+        self.run(&["--remote-expr", expr])
+    }
+
+    fn get_cmd_key(direction: &Direction) -> &'static str {
+        direction.vim_key()
+    }
+
+    /// Keys which move window in direction
+    fn get_move_keys(direction: &Direction) -> &'static str {
+        match direction {
+            Direction::Up | Direction::First => "<C-w>K",
+            Direction::Down | Direction::Last => "<C-w>J",
+            Direction::Left => "<C-w>H",
+            Direction::Right => "<C-w>L",
+            Direction::Next => "<C-w>x<C-w>w",
+            Direction::Prev => "<C-w>W<C-w>x",
+        }
+    }
+
+    /// Whenever there is vim window in direction
+    fn has_window(&self, direction: &Direction) -> Result<bool> {
+        let nr = direction.vim_winnr();
+        let expr =
+            format!("{nr} != winnr() && {nr} >= 1 && {nr} <= winnr('$')");
+        Ok(self.remote_expr(&expr)? == "1")
+    }
+
+    pub fn switch(
+        &self,
+        soc: &mut niri::Socket,
+        direction: &Direction,
+        wrap: bool,
+    ) -> Result<()> {
+        if self.has_window(direction)? {
+            self.remote_send(&format!("<C-w>{}", Self::get_cmd_key(direction)))
+        } else if !Launcher::switch_niri(soc, direction, wrap)? {
+            let key = direction.vim_wrap_key();
+            self.remote_send(&format!(":999wincmd {key}<CR>"))
+        } else {
+            Ok(())
+        }
+    }
+
+    pub fn move_window(
+        &self,
+        soc: &mut niri::Socket,
+        direction: &Direction,
+    ) -> Result<()> {
+        if self.has_window(direction)? {
+            self.remote_send(Self::get_move_keys(direction))
+        } else {
+            Launcher::move_niri(soc, direction)
+        }
+    }
+
+    /// Exchange buffers of current window and its neighbour in direction
+    ///
+    /// The cursor follows the current buffer.
+    pub fn swap(
+        &self,
+        soc: &mut niri::Socket,
+        direction: &Direction,
+    ) -> Result<()> {
+        if self.has_window(direction)? {
+            let key = Self::get_cmd_key(direction);
+            self.remote_send(&format!(
+                ":let g:niri_swap = [bufnr()] | wincmd {key} | \
+                 call add(g:niri_swap, bufnr()) | exe 'b' g:niri_swap[0] | \
+                 wincmd p | exe 'b' g:niri_swap[1] | wincmd p | \
+                 unlet g:niri_swap<CR>"
+            ))
+        } else {
+            Launcher::swap_niri(soc, direction)
+        }
+    }
+
+    /// Send keys to vim as if typed by user
+    ///
+    /// Unlike [Self::remote_send] keys are sent in the current mode.
+    pub fn send_keys(&self, keys: &str) -> Result<()> {
+        if dryrun::skip(format_args!("vim --remote-send {keys}")) {
+            return Ok(());
+        }
+        self.run(&["--remote-send", keys])?;
+        Ok(())
+    }
+
+    /// Run tabpage operation
+    pub fn tab(&self, tab: &Tab) -> Result<()> {
+        self.remote_send(&format!(":{}<CR>", tab.vim_command()))
+    }
+
+    /// Maximize current window or restore layout of zoomed tabpage
+    pub fn zoom(&self) -> Result<()> {
+        self.remote_send(&format!(":{}<CR>", vim::ZOOM_COMMAND))
+    }
+
+    /// Make all vim windows (almost) equally high and wide
+    pub fn equalize(&self) -> Result<()> {
+        self.remote_send("<C-w>=")
+    }
+
+    /// Close current vim window or the niri window if it is the last one
+    pub fn close_window(
+        &self,
+        force: bool,
+        write: bool,
+        soc: &mut niri::Socket,
+    ) -> Result<()> {
+        if write {
+            self.remote_send(":update<CR>")?;
+        }
+        let bang = if force { "!" } else { "" };
+        if self.remote_expr("winnr('$')")? != "1" {
+            self.remote_send(&format!(":close{bang}<CR>"))
+        } else {
+            niri::action(soc, niri_ipc::Action::CloseWindow { id: None })
+        }
+    }
+}